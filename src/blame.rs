@@ -0,0 +1,162 @@
+//! Module for the blame view, which shows the commit that last changed each line of a file.
+
+use std::fmt;
+
+use anyhow::{bail, Result};
+use crossterm::style::Attribute;
+
+use crate::{
+    config::CONFIG,
+    git_process, parse,
+    render::{self, Renderer, ResetAttributes, SetAttribute, SetCursorStyle},
+    status::expand_tabs,
+};
+
+/// The widest the author column gets, so that long names don't push the lines off the screen.
+const MAX_AUTHOR_WIDTH: usize = 20;
+
+struct Line {
+    /// The full hash of the commit that last changed the line, or all zeros if it has changed
+    /// since the last commit.
+    hash: String,
+    author: String,
+    /// The day the line was last changed, like "2023-10-14".
+    date: String,
+    content: String,
+}
+
+#[derive(Default)]
+pub struct Blame {
+    /// The file being blamed.
+    pub path: String,
+    lines: Vec<Line>,
+    pub cursor: usize,
+}
+
+impl render::Render for Blame {
+    fn render(&self, f: &mut Renderer) -> fmt::Result {
+        use fmt::Write;
+        let config = CONFIG.get().expect("config wasn't initialised");
+
+        let author_width = self
+            .lines
+            .iter()
+            .map(|line| line.author.chars().count())
+            .max()
+            .unwrap_or_default()
+            .min(MAX_AUTHOR_WIDTH);
+        for (i, line) in self.lines.iter().enumerate() {
+            if i == self.cursor {
+                f.insert_cursor();
+            }
+            // Like `tig`, each commit is only named on the first of a run of lines from it.
+            let info = if i > 0 && self.lines[i - 1].hash == line.hash {
+                " ".repeat(8 + author_width + 1 + line.date.len())
+            } else if line.hash.bytes().all(|b| b == b'0') {
+                format!(
+                    "{:<1$}",
+                    "Uncommitted",
+                    8 + author_width + 1 + line.date.len()
+                )
+            } else {
+                let author = line.author.chars().take(author_width).collect::<String>();
+                format!(
+                    "{} {author:<author_width$} {}",
+                    &line.hash[..7.min(line.hash.len())],
+                    line.date
+                )
+            };
+            writeln!(
+                f,
+                "\r{}{info}{ResetAttributes} {}{}{ResetAttributes}",
+                SetAttribute(Attribute::Dim),
+                if i == self.cursor {
+                    SetCursorStyle.to_string()
+                } else {
+                    String::new()
+                },
+                expand_tabs(&line.content, config.options.tab_width),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Blame {
+    /// Blames the file at `path`, with the cursor on line number `line`.
+    pub fn fetch(&mut self, path: &str, line: usize) -> Result<()> {
+        let output = git_process(&["blame", "--line-porcelain", "--", path])?;
+        if !output.status.success() {
+            bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+        }
+
+        self.path = path.to_string();
+        self.lines = parse::parse_blame(&String::from_utf8_lossy(&output.stdout))
+            .into_iter()
+            .map(|line| Line {
+                hash: line.hash.to_string(),
+                author: line.author.to_string(),
+                date: line.date,
+                content: line.content.to_string(),
+            })
+            .collect();
+        self.cursor = line
+            .saturating_sub(1)
+            .min(self.lines.len().saturating_sub(1));
+        Ok(())
+    }
+
+    pub const fn down(&mut self) {
+        if self.cursor + 1 < self.lines.len() {
+            self.cursor += 1;
+        }
+    }
+
+    pub const fn up(&mut self) {
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    pub const fn cursor_first(&mut self) {
+        self.cursor = 0;
+    }
+
+    pub const fn cursor_last(&mut self) {
+        self.cursor = self.lines.len().saturating_sub(1);
+    }
+
+    /// Moves the cursor to the first line of the next run of lines from a different commit.
+    pub fn next_commit(&mut self) {
+        let Some(current) = self.lines.get(self.cursor) else {
+            return;
+        };
+        if let Some(i) = self.lines[self.cursor..]
+            .iter()
+            .position(|line| line.hash != current.hash)
+        {
+            self.cursor += i;
+        }
+    }
+
+    /// Moves the cursor to the first line of the previous run of lines from a different commit.
+    pub fn previous_commit(&mut self) {
+        let start_of_run = |lines: &[Line], mut i: usize| {
+            while i > 0 && lines[i - 1].hash == lines[i].hash {
+                i -= 1;
+            }
+            i
+        };
+        let start = start_of_run(&self.lines, self.cursor);
+        if start > 0 {
+            self.cursor = start_of_run(&self.lines, start - 1);
+        }
+    }
+
+    /// The hash of the commit that last changed the line under the cursor, or `None` if it's
+    /// uncommitted.
+    pub fn selected_hash(&self) -> Option<&str> {
+        self.lines
+            .get(self.cursor)
+            .map(|line| line.hash.as_str())
+            .filter(|hash| !hash.bytes().all(|b| b == b'0'))
+    }
+}
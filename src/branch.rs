@@ -1,20 +1,12 @@
-use std::{
-    fmt,
-    io::{stdin, stdout, BufRead, Write},
-    process::Output,
-};
+use std::{cmp, fmt, process::Output};
 
-use anyhow::{Context, Result};
-use crossterm::{
-    cursor,
-    style::{Attribute, SetForegroundColor},
-    terminal::{self, ClearType},
-};
+use anyhow::Result;
 
 use crate::{
     config::CONFIG,
     git_process,
-    render::{self, Clear, Renderer, ResetAttributes},
+    minibuffer::{MessageType, MiniBuffer},
+    render::{self, Renderer, ResetAttributes, SetCursorStyle, SetForegroundColor},
 };
 
 pub struct BranchList {
@@ -41,8 +33,8 @@ impl render::Render for BranchList {
                 write!(f, "{}", SetForegroundColor(config.colors.heading))?;
             }
             if i == self.cursor {
-                let mut branch = branch.to_string();
-                branch.insert_str(2, &format!("{}", Attribute::Reverse));
+                let mut branch = branch.clone();
+                branch.insert_str(2, &SetCursorStyle.to_string());
                 write!(&mut branch, "{ResetAttributes}")?;
                 f.insert_cursor();
                 writeln!(f, "\r{branch}")?;
@@ -70,39 +62,69 @@ impl BranchList {
     pub fn fetch(&mut self) -> Result<()> {
         let output = git_process(&["branch"])?;
 
-        self.branches = std::str::from_utf8(&output.stdout)
-            .context("broken stdout from `git branch`")?
+        self.branches = String::from_utf8_lossy(&output.stdout)
             .lines()
             .map(|l| l.to_string())
             .collect::<Vec<_>>();
+        // Start on the current branch.
+        self.cursor = self
+            .branches
+            .iter()
+            .position(|b| b.starts_with('*'))
+            .unwrap_or_default();
 
         Ok(())
     }
 
-    pub fn checkout(&self) -> Result<Output> {
-        git_process(&["checkout", &self.branches[self.cursor][2..]])
+    /// The name of the branch under the cursor.
+    pub fn selected(&self) -> Option<&str> {
+        self.branches.get(self.cursor)?.get(2..)
     }
 
-    pub fn checkout_new() -> Result<Output> {
-        terminal::disable_raw_mode().context("failed to exit raw mode")?;
-        print!(
-            "{}{}{}Name for the new branch: ",
-            cursor::MoveTo(0, 0),
-            Clear(ClearType::All),
-            cursor::Show
-        );
-        drop(stdout().flush());
-
-        let input = stdin()
-            .lock()
-            .lines()
-            .next()
-            .context("no stdin")?
-            .context("malformed stdin")?;
+    /// Whether there's a local branch called `name`.
+    pub fn contains(&self, name: &str) -> bool {
+        self.branches.iter().any(|b| b.get(2..) == Some(name))
+    }
 
-        terminal::enable_raw_mode().context("failed to enter raw mode")?;
-        print!("{}", cursor::Hide);
+    pub fn down(&mut self) {
+        self.cursor = cmp::min(self.cursor + 1, self.branches.len().saturating_sub(1));
+    }
+
+    pub const fn cursor_last(&mut self) {
+        self.cursor = self.branches.len().saturating_sub(1);
+    }
 
-        git_process(&["checkout", "-b", &input])
+    /// Checks out the branch under the cursor, if there is one.
+    pub fn checkout(&self) -> Result<Option<Output>> {
+        self.selected()
+            .map(|branch| git_process(&["checkout", branch]))
+            .transpose()
+    }
+
+    /// Deletes the branch under the cursor, if it's been merged. Returns false if it hasn't, in
+    /// which case it has to be deleted with `force`.
+    pub fn delete(&self, force: bool) -> Result<bool> {
+        let Some(branch) = self.selected() else {
+            return Ok(true);
+        };
+        if self.branches[self.cursor].starts_with('*') {
+            MiniBuffer::push(
+                "Can't delete the branch you're on. Switch to another branch first.",
+                MessageType::Error,
+            );
+            return Ok(true);
+        }
+        let output = git_process(&["branch", if force { "-D" } else { "-d" }, branch])?;
+        // git's message is translated, so check whether it failed for not being merged ourselves.
+        if !force
+            && !output.status.success()
+            && !git_process(&["merge-base", "--is-ancestor", branch, "HEAD"])?
+                .status
+                .success()
+        {
+            return Ok(false);
+        }
+        MiniBuffer::push_command_output(&output);
+        Ok(true)
     }
 }
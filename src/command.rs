@@ -1,23 +1,28 @@
 use std::{
     fmt,
     io::stdout,
-    process::{Command, Stdio},
+    process::{Output, Stdio},
+    rc::Rc,
 };
 
 use anyhow::{Context, Result};
 use crossterm::{cursor, terminal};
 
-use crate::{branch::BranchList, config::Config, git_process, minibuffer::MiniBuffer, State, View};
+use crate::{
+    capture_mouse, config, git_command, git_process,
+    minibuffer::{MessageType, MiniBuffer},
+    output::CommandOutput,
+    render,
+    status::{self, Status},
+    wait_with_output, State, View,
+};
 
 macro_rules! commands {
-    ($($key:literal: $cmd:tt => [$($subkey:literal: $subcmd:tt),+$(,)?]),*$(,)?) => {
+    ($($cmd:tt => [$($subkey:literal: $subcmd:tt),+$(,)?]),*$(,)?) => {
         paste::paste! {
             #[derive(Clone, Copy, Debug)]
             pub enum GexCommand { $($cmd),* }
             impl GexCommand {
-                pub const fn commands() -> &'static [(char, Self)] {
-                    &[$(($key, Self::$cmd)),*]
-                }
                 pub const fn subcommands(&self) -> &[(char, SubCommand)] {
                     match self {
                         $(Self::$cmd => {
@@ -57,20 +62,27 @@ macro_rules! commands {
 }
 
 commands! {
-    'b': Branch => ['b': Checkout, 'n': New],
-    'c': Commit => ['c': Commit, 'a': Amend, 'e': Extend],
-    'p': Push => ['p': Remote, 'f': Force],
-    'z': Stash => ['s': Stash, 'p': Pop],
+    Branch => ['b': Checkout, 'n': New],
+    Commit => ['c': Commit, 'm': Message, 'a': Amend, 'e': Extend, 'u': Unsigned, 'n': Unverified, 'w': Reword],
+    Fetch => ['f': Remote, 'a': All],
+    Pull => ['p': Remote, 'r': Rebase],
+    Push => ['p': Remote, 'f': Force],
+    Stash => ['s': Stash, 'm': Message, 'u': Untracked, 'p': Pop, 'l': List],
+    Operation => ['c': Continue, 'a': Abort, 's': Skip],
+    Reset => ['s': Soft, 'm': Mixed, 'h': Hard],
+    Tag => ['t': List, 'n': Lightweight, 'a': Annotated],
+    Patch => ['a': Apply, 's': Stage],
+    Fixup => ['f': Fixup, 's': Squash, 'a': Autosquash],
 }
 
 impl GexCommand {
     #[allow(clippy::enum_glob_use)]
-    pub fn handle_input(self, key: char, state: &mut State, config: &Config) -> Result<()> {
+    pub fn handle_input(self, key: char, state: &mut State) -> Result<()> {
         use SubCommand::*;
         let State {
             ref mut status,
             ref mut view,
-            repo,
+            output: ref mut command_output,
             ..
         } = state;
         let Some((_, cmd)) = self.subcommands().iter().find(|(c, _)| key == *c) else {
@@ -82,10 +94,23 @@ impl GexCommand {
                 use branch::SubCommand;
                 match subcmd {
                     SubCommand::New => {
-                        let checkout = BranchList::checkout_new()?;
-                        MiniBuffer::push_command_output(&checkout);
-                        status.fetch(repo, &config.options)?;
                         *view = View::Status;
+                        state.minibuffer.get_input(
+                            Rc::new(|name: Option<&str>, state: &mut State| {
+                                let Some(name) = name.map(str::trim).filter(|n| !n.is_empty())
+                                else {
+                                    return Ok(());
+                                };
+                                MiniBuffer::push_command_output(&git_process(&[
+                                    "checkout", "-b", name,
+                                ])?);
+                                state.status.fetch();
+                                Ok(())
+                            }),
+                            Some("Name for the new branch: "),
+                            view,
+                        );
+                        return Ok(());
                     }
                     SubCommand::Checkout => {
                         state.branch_list.fetch()?;
@@ -95,80 +120,578 @@ impl GexCommand {
             }
             Commit(subcmd) => {
                 use commit::SubCommand;
+                let sign = sign_args(state.no_gpg_sign);
+                // Skipping hooks is used up by whatever commit is started next, even if it's
+                // cancelled, rather than being left on to skip them by surprise later.
+                let verify = verify_args(state.no_verify);
+                if !matches!(subcmd, SubCommand::Unsigned | SubCommand::Unverified) {
+                    state.no_verify = false;
+                }
                 match subcmd {
                     SubCommand::Commit => {
-                        crossterm::execute!(stdout(), terminal::LeaveAlternateScreen)
-                            .context("failed to leave alternate screen")?;
-                        MiniBuffer::push_command_output(
-                            &Command::new("git")
-                                .arg("commit")
-                                .stdout(Stdio::inherit())
-                                .stdin(Stdio::inherit())
-                                .output()
-                                .context("failed to run `git commit`")?,
+                        *view = View::Status;
+                        // With stdout handed over to the editor we wouldn't otherwise get to see
+                        // why git refused to make the commit, so check first.
+                        let dry_run = git_process(&["commit", "--dry-run"])?;
+                        if dry_run.status.success() {
+                            let output = commit_with_editor(&[sign, verify].concat())?;
+                            show_commit_output(&output, view, command_output);
+                        } else {
+                            push_commit_refusal(&dry_run);
+                        }
+                        status.fetch();
+                    }
+                    SubCommand::Message => {
+                        *view = View::Status;
+                        state.minibuffer.get_input(
+                            Rc::new(move |msg: Option<&str>, state: &mut State| {
+                                let Some(msg) = msg else {
+                                    return Ok(());
+                                };
+                                if msg.trim().is_empty() {
+                                    MiniBuffer::push(
+                                        "Aborting commit due to empty commit message.",
+                                        MessageType::Error,
+                                    );
+                                    return Ok(());
+                                }
+                                let sign = sign_args(state.no_gpg_sign);
+                                let output =
+                                    commit_process(&[&["-m", msg], sign, verify].concat())?;
+                                show_commit_output(&output, &mut state.view, &mut state.output);
+                                state.status.fetch();
+                                Ok(())
+                            }),
+                            Some("Commit message: "),
+                            view,
                         );
-                        status.fetch(repo, &config.options)?;
-                        crossterm::execute!(stdout(), terminal::EnterAlternateScreen, cursor::Hide)
-                            .context("failed to enter alternate screen")?;
+                        return Ok(());
                     }
                     SubCommand::Extend => {
-                        MiniBuffer::push_command_output(
-                            &Command::new("git")
-                                .args(["commit", "--amend", "--no-edit"])
-                                .stdout(Stdio::inherit())
-                                .stdin(Stdio::inherit())
-                                .output()
-                                .context("failed to run `git commit`")?,
-                        );
-                        status.fetch(repo, &config.options)?;
+                        *view = View::Status;
+                        let output =
+                            commit_process(&[&["--amend", "--no-edit"], sign, verify].concat())?;
+                        show_commit_output(&output, view, command_output);
+                        status.fetch();
                     }
                     SubCommand::Amend => {
-                        crossterm::execute!(stdout(), terminal::LeaveAlternateScreen)
-                            .context("failed to leave alternate screen")?;
-                        MiniBuffer::push_command_output(
-                            &Command::new("git")
-                                .args(["commit", "--amend"])
-                                .stdout(Stdio::inherit())
-                                .stdin(Stdio::inherit())
-                                .output()
-                                .context("failed to run `git commit`")?,
+                        *view = View::Status;
+                        // Rewriting a commit that others may already have is rarely intended.
+                        if config!().confirm.amend_pushed && head_pushed()? {
+                            let head = git_process(&["rev-parse", "--short", "HEAD"])?;
+                            state.minibuffer.confirm(
+                                &format!(
+                                    "{} has already been pushed. Amend it anyway? (y/N) ",
+                                    String::from_utf8_lossy(&head.stdout).trim()
+                                ),
+                                view,
+                                move |state| {
+                                    let sign = sign_args(state.no_gpg_sign);
+                                    let output =
+                                        commit_with_editor(&[&["--amend"], sign, verify].concat())?;
+                                    show_commit_output(&output, &mut state.view, &mut state.output);
+                                    state.status.fetch();
+                                    Ok(())
+                                },
+                            );
+                            return Ok(());
+                        }
+                        let output = commit_with_editor(&[&["--amend"], sign, verify].concat())?;
+                        show_commit_output(&output, view, command_output);
+                        status.fetch();
+                    }
+                    SubCommand::Reword => {
+                        *view = View::Status;
+                        if status.head.is_empty() {
+                            MiniBuffer::push(
+                                "There's no commit to reword yet.",
+                                MessageType::Error,
+                            );
+                            return Ok(());
+                        }
+                        if config!().confirm.amend_pushed && head_pushed()? {
+                            let head = git_process(&["rev-parse", "--short", "HEAD"])?;
+                            state.minibuffer.confirm(
+                                &format!(
+                                    "{} has already been pushed. Reword it anyway? (y/N) ",
+                                    String::from_utf8_lossy(&head.stdout).trim()
+                                ),
+                                view,
+                                move |state| {
+                                    reword(state, verify);
+                                    Ok(())
+                                },
+                            );
+                            return Ok(());
+                        }
+                        reword(state, verify);
+                        return Ok(());
+                    }
+                    SubCommand::Unsigned => {
+                        state.no_gpg_sign = !state.no_gpg_sign;
+                        MiniBuffer::push(
+                            if state.no_gpg_sign {
+                                "Commits won't be signed."
+                            } else {
+                                "Commits will be signed if git is configured to sign them."
+                            },
+                            MessageType::Note,
                         );
-                        status.fetch(repo, &config.options)?;
-                        crossterm::execute!(stdout(), terminal::EnterAlternateScreen, cursor::Hide)
-                            .context("failed to enter alternate screen")?;
+                        // Stay in the menu to make the commit.
+                        return Ok(());
                     }
+                    SubCommand::Unverified => {
+                        state.no_verify = !state.no_verify;
+                        MiniBuffer::push(
+                            if state.no_verify {
+                                "The next commit will skip the pre-commit and commit-msg hooks."
+                            } else {
+                                "Hooks will run for the next commit."
+                            },
+                            MessageType::Note,
+                        );
+                        // Stay in the menu to make the commit.
+                        return Ok(());
+                    }
+                }
+            }
+            Fetch(subcmd) => {
+                use fetch::SubCommand;
+                match subcmd {
+                    SubCommand::Remote => remote_process(&["fetch"])?,
+                    SubCommand::All => remote_process(&["fetch", "--all"])?,
                 }
+                status.fetch();
+                *view = View::Status;
+            }
+            Pull(subcmd) => {
+                use pull::SubCommand;
+                match subcmd {
+                    SubCommand::Remote => remote_process(&["pull"])?,
+                    SubCommand::Rebase => remote_process(&["pull", "--rebase"])?,
+                }
+                status.fetch();
                 *view = View::Status;
             }
             Push(subcmd) => {
                 use push::SubCommand;
-                // For now we are just temporarily disabling the raw mode so that if the user is
-                // aksed for credentials then they can provide them that way.
-                crossterm::execute!(stdout(), cursor::MoveToColumn(0), cursor::Show)?;
-                terminal::disable_raw_mode().context("failed to disable raw mode")?;
+                *view = View::Status;
                 match subcmd {
-                    SubCommand::Remote => MiniBuffer::push_command_output(&git_process(&["push"])?),
-                    SubCommand::Force => {
-                        MiniBuffer::push_command_output(&git_process(&["push", "--force"])?);
+                    SubCommand::Remote => push(status.branch.as_deref(), &[])?,
+                    SubCommand::Force if config!().confirm.force_push => {
+                        state.minibuffer.confirm(
+                            &format!(
+                                "Force push {}? This may discard commits on the remote. (y/N) ",
+                                status.branch.as_deref().unwrap_or("HEAD")
+                            ),
+                            view,
+                            |state| {
+                                push(state.status.branch.as_deref(), &["--force"])?;
+                                state.status.fetch();
+                                Ok(())
+                            },
+                        );
+                        return Ok(());
                     }
+                    SubCommand::Force => push(status.branch.as_deref(), &["--force"])?,
                 }
-                crossterm::execute!(stdout(), cursor::Hide)?;
-                terminal::enable_raw_mode().context("failed to enable raw mode")?;
-                *view = View::Status;
+                status.fetch();
             }
             Stash(subcmd) => {
                 use stash::SubCommand;
                 match subcmd {
-                    SubCommand::Stash => MiniBuffer::push_command_output(&git_process(&["stash"])?),
-                    SubCommand::Pop => {
-                        MiniBuffer::push_command_output(&git_process(&["stash", "pop"])?);
+                    SubCommand::Stash => Status::stash_save(None, false)?,
+                    SubCommand::Message => {
+                        *view = View::Status;
+                        state.minibuffer.get_input(
+                            Rc::new(|msg: Option<&str>, state: &mut State| {
+                                let Some(msg) = msg else {
+                                    return Ok(());
+                                };
+                                Status::stash_save(
+                                    Some(msg).filter(|m| !m.trim().is_empty()),
+                                    false,
+                                )?;
+                                state.status.fetch();
+                                Ok(())
+                            }),
+                            Some("Stash message: "),
+                            view,
+                        );
+                        return Ok(());
+                    }
+                    SubCommand::Untracked => Status::stash_save(None, true)?,
+                    SubCommand::Pop => Status::stash_pop()?,
+                    SubCommand::List => {
+                        state.stash_list.fetch()?;
+                        *view = View::StashList;
+                        return Ok(());
+                    }
+                }
+                status.fetch();
+                *view = View::Status;
+            }
+            Operation(subcmd) => {
+                use operation::SubCommand;
+                *view = View::Status;
+                let Some(operation) = status.operation else {
+                    MiniBuffer::push("There's nothing to continue or abort.", MessageType::Error);
+                    return Ok(());
+                };
+                let flag = match subcmd {
+                    SubCommand::Continue => "--continue",
+                    SubCommand::Abort => "--abort",
+                    SubCommand::Skip if operation == status::Operation::Merge => {
+                        MiniBuffer::push("A merge can't be skipped.", MessageType::Error);
+                        return Ok(());
+                    }
+                    SubCommand::Skip => "--skip",
+                };
+                // Continuing makes a commit, which would open an editor for its message. Keep the
+                // message git has prepared instead.
+                let args = [operation.command(), flag];
+                let output = wait_with_output(
+                    git_command(&args)
+                        .env("GIT_EDITOR", "true")
+                        .stdin(Stdio::null())
+                        .stdout(Stdio::piped())
+                        .stderr(Stdio::piped()),
+                    &args,
+                )?;
+                MiniBuffer::push_command_output(&output);
+                status.fetch();
+            }
+            Reset(subcmd) => {
+                use reset::SubCommand;
+                *view = View::Log;
+                let Some(hash) = state.log.selected_hash().map(str::to_string) else {
+                    return Ok(());
+                };
+                let mode = match subcmd {
+                    SubCommand::Soft => "--soft",
+                    SubCommand::Mixed => "--mixed",
+                    SubCommand::Hard => "--hard",
+                };
+                if matches!(subcmd, SubCommand::Hard) && config!().confirm.reset_hard {
+                    state.minibuffer.confirm(
+                        &format!("Hard reset to {hash}? Uncommitted changes will be lost. (y/N) "),
+                        view,
+                        move |state| reset(state, "--hard", &hash),
+                    );
+                    return Ok(());
+                }
+                reset(state, mode, &hash)?;
+            }
+            Fixup(subcmd) => {
+                use fixup::SubCommand;
+                *view = View::Log;
+                let Some(hash) = state.log.selected_hash().map(str::to_string) else {
+                    return Ok(());
+                };
+                if matches!(subcmd, SubCommand::Autosquash) {
+                    // The commit under the cursor is rebased too, so that its fixups are folded
+                    // into it.
+                    let parent = format!("{hash}~");
+                    let base = if git_process(&["rev-parse", "--verify", "--quiet", &parent])?
+                        .status
+                        .success()
+                    {
+                        parent.as_str()
+                    } else {
+                        "--root"
+                    };
+                    return rebase(state, &["--autosquash", base]);
+                }
+                let sign = sign_args(state.no_gpg_sign);
+                let verify = verify_args(state.no_verify);
+                state.no_verify = false;
+                let output = if matches!(subcmd, SubCommand::Fixup) {
+                    commit_process(&[&[format!("--fixup={hash}").as_str()], sign, verify].concat())?
+                } else {
+                    // A squash commit's message is added to the one it's squashed into, so it's
+                    // written in the editor.
+                    commit_with_editor(
+                        &[&[format!("--squash={hash}").as_str()], sign, verify].concat(),
+                    )?
+                };
+                show_commit_output(&output, view, command_output);
+                status.fetch();
+                state.log.fetch()?;
+            }
+            Tag(subcmd) => {
+                use tag::SubCommand;
+                let target = state.tag_target.take();
+                *view = if target.is_some() {
+                    View::Log
+                } else {
+                    View::Status
+                };
+                match subcmd {
+                    SubCommand::List => {
+                        state.tag_list.fetch()?;
+                        *view = View::TagList;
+                    }
+                    SubCommand::Lightweight | SubCommand::Annotated => {
+                        let annotated = matches!(subcmd, SubCommand::Annotated);
+                        let prompt = format!(
+                            "Name for the tag on {}: ",
+                            target.as_deref().unwrap_or("HEAD")
+                        );
+                        state.minibuffer.get_input(
+                            Rc::new(move |name: Option<&str>, _: &mut State| {
+                                let Some(name) = name.map(str::trim).filter(|n| !n.is_empty())
+                                else {
+                                    return Ok(());
+                                };
+                                let mut args = vec!["tag"];
+                                if annotated {
+                                    args.push("--annotate");
+                                }
+                                args.push(name);
+                                args.extend(target.as_deref());
+                                if annotated {
+                                    git_with_editor(&args)
+                                } else {
+                                    MiniBuffer::push_command_output(&git_process(&args)?);
+                                    Ok(())
+                                }
+                            }),
+                            Some(&prompt),
+                            view,
+                        );
                     }
                 }
-                status.fetch(repo, &config.options)?;
+            }
+            Patch(subcmd) => {
+                use patch::SubCommand;
                 *view = View::Status;
+                let cached = matches!(subcmd, SubCommand::Stage);
+                state.minibuffer.get_input(
+                    Rc::new(move |path: Option<&str>, state: &mut State| {
+                        let Some(path) = path.map(str::trim).filter(|p| !p.is_empty()) else {
+                            return Ok(());
+                        };
+                        let mut args = vec!["apply"];
+                        if cached {
+                            args.push("--cached");
+                        }
+                        args.push(path);
+                        state.status.save_index()?;
+                        let output = git_process(&args)?;
+                        if output.status.success() && output.stderr.is_empty() {
+                            MiniBuffer::push(&format!("Applied {path}."), MessageType::Note);
+                        } else {
+                            MiniBuffer::push_command_output(&output);
+                        }
+                        state.status.fetch();
+                        Ok(())
+                    }),
+                    Some(if cached {
+                        "Patch to stage: "
+                    } else {
+                        "Patch to apply: "
+                    }),
+                    view,
+                );
             }
         }
 
         Ok(())
     }
 }
+
+/// Runs `git reset <mode> <hash>` and refreshes everything it may have changed.
+fn reset(state: &mut State, mode: &str, hash: &str) -> Result<()> {
+    MiniBuffer::push_command_output(&git_process(&["reset", mode, hash])?);
+    state.status.fetch();
+    state.log.fetch()
+}
+
+/// Runs `git rebase --interactive` with `args`, e.g. the base, handing the terminal over for git's
+/// editor, and refreshes everything it may have changed. If the rebase stops partway, e.g. because
+/// of conflicts, this switches to the status to show them.
+pub fn rebase(state: &mut State, args: &[&str]) -> Result<()> {
+    git_with_editor(&[&["rebase", "--interactive"], args].concat())?;
+    state.status.fetch();
+    state.log.fetch()?;
+    if status::Operation::from_repo(&state.repo).is_some() {
+        state.view = View::Status;
+    }
+    Ok(())
+}
+
+/// Pushes the current `branch`, setting its upstream on `origin` if it doesn't have one yet.
+/// `branch` is `None` when HEAD is detached.
+fn push(branch: Option<&str>, args: &[&str]) -> Result<()> {
+    let mut push_args = vec!["push"];
+    if !git_process(&["rev-parse", "--abbrev-ref", "@{upstream}"])?
+        .status
+        .success()
+    {
+        let Some(branch) = branch else {
+            MiniBuffer::push(
+                "HEAD is detached, so there's no branch to push.",
+                MessageType::Error,
+            );
+            return Ok(());
+        };
+        push_args.extend(["--set-upstream", "origin", branch]);
+    }
+    push_args.extend(args);
+    remote_process(&push_args)
+}
+
+/// Runs a git command which talks to a remote and displays its output.
+fn remote_process(args: &[&str]) -> Result<()> {
+    // For now we are just temporarily disabling the raw mode so that if the user is aksed for
+    // credentials then they can provide them that way.
+    crossterm::execute!(stdout(), cursor::MoveToColumn(0), cursor::Show)?;
+    terminal::disable_raw_mode().context("failed to disable raw mode")?;
+    let output = git_process(args);
+    crossterm::execute!(stdout(), cursor::Hide)?;
+    terminal::enable_raw_mode().context("failed to enable raw mode")?;
+    MiniBuffer::push_command_output(&output?);
+    Ok(())
+}
+
+/// Displays why `git commit` refused to make a commit. git prints the whole status before its
+/// verdict, but we already display all that.
+fn push_commit_refusal(output: &Output) {
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    MiniBuffer::push(
+        stdout.lines().last().unwrap_or_default(),
+        MessageType::Error,
+    );
+    MiniBuffer::push(&String::from_utf8_lossy(&output.stderr), MessageType::Error);
+}
+
+/// Whether the commit at HEAD is on the upstream of the current branch, so others may have it.
+fn head_pushed() -> Result<bool> {
+    Ok(
+        git_process(&["merge-base", "--is-ancestor", "HEAD", "@{upstream}"])?
+            .status
+            .success(),
+    )
+}
+
+/// Asks for a new subject for the commit at HEAD, starting from the current one, and amends the
+/// commit with it. Only the message changes, not what's in the commit, even if there are staged
+/// changes. `verify` is from `verify_args`.
+fn reword(state: &mut State, verify: &'static [&'static str]) {
+    let subject = state
+        .status
+        .head
+        .split_once(' ')
+        .map_or("", |(_, subject)| subject)
+        .to_string();
+    state.minibuffer.get_input(
+        Rc::new(move |msg: Option<&str>, state: &mut State| {
+            let Some(msg) = msg.map(str::trim).filter(|msg| !msg.is_empty()) else {
+                return Ok(());
+            };
+            // The rest of the message is kept.
+            let body = git_process(&["log", "-1", "--format=%b", "HEAD"])?;
+            let body = String::from_utf8_lossy(&body.stdout).trim().to_string();
+            let mut args = vec!["--amend", "--only", "-m", msg];
+            if !body.is_empty() {
+                args.extend(["-m", &body]);
+            }
+            let sign = sign_args(state.no_gpg_sign);
+            let output = commit_process(&[&args, sign, verify].concat())?;
+            show_commit_output(&output, &mut state.view, &mut state.output);
+            state.status.fetch();
+            Ok(())
+        }),
+        Some("Reword: "),
+        &mut state.view,
+    );
+    state.minibuffer.set_input(&subject);
+}
+
+/// Shows what `git commit` said. If it refused to make the commit, like when a pre-commit hook
+/// fails, and said too much for the minibuffer, that's opened in the output view instead to be
+/// scrolled through.
+fn show_commit_output(output: &Output, view: &mut View, command_output: &mut CommandOutput) {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if output.status.success() {
+        MiniBuffer::push_command_output(output);
+    } else if terminal::size().is_ok_and(|(_, height)| CommandOutput::too_long(&stderr, height)) {
+        *command_output = CommandOutput::new("The commit was refused:", &stderr);
+        *view = View::Output;
+    } else {
+        push_commit_refusal(output);
+    }
+}
+
+/// The extra arguments for `git commit` to skip signing if `no_gpg_sign`.
+const fn sign_args(no_gpg_sign: bool) -> &'static [&'static str] {
+    if no_gpg_sign {
+        &["--no-gpg-sign"]
+    } else {
+        &[]
+    }
+}
+
+/// The extra arguments for `git commit` to skip the hooks if `no_verify`.
+const fn verify_args(no_verify: bool) -> &'static [&'static str] {
+    if no_verify {
+        &["--no-verify"]
+    } else {
+        &[]
+    }
+}
+
+/// Runs `git commit` with the given `args`, and without raw mode so that signing the commit can
+/// ask for a passphrase, e.g. through pinentry.
+fn commit_process(args: &[&str]) -> Result<Output> {
+    render::repaint_all();
+    crossterm::execute!(stdout(), cursor::MoveToColumn(0), cursor::Show)?;
+    terminal::disable_raw_mode().context("failed to disable raw mode")?;
+    let args = [&["commit"], args].concat();
+    let output = wait_with_output(
+        git_command(&args)
+            .stdin(Stdio::inherit())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped()),
+        &args,
+    );
+    crossterm::execute!(stdout(), cursor::Hide)?;
+    terminal::enable_raw_mode().context("failed to enable raw mode")?;
+    output
+}
+
+/// Runs `git commit` with the given extra `args`, handing the terminal over to git so that the
+/// user's editor (`$GIT_EDITOR`, `core.editor`, etc.) can take over, and signing the commit can ask
+/// for a passphrase.
+fn commit_with_editor(args: &[&str]) -> Result<Output> {
+    run_with_editor(&[&["commit"], args].concat())
+}
+
+/// Runs git with `args`, handing the terminal over to it for commands that open the user's editor
+/// for a message or may ask for a passphrase, and shows its output.
+pub fn git_with_editor(args: &[&str]) -> Result<()> {
+    MiniBuffer::push_command_output(&run_with_editor(args)?);
+    Ok(())
+}
+
+/// Runs git with `args` like `git_with_editor`, returning its output instead of showing it. Only
+/// stderr is captured, as stdout is the terminal.
+fn run_with_editor(args: &[&str]) -> Result<Output> {
+    capture_mouse(false)?;
+    render::repaint_all();
+    crossterm::execute!(stdout(), terminal::LeaveAlternateScreen)
+        .context("failed to leave alternate screen")?;
+    terminal::disable_raw_mode().context("failed to disable raw mode")?;
+    let output = wait_with_output(
+        git_command(args)
+            .stdout(Stdio::inherit())
+            .stdin(Stdio::inherit())
+            .stderr(Stdio::piped()),
+        args,
+    );
+    terminal::enable_raw_mode().context("failed to enable raw mode")?;
+    crossterm::execute!(stdout(), terminal::EnterAlternateScreen, cursor::Hide)
+        .context("failed to enter alternate screen")?;
+    capture_mouse(true)?;
+    output
+}
@@ -1,10 +1,13 @@
 //! Gex configuration.
-#![allow(clippy::derivable_impls)]
-use std::{fs, path::PathBuf, str::FromStr, sync::OnceLock};
+#![allow(clippy::derivable_impls, clippy::struct_excessive_bools)]
+use std::{env, fmt, fs, path::PathBuf, str::FromStr, sync::OnceLock};
 
 use anyhow::{Context, Result};
 use clap::Parser;
-use crossterm::style::Color;
+use crossterm::{
+    event::{KeyCode, KeyEvent, KeyModifiers},
+    style::Color,
+};
 use serde::Deserialize;
 
 pub static CONFIG: OnceLock<Config> = OnceLock::new();
@@ -28,6 +31,30 @@ pub struct Clargs {
     /// Path to a config file to use.
     #[clap(short, long, name = "PATH")]
     pub config_file: Option<String>,
+
+    /// Don't use colours or text styles like bold, even if set in the config file.
+    #[clap(long)]
+    pub no_color: bool,
+
+    /// Print the status and exit, instead of opening the interface.
+    #[clap(long)]
+    pub print: bool,
+
+    /// Print the status as JSON and exit, for scripts and editor integrations.
+    #[clap(long, conflicts_with = "print")]
+    pub json: bool,
+
+    /// Log each git command run to stderr, e.g. `gex --verbose 2> gex.log`.
+    #[clap(long)]
+    pub verbose: bool,
+
+    /// Path to the repository's git directory, like git's `--git-dir`. Overrides `$GIT_DIR`.
+    #[clap(long, name = "GIT_DIR")]
+    pub git_dir: Option<String>,
+
+    /// Path to the work tree, like git's `--work-tree`. Overrides `$GIT_WORK_TREE`.
+    #[clap(long, name = "WORK_TREE")]
+    pub work_tree: Option<String>,
 }
 
 /// The top-level of the config parsed from the config file.
@@ -36,6 +63,9 @@ pub struct Clargs {
 pub struct Config {
     pub options: Options,
     pub colors: Colors,
+    pub symbols: Symbols,
+    pub confirm: Confirm,
+    pub keys: Keys,
 }
 
 #[derive(Deserialize, Debug, PartialEq, Eq)]
@@ -45,7 +75,93 @@ pub struct Options {
     pub auto_expand_hunks: bool,
     pub lookahead_lines: usize,
     pub truncate_lines: bool,
+    pub wrap_lines: bool,
     pub ws_error_highlight: WsErrorHighlight,
+    pub whitespace: Whitespace,
+    pub tab_width: usize,
+    pub line_numbers: bool,
+    pub syntax_highlighting: bool,
+    pub context_lines: usize,
+    pub untracked_preview_lines: usize,
+    pub untracked_preview_bytes: u64,
+    pub git_path: String,
+    pub tree_view: bool,
+    pub side_by_side: bool,
+    pub auto_refresh: bool,
+    pub show_untracked: bool,
+    pub show_last_command: bool,
+    pub mouse: bool,
+    pub git_timeout: u64,
+    pub rename_threshold: u8,
+    pub detect_copies: bool,
+    pub advance_after_stage: bool,
+    pub editor_line_number: bool,
+}
+
+/// The symbols shown before files, directories and hunks, depending on whether they're expanded.
+/// Those left out depend on whether the locale is UTF-8.
+#[derive(Deserialize, Default, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct Symbols {
+    pub expanded: Option<String>,
+    pub collapsed: Option<String>,
+}
+
+impl Symbols {
+    /// The symbol for something that's `expanded`, or collapsed if not.
+    pub fn get(&self, expanded: bool) -> &str {
+        let utf8 = utf8_locale();
+        if expanded {
+            self.expanded
+                .as_deref()
+                .unwrap_or(if utf8 { "⌄" } else { "v" })
+        } else {
+            self.collapsed
+                .as_deref()
+                .unwrap_or(if utf8 { "›" } else { ">" })
+        }
+    }
+}
+
+/// Whether the locale is UTF-8, going by `$LC_ALL`, `$LC_CTYPE` and `$LANG` in that order, like
+/// the C library does. Without any of them, it's the "C" locale, which isn't, except on Windows
+/// where they aren't used.
+fn utf8_locale() -> bool {
+    static UTF8: OnceLock<bool> = OnceLock::new();
+    *UTF8.get_or_init(|| {
+        ["LC_ALL", "LC_CTYPE", "LANG"]
+            .into_iter()
+            .find_map(|var| env::var(var).ok().filter(|locale| !locale.is_empty()))
+            .map_or(cfg!(windows), |locale| {
+                let locale = locale.to_lowercase();
+                locale.contains("utf-8") || locale.contains("utf8")
+            })
+    })
+}
+
+/// Which actions that can lose work ask before going ahead.
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct Confirm {
+    pub discard: bool,
+    pub force_push: bool,
+    pub delete_branch: bool,
+    pub amend_pushed: bool,
+    pub reset_hard: bool,
+    pub drop_stash: bool,
+}
+
+impl Default for Confirm {
+    fn default() -> Self {
+        Self {
+            discard: true,
+            force_push: true,
+            delete_branch: true,
+            amend_pushed: true,
+            reset_hard: true,
+            drop_stash: true,
+        }
+    }
 }
 
 #[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
@@ -56,6 +172,15 @@ pub struct WsErrorHighlight {
     pub context: bool,
 }
 
+/// The whitespace errors to highlight, like git's `core.whitespace`.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct Whitespace {
+    pub blank_at_eol: bool,
+    pub space_before_tab: bool,
+    pub tab_in_indent: bool,
+}
+
 impl Default for Options {
     fn default() -> Self {
         Self {
@@ -63,7 +188,27 @@ impl Default for Options {
             auto_expand_hunks: true,
             lookahead_lines: 5,
             truncate_lines: true,
+            wrap_lines: false,
             ws_error_highlight: WsErrorHighlight::default(),
+            whitespace: Whitespace::default(),
+            tab_width: 8,
+            line_numbers: false,
+            syntax_highlighting: true,
+            context_lines: 3,
+            untracked_preview_lines: 500,
+            untracked_preview_bytes: 1_000_000,
+            git_path: String::from("git"),
+            tree_view: false,
+            side_by_side: false,
+            auto_refresh: false,
+            show_untracked: true,
+            show_last_command: true,
+            mouse: false,
+            git_timeout: 120,
+            rename_threshold: 50,
+            detect_copies: false,
+            advance_after_stage: false,
+            editor_line_number: true,
         }
     }
 }
@@ -79,47 +224,307 @@ pub struct Colors {
     pub deletion: Color,
     pub key: Color,
     pub error: Color,
+    pub search_match: Color,
+    /// The background of the cursor, or reverse video if it's `Reset`.
+    pub cursor: Color,
 }
 
 impl Default for Colors {
     fn default() -> Self {
         // We have to force colour output here regardless of NO_COLOR setting, because then we can
-        // handle it ourselves. The NO_COLOR standard specifies that colour output should be
-        // enabled when the user has explicitly set it, which can be achieved here by detecting the
-        // env variable and then enabling color granularly based on the user config.
+        // handle it ourselves, turning off all styling in `render` when it's set.
         crossterm::style::force_color_output(true);
-        if std::env::var("NO_COLOR").map_or(false, |v| !v.is_empty()) {
-            Self {
-                foreground: Color::Reset,
-                background: Color::Reset,
-                heading: Color::Reset,
-                hunk_head: Color::Reset,
-                addition: Color::Reset,
-                deletion: Color::Reset,
-                key: Color::Reset,
-                error: Color::Reset,
+        Self {
+            foreground: Color::Reset,
+            background: Color::Reset,
+            heading: Color::Yellow,
+            hunk_head: Color::Blue,
+            addition: Color::DarkGreen,
+            deletion: Color::DarkRed,
+            key: Color::Green,
+            error: Color::Red,
+            search_match: Color::DarkYellow,
+            cursor: Color::Reset,
+        }
+    }
+}
+
+/// A key press that can be bound to an action, e.g. "j", "Down", "C-d" (Ctrl+d) or "M-x" (Alt+x).
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct Key {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+}
+
+impl Key {
+    /// Whether `event` is a press of this key. Shift isn't compared, as it's already part of
+    /// the character for character keys.
+    pub fn matches(&self, event: &KeyEvent) -> bool {
+        let modifiers = KeyModifiers::CONTROL | KeyModifiers::ALT;
+        self.code == event.code && self.modifiers & modifiers == event.modifiers & modifiers
+    }
+}
+
+impl TryFrom<String> for Key {
+    type Error = anyhow::Error;
+    fn try_from(s: String) -> std::result::Result<Self, Self::Error> {
+        Self::from_str(&s)
+    }
+}
+
+impl FromStr for Key {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut key = s;
+        // Careful not to take "-" itself as the start of a modifier.
+        while key.len() > 2 {
+            if let Some(rest) = key.strip_prefix("C-") {
+                modifiers |= KeyModifiers::CONTROL;
+                key = rest;
+            } else if let Some(rest) = key.strip_prefix("M-") {
+                modifiers |= KeyModifiers::ALT;
+                key = rest;
+            } else {
+                break;
             }
-        } else {
-            Self {
-                foreground: Color::Reset,
-                background: Color::Reset,
-                heading: Color::Yellow,
-                hunk_head: Color::Blue,
-                addition: Color::DarkGreen,
-                deletion: Color::DarkRed,
-                key: Color::Green,
-                error: Color::Red,
+        }
+
+        let mut chars = key.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match key.to_lowercase().as_str() {
+                "space" => KeyCode::Char(' '),
+                "tab" => KeyCode::Tab,
+                "enter" => KeyCode::Enter,
+                "esc" => KeyCode::Esc,
+                "backspace" => KeyCode::Backspace,
+                "delete" => KeyCode::Delete,
+                "up" => KeyCode::Up,
+                "down" => KeyCode::Down,
+                "left" => KeyCode::Left,
+                "right" => KeyCode::Right,
+                "home" => KeyCode::Home,
+                "end" => KeyCode::End,
+                "pageup" => KeyCode::PageUp,
+                "pagedown" => KeyCode::PageDown,
+                _ => return Err(anyhow::Error::msg(format!("unrecognised key: {s}"))),
+            },
+        };
+        Ok(Self { code, modifiers })
+    }
+}
+
+/// Writes the key the way it's written in the config file.
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            f.write_str("C-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            f.write_str("M-")?;
+        }
+        match self.code {
+            KeyCode::Char(' ') => f.write_str("Space"),
+            KeyCode::Char(c) => write!(f, "{c}"),
+            // The rest are named the same in crossterm.
+            code => write!(f, "{code:?}"),
+        }
+    }
+}
+
+/// Generates the `Keys` config section with a field for each action, and the `Action` enum that
+/// key presses are turned into.
+macro_rules! keys {
+    ($(#[doc = $doc:literal] $action:ident: [$($key:literal),*]),*$(,)?) => {
+        paste::paste! {
+            #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+            pub enum Action { $(#[doc = $doc] [<$action:camel>]),* }
+
+            /// The keys bound to each action. Each action can have any number of keys.
+            #[derive(Deserialize, Debug, PartialEq, Eq)]
+            #[serde(default)]
+            pub struct Keys { $(pub $action: Vec<Key>),* }
+
+            impl Default for Keys {
+                fn default() -> Self {
+                    Self {
+                        $($action: vec![
+                            $(Key::from_str($key).expect("default keys are valid")),*
+                        ]),*
+                    }
+                }
+            }
+
+            impl Keys {
+                /// Gets the action bound to the key pressed in `event`, if there is one.
+                pub fn action(&self, event: &KeyEvent) -> Option<Action> {
+                    $(
+                        if self.$action.iter().any(|key| key.matches(event)) {
+                            return Some(Action::[<$action:camel>]);
+                        }
+                    )*
+                    None
+                }
+
+                /// The keys bound to `action`.
+                pub fn keys(&self, action: Action) -> &[Key] {
+                    match action {
+                        $(Action::[<$action:camel>] => &self.$action),*
+                    }
+                }
+
+                /// The description of each action, taken from its doc comment, and the keys bound
+                /// to it.
+                pub fn bindings(&self) -> Vec<(&'static str, &[Key])> {
+                    vec![$(($doc.trim(), &self.$action)),*]
+                }
             }
         }
     }
 }
 
+keys! {
+    /// Move down
+    down: ["j", "Down"],
+    /// Move up
+    up: ["k", "Up"],
+    /// Jump to next file
+    file_down: ["J", "}"],
+    /// Jump to previous file
+    file_up: ["K", "{"],
+    /// Go to top, pressed twice like vim's `gg`
+    first: ["g"],
+    /// Go to bottom
+    last: ["G"],
+    /// Scroll down
+    scroll_down: ["C-e"],
+    /// Scroll up
+    scroll_up: ["C-y"],
+    /// Move down half a page
+    half_page_down: ["C-d"],
+    /// Move up half a page
+    half_page_up: ["C-u"],
+    /// Search
+    search: ["/"],
+    /// Next match
+    search_next: ["n"],
+    /// Previous match
+    search_previous: ["N"],
+    /// Filter files by path
+    filter: ["&"],
+    /// Toggle expand
+    expand: ["Tab", "Space"],
+    // Takes precedence over `expand` in the status view, which it shares Space with.
+    /// Mark file, to stage, unstage or discard several at once
+    mark: ["Space"],
+    /// Collapse all
+    collapse_all: ["Z"],
+    /// Expand all
+    expand_all: ["E"],
+    /// Stage item
+    stage: ["s"],
+    /// Stage all items
+    stage_all: ["S"],
+    /// Unstage item
+    unstage: ["u"],
+    /// Unstage all items
+    unstage_all: ["U"],
+    /// Stage everything in the item's directory
+    stage_directory: ["d"],
+    /// Unstage everything in the item's directory
+    unstage_directory: ["D"],
+    /// Undo the last stage or unstage
+    undo: ["C-z"],
+    /// Discard item
+    discard: ["x"],
+    /// Select lines
+    select_lines: ["v"],
+    /// Split hunk
+    split_hunk: ["|"],
+    /// Take our side of a conflict
+    take_ours: ["o"],
+    /// Take their side of a conflict
+    take_theirs: ["t"],
+    /// Open file in $EDITOR
+    open_editor: ["e"],
+    /// Copy hunk to the clipboard
+    copy_hunk: ["y"],
+    /// Copy file path to the clipboard
+    copy_path: ["Y"],
+    /// Pipe hunk to a shell command
+    pipe_hunk: [">"],
+    /// Save file or hunk as a patch
+    export_patch: ["O"],
+    /// Apply or stage a patch file
+    apply_patch: ["I"],
+    /// More diff context
+    more_context: ["+"],
+    /// Less diff context
+    less_context: ["-"],
+    /// Toggle ignoring whitespace changes
+    ignore_whitespace: ["w"],
+    /// Toggle grouping files by directory
+    tree: ["T"],
+    /// Toggle side-by-side diffs
+    side_by_side: ["="],
+    /// Toggle showing untracked files
+    untracked: ["."],
+    /// Refresh
+    refresh: ["r"],
+    /// Execute git command
+    git_command: [":"],
+    /// Execute subprocess
+    shell_command: ["!"],
+    /// Quit gex
+    quit: ["q"],
+    /// Branch
+    branch: ["b"],
+    /// Tag
+    tag: ["#"],
+    /// Commit
+    commit: ["c"],
+    /// Fetch
+    fetch: ["f"],
+    /// Pull
+    pull: ["F"],
+    /// Push
+    push: ["p"],
+    /// Stash
+    stash: ["z"],
+    /// Show log
+    log: ["l"],
+    /// Blame the file under the cursor
+    blame: ["B"],
+    /// List worktrees, to switch to another
+    worktree: ["W"],
+    /// Revert the commit under the cursor in the log
+    revert: ["V"],
+    /// Cherry-pick the commit under the cursor in the log
+    cherry_pick: ["A"],
+    /// Reset to the commit under the cursor in the log
+    reset: ["X"],
+    /// Apply the stash under the cursor in the stash list
+    apply_stash: ["a"],
+    /// Apply the stash under the cursor in the stash list and drop it
+    pop_stash: ["P"],
+    /// Rebase interactively onto the commit under the cursor in the log, or one you type
+    rebase: ["i"],
+    /// Make a fixup or squash commit for the commit under the cursor in the log, or fold them in
+    fixup: ["C"],
+    /// Continue, skip or abort a merge, cherry-pick, revert or rebase
+    operation: ["R"],
+    /// Show this help
+    help: ["?"],
+}
+
 impl Config {
     /// Reads the config from the config file (usually `~/.config/gex/config.toml` on Linux) and
     /// returns it along with a Vec of unrecognised keys.
     /// If there is no config file, it will return `Ok(None)`.
     /// If there is a config file but it is unable to parse it, it will return `Err(_)`.
-    pub fn read_from_file(path: &Option<String>) -> Result<Option<(Self, Vec<String>)>> {
+    pub fn read_from_file(path: Option<&str>) -> Result<Option<(Self, Vec<String>)>> {
         let mut config_path;
         if let Some(path) = path {
             config_path = PathBuf::from(path);
@@ -218,19 +623,138 @@ impl FromStr for WsErrorHighlight {
     }
 }
 
+impl Whitespace {
+    /// The default value defined by git.
+    const GIT_DEFAULT: Self = Self {
+        blank_at_eol: true,
+        space_before_tab: true,
+        tab_in_indent: false,
+    };
+}
+
+impl Default for Whitespace {
+    /// Like `WsErrorHighlight`, this comes from the git config if the gex config doesn't set it.
+    fn default() -> Self {
+        let Ok(git_config) = git2::Config::open_default().and_then(|mut config| config.snapshot())
+        else {
+            return Self::GIT_DEFAULT;
+        };
+
+        let Ok(value) = git_config.get_str("core.whitespace") else {
+            return Self::GIT_DEFAULT;
+        };
+
+        Self::from_str(value).unwrap_or(Self::GIT_DEFAULT)
+    }
+}
+
+impl TryFrom<String> for Whitespace {
+    type Error = anyhow::Error;
+    fn try_from(s: String) -> std::result::Result<Self, Self::Error> {
+        Self::from_str(&s)
+    }
+}
+
+impl FromStr for Whitespace {
+    type Err = anyhow::Error;
+    /// A comma separated list of problems to turn on, or off if they're prefixed with `-`,
+    /// starting from git's default. The problems git knows of that aren't shown, like
+    /// `blank-at-eof`, are accepted but do nothing.
+    ///
+    /// <https://git-scm.com/docs/git-config#Documentation/git-config.txt-corewhitespace>
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut result = Self::GIT_DEFAULT;
+        for opt in s.split(',').map(str::trim).filter(|opt| !opt.is_empty()) {
+            let (on, name) = opt
+                .strip_prefix('-')
+                .map_or((true, opt), |name| (false, name));
+            match name {
+                "blank-at-eol" | "trailing-space" => result.blank_at_eol = on,
+                "space-before-tab" => result.space_before_tab = on,
+                "tab-in-indent" => result.tab_in_indent = on,
+                "blank-at-eof" | "indent-with-non-tab" | "cr-at-eol" => {}
+                _ if name.starts_with("tabwidth=") => {}
+                otherwise => {
+                    return Err(anyhow::Error::msg(format!(
+                        "unrecognised option in `whitespace`: {otherwise}"
+                    )))
+                }
+            }
+        }
+        Ok(result)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crossterm::style::Color;
+    use test_case::test_case;
 
     // Should be up to date with the example config in the README.
+    #[test_case("j", KeyCode::Char('j'), KeyModifiers::NONE ; "char")]
+    #[test_case("-", KeyCode::Char('-'), KeyModifiers::NONE ; "dash")]
+    #[test_case("Space", KeyCode::Char(' '), KeyModifiers::NONE ; "named")]
+    #[test_case("pagedown", KeyCode::PageDown, KeyModifiers::NONE ; "lowercase name")]
+    #[test_case("C-d", KeyCode::Char('d'), KeyModifiers::CONTROL ; "ctrl")]
+    #[test_case("C-M-Left", KeyCode::Left, KeyModifiers::CONTROL | KeyModifiers::ALT ; "ctrl alt")]
+    #[test_case("C--", KeyCode::Char('-'), KeyModifiers::CONTROL ; "ctrl dash")]
+    fn parse_key(input: &str, code: KeyCode, modifiers: KeyModifiers) {
+        assert_eq!(Key::from_str(input).unwrap(), Key { code, modifiers });
+    }
+
+    #[test_case("j" ; "char")]
+    #[test_case("Space" ; "space")]
+    #[test_case("PageDown" ; "named")]
+    #[test_case("C-M-n" ; "modifiers")]
+    #[test_case("C--" ; "ctrl dash")]
+    fn display_key(key: &str) {
+        assert_eq!(Key::from_str(key).unwrap().to_string(), key);
+    }
+
+    #[test_case("", true, true, false ; "git default")]
+    #[test_case("tab-in-indent", true, true, true ; "turn on")]
+    #[test_case("-space-before-tab,-trailing-space", false, false, false ; "turn off")]
+    #[test_case("blank-at-eof, tabwidth=4", true, true, false ; "not shown")]
+    fn parse_whitespace(s: &str, blank_at_eol: bool, space_before_tab: bool, tab_in_indent: bool) {
+        assert_eq!(
+            Whitespace::from_str(s).unwrap(),
+            Whitespace {
+                blank_at_eol,
+                space_before_tab,
+                tab_in_indent
+            }
+        );
+    }
+
     #[test]
     fn parse_readme_example() {
-        const INPUT: &str = "auto_expand_files = false
+        const INPUT: &str = "[options]
+auto_expand_files = false
 auto_expand_hunks = true
 lookahead_lines = 5
 truncate_lines = true # `false` is not recommended - see #37
+wrap_lines = false # wrap long lines onto the next rows instead of cutting them off
 ws_error_highlight = \"new\" # override git's diff.wsErrorHighlight
+whitespace = \"tab-in-indent\" # override git's core.whitespace
+tab_width = 8 # columns between tab stops in diffs
+line_numbers = false # show the old and new line numbers of each line in diffs
+syntax_highlighting = true # turn off to speed up rendering huge diffs
+context_lines = 3 # lines of context shown around changes
+untracked_preview_lines = 500 # lines of an untracked file shown when it's expanded
+untracked_preview_bytes = 1000000 # untracked files bigger than this aren't shown
+git_path = \"git\" # the git executable to use, overridden by $GEX_GIT
+tree_view = false # group files by directory, toggled with `T`
+side_by_side = false # show the old and new versions of hunks next to each other, toggled with `=`
+auto_refresh = false # refresh when files change outside gex
+show_untracked = true # list untracked files, toggled with `.`
+show_last_command = true # show the last git command a key ran at the bottom of the screen
+mouse = false # click files and hunks to select and expand them, and scroll with the wheel
+git_timeout = 120 # seconds before a fetch, pull or push is stopped, or 0 to wait for ever
+rename_threshold = 50 # how similar, in percent, a deleted and a new file must be to count as a rename, or 0 to never
+detect_copies = false # also show new files that are copies of changed ones as copies, like `git diff -C`
+advance_after_stage = false # after staging or unstaging, move on to the next change left to stage or unstage
+editor_line_number = true # open files in your editor at the hunk with `+<line>`, turn off for editors that don't take it
 
 # Named colours use the terminal colour scheme. You can also describe your colours
 # by hex string \"#RRGGBB\", RGB \"rgb_(r,g,b)\" or by Ansi \"ansi_(value)\".
@@ -245,6 +769,29 @@ addition = \"#b8bb26\"
 deletion = \"#fb4934\"
 key = \"#d79921\"
 error = \"#cc241d\"
+search_match = \"#665c54\"
+cursor = \"#504945\" # leave out to show the cursor in reverse video
+
+# Shown before files, directories and hunks. Left out, they're \"⌄\" and \"›\", or \"v\" and \">\"
+# when your locale isn't UTF-8.
+[symbols]
+# expanded = \"⌄\"
+# collapsed = \"›\"
+
+# Actions that can lose work ask first. Turn off the ones you'd rather not be asked about.
+[confirm]
+discard = true
+force_push = true
+delete_branch = true
+amend_pushed = true
+reset_hard = true
+drop_stash = true
+
+# Each action can be bound to any number of keys. Modifiers are written like \"C-n\" for
+# Ctrl+n or \"M-n\" for Alt+n. Actions that aren't listed keep their default keys.
+[keys]
+down = [\"j\", \"Down\", \"C-n\"]
+up = [\"k\", \"Up\", \"C-p\"]
 ";
         assert_eq!(
             toml::from_str(INPUT),
@@ -254,11 +801,35 @@ error = \"#cc241d\"
                     auto_expand_hunks: true,
                     lookahead_lines: 5,
                     truncate_lines: true,
+                    wrap_lines: false,
                     ws_error_highlight: WsErrorHighlight {
                         old: false,
                         new: true,
                         context: false
-                    }
+                    },
+                    whitespace: Whitespace {
+                        blank_at_eol: true,
+                        space_before_tab: true,
+                        tab_in_indent: true,
+                    },
+                    tab_width: 8,
+                    line_numbers: false,
+                    syntax_highlighting: true,
+                    context_lines: 3,
+                    untracked_preview_lines: 500,
+                    untracked_preview_bytes: 1_000_000,
+                    git_path: String::from("git"),
+                    tree_view: false,
+                    side_by_side: false,
+                    auto_refresh: false,
+                    show_untracked: true,
+                    show_last_command: true,
+                    mouse: false,
+                    git_timeout: 120,
+                    rename_threshold: 50,
+                    detect_copies: false,
+                    advance_after_stage: false,
+                    editor_line_number: true,
                 },
                 colors: Colors {
                     foreground: Color::from((235, 219, 178)),
@@ -268,9 +839,33 @@ error = \"#cc241d\"
                     addition: Color::from((184, 187, 38)),
                     deletion: Color::from((251, 73, 52)),
                     key: Color::from((215, 153, 33)),
-                    error: Color::from((204, 36, 29))
+                    error: Color::from((204, 36, 29)),
+                    search_match: Color::from((102, 92, 84)),
+                    cursor: Color::from((80, 73, 69)),
+                },
+                symbols: Symbols::default(),
+                confirm: Confirm {
+                    discard: true,
+                    force_push: true,
+                    delete_branch: true,
+                    amend_pushed: true,
+                    reset_hard: true,
+                    drop_stash: true,
+                },
+                keys: Keys {
+                    down: vec![
+                        Key::from_str("j").unwrap(),
+                        Key::from_str("Down").unwrap(),
+                        Key::from_str("C-n").unwrap()
+                    ],
+                    up: vec![
+                        Key::from_str("k").unwrap(),
+                        Key::from_str("Up").unwrap(),
+                        Key::from_str("C-p").unwrap()
+                    ],
+                    ..Keys::default()
                 }
             })
-        )
+        );
     }
 }
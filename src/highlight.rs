@@ -0,0 +1,77 @@
+//! Syntax highlighting of diff content.
+
+use std::{fmt::Write, path::Path, sync::OnceLock};
+
+use crossterm::style::Color;
+use syntect::{
+    easy::HighlightLines,
+    highlighting::{Theme, ThemeSet},
+    parsing::{SyntaxReference, SyntaxSet},
+};
+
+use crate::render::SetForegroundColor;
+
+static SYNTAX_SET: OnceLock<SyntaxSet> = OnceLock::new();
+static THEME: OnceLock<Theme> = OnceLock::new();
+
+fn syntax_set() -> &'static SyntaxSet {
+    SYNTAX_SET.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+/// Finds the syntax to highlight `path` with from its extension, or its whole name for files
+/// like `Makefile`. Returns `None` for files we don't know how to highlight.
+pub fn syntax_for_path(path: &str) -> Option<&'static SyntaxReference> {
+    let path = Path::new(path);
+    let syntax_set = syntax_set();
+    path.extension()
+        .and_then(|ext| syntax_set.find_syntax_by_extension(ext.to_str()?))
+        .or_else(|| syntax_set.find_syntax_by_extension(path.file_name()?.to_str()?))
+}
+
+/// Highlights the content of each line of a hunk (without the `@@` header), leaving out the first
+/// character of each line, i.e. the `+`, `-` or ` `. Lines that aren't code, like "\ No newline at
+/// end of file", aren't highlighted.
+///
+/// Old and new lines are highlighted as if they were one file, which can go wrong across
+/// multi-line constructs, but is right most of the time.
+pub fn highlight_lines<'a>(
+    syntax: &SyntaxReference,
+    lines: impl Iterator<Item = &'a str>,
+) -> Vec<String> {
+    let theme = THEME.get_or_init(|| {
+        ThemeSet::load_defaults()
+            .themes
+            .remove("base16-ocean.dark")
+            .expect("theme is included in syntect's defaults")
+    });
+    let mut highlighter = HighlightLines::new(syntax, theme);
+
+    lines
+        .map(|line| {
+            let Some(content) = line.strip_prefix(['+', '-', ' ']) else {
+                return line.get(1..).unwrap_or_default().to_string();
+            };
+            // The syntaxes expect each line to end with a newline.
+            let content = format!("{content}\n");
+            let Ok(regions) = highlighter.highlight_line(&content, syntax_set()) else {
+                return content.trim_end_matches('\n').to_string();
+            };
+            let mut escaped = String::new();
+            for (style, text) in regions {
+                let fg = style.foreground;
+                write!(
+                    escaped,
+                    "{}{}",
+                    SetForegroundColor(Color::Rgb {
+                        r: fg.r,
+                        g: fg.g,
+                        b: fg.b
+                    }),
+                    text.trim_end_matches('\n')
+                )
+                .expect("writing to a String can't fail");
+            }
+            escaped
+        })
+        .collect()
+}
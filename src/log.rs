@@ -0,0 +1,223 @@
+//! Module for the log view, which lists recent commits and shows the diff of one when it's opened.
+
+use std::fmt;
+
+use anyhow::{Context, Result};
+use crossterm::style::Attribute;
+
+use crate::{
+    config::{Options, CONFIG},
+    git_process,
+    minibuffer::MiniBuffer,
+    parse,
+    render::{self, Renderer, ResetAttributes, SetAttribute, SetCursorStyle, SetForegroundColor},
+    status::FileDiff,
+};
+
+/// How many commits to list.
+const LOG_LENGTH: &str = "256";
+
+pub struct Commit {
+    /// The rails of the graph of branches and merges on the commit's line.
+    graph: String,
+    /// The lines of only rails after the commit, where branches fork or merge.
+    connectors: Vec<String>,
+    hash: String,
+    subject: String,
+    author: String,
+    /// When the commit was made, relative to now, e.g. "2 days ago".
+    date: String,
+}
+
+/// A commit opened from the log.
+pub struct CommitDiff {
+    /// What `git show` prints before the diff: the hash, author, date and message.
+    info: String,
+    files: Vec<FileDiff>,
+    /// Whether to show hunks side by side, as they were in the status when this was opened.
+    side_by_side: bool,
+}
+
+#[derive(Default)]
+pub struct Log {
+    pub commits: Vec<Commit>,
+    pub cursor: usize,
+    /// The commit under the cursor, once it's been opened.
+    pub commit: Option<CommitDiff>,
+}
+
+impl render::Render for Log {
+    fn render(&self, f: &mut Renderer) -> fmt::Result {
+        use fmt::Write;
+        let config = CONFIG.get().expect("config wasn't initialised");
+
+        if let Some(ref commit) = self.commit {
+            // The whole commit is the selected item, so that it can be scrolled through.
+            f.insert_cursor();
+            let mut info = commit.info.lines();
+            write!(
+                f,
+                "\r{}{}{ResetAttributes}",
+                SetForegroundColor(config.colors.heading),
+                info.next().unwrap_or_default()
+            )?;
+            for line in info {
+                write!(f, "\r\n{line}")?;
+            }
+            if !commit.files.is_empty() {
+                write!(f, "\r\n")?;
+            }
+            for file in &commit.files {
+                write!(f, "\r\n")?;
+                file.render(f, "", commit.side_by_side)?;
+                write!(f, "{ResetAttributes}")?;
+            }
+            f.insert_item_end();
+            return Ok(());
+        }
+
+        if self.commits.is_empty() {
+            return write!(
+                f,
+                "{}No commits yet.{ResetAttributes}",
+                SetForegroundColor(config.colors.heading),
+            );
+        }
+
+        for (i, commit) in self.commits.iter().enumerate() {
+            if i == self.cursor {
+                f.insert_cursor();
+            }
+            writeln!(
+                f,
+                "\r{}{}{}{ResetAttributes} {}{}{ResetAttributes} {}({}, {}){ResetAttributes}",
+                commit.graph,
+                SetAttribute(Attribute::Dim),
+                commit.hash,
+                if i == self.cursor {
+                    SetCursorStyle.to_string()
+                } else {
+                    String::new()
+                },
+                commit.subject,
+                SetAttribute(Attribute::Dim),
+                commit.author,
+                commit.date,
+            )?;
+            for connector in &commit.connectors {
+                writeln!(f, "\r{connector}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Log {
+    pub fn fetch(&mut self) -> Result<()> {
+        let output = git_process(&[
+            "log",
+            "--graph",
+            "--format=%x00%h%x00%s%x00%an%x00%ar",
+            "-n",
+            LOG_LENGTH,
+        ])?;
+
+        // Without any commits, `git log` fails and there's nothing to list.
+        self.commits = parse::parse_log_graph(&String::from_utf8_lossy(&output.stdout))
+            .into_iter()
+            .filter_map(|commit| {
+                let mut fields = commit.fields.into_iter();
+                Some(Commit {
+                    graph: commit.graph.to_string(),
+                    connectors: commit.connectors.into_iter().map(str::to_string).collect(),
+                    hash: fields.next()?.to_string(),
+                    subject: fields.next()?.to_string(),
+                    author: fields.next()?.to_string(),
+                    date: fields.next()?.to_string(),
+                })
+            })
+            .collect();
+        self.cursor = self.cursor.min(self.commits.len().saturating_sub(1));
+        self.commit = None;
+        Ok(())
+    }
+
+    pub const fn down(&mut self) {
+        if self.cursor + 1 < self.commits.len() {
+            self.cursor += 1;
+        }
+    }
+
+    pub const fn up(&mut self) {
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    pub const fn cursor_first(&mut self) {
+        self.cursor = 0;
+    }
+
+    pub const fn cursor_last(&mut self) {
+        self.cursor = self.commits.len().saturating_sub(1);
+    }
+
+    /// Moves the cursor to the commit with the full or abbreviated `hash`. Returns false if it
+    /// isn't one of the commits listed.
+    pub fn select(&mut self, hash: &str) -> bool {
+        let Some(i) = self
+            .commits
+            .iter()
+            .position(|commit| hash.starts_with(&commit.hash) || commit.hash.starts_with(hash))
+        else {
+            return false;
+        };
+        self.cursor = i;
+        true
+    }
+
+    /// The abbreviated hash of the commit under the cursor.
+    pub fn selected_hash(&self) -> Option<&str> {
+        self.commits.get(self.cursor).map(|c| c.hash.as_str())
+    }
+
+    /// Runs `git <args> <hash>` on the commit under the cursor, e.g. to revert it, and shows its
+    /// output. Returns whether it worked, as it stops partway when there are conflicts.
+    pub fn apply_selected(&self, args: &[&str]) -> Result<bool> {
+        let Some(commit) = self.commits.get(self.cursor) else {
+            return Ok(true);
+        };
+        let output = git_process(&[args, &[&commit.hash]].concat())?;
+        MiniBuffer::push_command_output(&output);
+        Ok(output.status.success())
+    }
+
+    /// Opens the commit under the cursor, showing its diff with hunks side by side if
+    /// `side_by_side`.
+    pub fn open(&mut self, options: &Options, side_by_side: bool) -> Result<()> {
+        let Some(commit) = self.commits.get(self.cursor) else {
+            return Ok(());
+        };
+        let output = git_process(&["show", "--no-ext-diff", &commit.hash])?;
+        let show = String::from_utf8_lossy(&output.stdout);
+
+        let info = show
+            .lines()
+            .take_while(|l| !l.starts_with("diff"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let mut diffs = parse::parse_diff(&show)
+            .context("failed to parse commit diff")?
+            .into_iter()
+            .collect::<Vec<_>>();
+        diffs.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        self.commit = Some(CommitDiff {
+            info: info.trim_end().to_string(),
+            files: diffs
+                .iter()
+                .map(|(path, diff)| FileDiff::from_diff(path, diff, options))
+                .collect(),
+            side_by_side,
+        });
+        Ok(())
+    }
+}
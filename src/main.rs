@@ -5,77 +5,449 @@
     clippy::missing_errors_doc,
     clippy::redundant_closure_for_method_calls,
     clippy::module_name_repetitions,
-    clippy::let_underscore_untyped
+    clippy::let_underscore_untyped,
+    clippy::collapsible_match,
+    clippy::format_collect
 )]
 
 use std::{
-    cmp, env,
-    io::{stdin, stdout, BufRead, Write},
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    collections::VecDeque,
+    env,
+    ffi::OsString,
+    fs,
+    io::{stdin, stdout, BufRead, Read, Write},
     panic,
-    process::{self, Command, Output},
+    path::{self, Path, PathBuf},
+    process::{self, Child, Command, ExitStatus, Output, Stdio},
     rc::Rc,
+    sync::{OnceLock, PoisonError, RwLock},
+    thread,
+    time::{Duration, Instant},
 };
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use arboard::Clipboard;
 use clap::Parser;
 use config::Clargs;
 use crossterm::{
     cursor,
-    event::{self, Event, KeyCode, KeyEventKind},
-    style::{Attribute, SetForegroundColor},
+    event::{
+        self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyEventKind,
+        KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+    },
+    style::Attribute,
     terminal::{self, ClearType},
 };
 use git2::Repository;
 
 use crate::{
     command::GexCommand,
-    config::{Config, CONFIG},
-    minibuffer::{Callback, MessageType, MiniBuffer},
-    render::{Clear, Render, ResetAttributes},
+    config::{Action, Config, Options, CONFIG},
+    minibuffer::{Callback, Input, MessageType, MiniBuffer},
+    render::{Clear, LongLines, Render, ResetAttributes, SetAttribute, SetForegroundColor},
 };
 
+mod blame;
 mod branch;
 mod command;
 mod config;
 mod debug;
+mod highlight;
+mod log;
 mod minibuffer;
+mod output;
 mod parse;
+mod patch;
+mod process_group;
 mod render;
+mod stash;
 mod status;
+mod tag;
+mod watch;
+mod worktree;
 
+use blame::Blame;
 use branch::BranchList;
+use log::Log;
+use output::CommandOutput;
 use render::Renderer;
+use stash::StashList;
 use status::Status;
+use tag::TagList;
+use watch::Watcher;
+use worktree::WorktreeList;
 
 pub struct State {
     view: View,
     minibuffer: MiniBuffer,
     status: Status,
     branch_list: BranchList,
+    tag_list: TagList,
+    stash_list: StashList,
+    worktree_list: WorktreeList,
+    log: Log,
+    blame: Blame,
+    output: CommandOutput,
+    /// The commit the Tag menu was opened on in the log. Tags are put on HEAD otherwise.
+    tag_target: Option<String>,
     repo: Repository,
     renderer: Renderer,
+    /// Whether commits are made with `--no-gpg-sign`, for quick commits that don't need signing.
+    no_gpg_sign: bool,
+    /// Whether the next commit is made with `--no-verify`, skipping the pre-commit and commit-msg
+    /// hooks. It only lasts for one commit, so that hooks aren't skipped by accident.
+    no_verify: bool,
+    /// Created when something is first copied. On some platforms, what's been copied is only
+    /// available while this is still around.
+    clipboard: Option<Clipboard>,
+    /// Watches for changes to the work tree, if `options.auto_refresh` is on.
+    watcher: Option<Watcher>,
+    /// Whether the last key pressed was the first of the two that go to the top.
+    first_pending: bool,
 }
 
 #[derive(Clone)]
 pub enum View {
     Status,
+    /// Choosing individual lines of the hunk under the status cursor.
+    LineSelect,
     BranchList,
+    TagList,
+    /// The stashes, or the diff of one of them.
+    StashList,
+    WorktreeList,
+    /// Recent commits, or the diff of one of them.
+    Log,
+    /// Who last changed each line of a file.
+    Blame,
+    /// What a git command said, when it was too long for the minibuffer.
+    Output,
+    /// The keys bound to each action, shown over the status.
+    Help,
     Command(GexCommand),
-    Input(Callback, Box<View>),
+    Input(Callback, Box<Self>),
 }
 
+/// The git executable, from `$GEX_GIT` or `options.git_path`. Set once at startup.
+static GIT: OnceLock<String> = OnceLock::new();
+
+/// Whether each git command is logged to stderr, from `--verbose`. Set once at startup.
+static VERBOSE: OnceLock<bool> = OnceLock::new();
+
+/// The work tree switched to from the list of them, which commands are run in and paths are
+/// relative to, rather than the one gex started in. `None` until gex switches.
+static WORKTREE: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+/// The work tree switched to from the list of them, if gex has switched.
+#[must_use]
+pub fn worktree() -> Option<PathBuf> {
+    WORKTREE
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+}
+
+/// Makes `command` run in the work tree gex is showing, which may not be the one it started in.
+pub fn in_worktree(command: &mut Command) -> &mut Command {
+    if let Some(path) = worktree() {
+        // These would keep the command in the work tree gex was started in.
+        command
+            .current_dir(path)
+            .env_remove("GIT_DIR")
+            .env_remove("GIT_WORK_TREE");
+    }
+    command
+}
+
+/// Where `path`, relative to the work tree gex is showing, can be opened.
+#[must_use]
+pub fn worktree_path(path: &str) -> PathBuf {
+    worktree().map_or_else(|| PathBuf::from(path), |worktree| worktree.join(path))
+}
+
+thread_local! {
+    /// The last git command run for a key press, shown at the bottom of the screen. Those gex runs
+    /// by itself, like to fetch the status, aren't shown.
+    static LAST_COMMAND: RefCell<Option<String>> = const { RefCell::new(None) };
+
+    /// Whether a key press is being handled, so that the git commands run are ones the user asked
+    /// for.
+    static HANDLING_KEY: Cell<bool> = const { Cell::new(false) };
+
+    /// Events read while waiting for a git command to finish, other than those cancelling it, to
+    /// be handled once it has.
+    static PENDING_EVENTS: RefCell<VecDeque<Event>> = const { RefCell::new(VecDeque::new()) };
+}
+
+/// The git command with `args`, quoted so that it could be run in a shell.
+fn command_line(args: &[&str]) -> String {
+    format!(
+        "git {}",
+        args.iter()
+            .map(|arg| shell_quote(arg))
+            .collect::<Vec<_>>()
+            .join(" ")
+    )
+}
+
+/// A `Command` to run git with `args`. Once it has run, [`record_command`] remembers it as the
+/// last command run.
+pub fn git_command(args: &[&str]) -> Command {
+    if VERBOSE.get() == Some(&true) {
+        eprintln!("{}", command_line(args));
+    }
+    let mut command = Command::new(GIT.get().map_or("git", String::as_str));
+    command.args(args);
+    in_worktree(&mut command);
+    command
+}
+
+/// Options given to git itself that take the next argument as their value.
+const GIT_OPTIONS_WITH_VALUES: [&str; 6] = [
+    "-c",
+    "-C",
+    "--git-dir",
+    "--work-tree",
+    "--namespace",
+    "--config-env",
+];
+
+/// Splits the arguments of a git command into its subcommand and the options given to that,
+/// skipping git's own options, like `-c core.editor=vim`, which come before the subcommand.
+#[must_use]
+pub fn split_subcommand<'a, 'b>(mut args: &'b [&'a str]) -> Option<(&'a str, &'b [&'a str])> {
+    loop {
+        match args {
+            [option, _, rest @ ..] if GIT_OPTIONS_WITH_VALUES.contains(option) => args = rest,
+            [option, rest @ ..] if option.starts_with('-') => args = rest,
+            [subcommand, options @ ..] => return Some((subcommand, options)),
+            [] => return None,
+        }
+    }
+}
+
+/// The git subcommands that talk to a remote, which can hang waiting on it and so are stopped
+/// after `options.git_timeout`.
+const REMOTE_SUBCOMMANDS: [&str; 4] = ["fetch", "pull", "push", "ls-remote"];
+
+/// Runs git with `args` and waits for its output. If it talks to a remote, it's killed if it takes
+/// longer than `options.git_timeout`. It's also killed if Esc is pressed while it's running.
 pub fn git_process(args: &[&str]) -> Result<Output> {
-    Command::new("git").args(args).output().with_context(|| {
-        format!(
-            "failed to run `git{}`",
-            args.iter().map(|a| " ".to_string() + a).collect::<String>()
-        )
+    let timeout = match split_subcommand(args) {
+        Some((subcommand, _)) if REMOTE_SUBCOMMANDS.contains(&subcommand) => {
+            CONFIG.get().map_or(0, |config| config.options.git_timeout)
+        }
+        _ => 0,
+    };
+    spawn_and_wait(
+        git_command(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped()),
+        args,
+        timeout,
+    )
+}
+
+/// Runs `command`, git with `args`, and waits for its output like `git_process`, but without a
+/// timeout, as it may be waiting on the user, like in their editor.
+///
+/// Only what it writes to stdout and stderr when they're piped is captured.
+pub fn wait_with_output(command: &mut Command, args: &[&str]) -> Result<Output> {
+    spawn_and_wait(command, args, 0)
+}
+
+/// Runs `command`, git with `args`, and waits for its output, killing it if it takes longer than
+/// `timeout` seconds, unless that's 0, or it's cancelled.
+fn spawn_and_wait(command: &mut Command, args: &[&str], timeout: u64) -> Result<Output> {
+    let line = format!(
+        "git{}",
+        args.iter().map(|a| " ".to_string() + a).collect::<String>()
+    );
+    // Keys can only be read to cancel the command while the terminal is ours.
+    let terminal_ours = terminal::is_raw_mode_enabled().unwrap_or(false);
+    let cancellable = thread::current().name() == Some("main") && terminal_ours;
+    let killable = timeout != 0 || cancellable;
+    if killable {
+        // Whatever git starts, like hooks and ssh, is stopped along with it.
+        process_group::isolate(command);
+    }
+    let mut child = command
+        .spawn()
+        .with_context(|| format!("failed to run `{line}`"))?;
+    // git, or ssh, may need the terminal to ask for credentials, which it can only read from if
+    // its process group is in the foreground.
+    let _foreground = (killable && !terminal_ours).then(|| process_group::Foreground::new(&child));
+
+    // The output is read as it comes, as git stops once the pipe is full until it's read.
+    let stdout = child.stdout.take().map(read_to_end);
+    let stderr = child.stderr.take().map(read_to_end);
+    let status = wait_or_kill(&mut child, &line, timeout, cancellable)?;
+    record_command(args);
+    let collect = |reader: Option<thread::JoinHandle<Vec<u8>>>| {
+        reader.map_or_else(Vec::new, |r| r.join().unwrap_or_default())
+    };
+    Ok(Output {
+        status,
+        stdout: collect(stdout),
+        stderr: collect(stderr),
     })
 }
 
+/// Reads all of `reader` on another thread.
+fn read_to_end(mut reader: impl Read + Send + 'static) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        drop(reader.read_to_end(&mut buf));
+        buf
+    })
+}
+
+/// Waits for the git command `child`, which was run as `line`, to finish. If it takes longer
+/// than `timeout` seconds, unless that's 0, or it's `cancellable` and Esc or Ctrl-C is pressed,
+/// it's killed along with its process group and this fails. Keys are only read once the command
+/// has taken long enough to say so at the bottom of the screen. Any other events read then are
+/// kept for the main loop, so that they aren't lost.
+fn wait_or_kill(
+    child: &mut Child,
+    line: &str,
+    timeout: u64,
+    cancellable: bool,
+) -> Result<ExitStatus> {
+    /// How long a command runs before it can be cancelled.
+    const CANCEL_AFTER: Duration = Duration::from_secs(1);
+
+    let start = Instant::now();
+    let mut interval = Duration::from_millis(1);
+    let mut prompted = false;
+    loop {
+        if let Some(status) = child
+            .try_wait()
+            .with_context(|| format!("failed to wait for `{line}`"))?
+        {
+            if prompted {
+                render::repaint_all();
+            }
+            return Ok(status);
+        }
+
+        let elapsed = start.elapsed();
+        let stopped = if timeout != 0 && elapsed.as_secs() >= timeout {
+            Some(format!(
+                "`{line}` timed out after {timeout} seconds and was stopped."
+            ))
+        } else if cancellable && elapsed >= CANCEL_AFTER {
+            if !prompted {
+                prompted = true;
+                let (_, height) = terminal::size().unwrap_or_default();
+                crossterm::execute!(
+                    stdout(),
+                    cursor::MoveTo(0, height.saturating_sub(1)),
+                    terminal::Clear(ClearType::CurrentLine),
+                )?;
+                print!("Running `{line}`. Press Esc to cancel.");
+                stdout().flush()?;
+            }
+            let cancelled = event::poll(interval)?
+                && match event::read()? {
+                    Event::Key(
+                        KeyEvent {
+                            code: KeyCode::Esc,
+                            kind: KeyEventKind::Press,
+                            ..
+                        }
+                        | KeyEvent {
+                            code: KeyCode::Char('c'),
+                            modifiers: KeyModifiers::CONTROL,
+                            kind: KeyEventKind::Press,
+                            ..
+                        },
+                    ) => true,
+                    event => {
+                        PENDING_EVENTS.with(|pending| pending.borrow_mut().push_back(event));
+                        false
+                    }
+                };
+            cancelled.then(|| format!("`{line}` was cancelled."))
+        } else {
+            thread::sleep(interval);
+            None
+        };
+        if let Some(message) = stopped {
+            process_group::kill(child);
+            drop(child.wait());
+            if prompted {
+                render::repaint_all();
+            }
+            bail!(message);
+        }
+        interval = (interval * 2).min(Duration::from_millis(50));
+    }
+}
+
+/// Remembers the git command with `args`, which has just run, as the last one run, if it was run
+/// for a key press.
+pub fn record_command(args: &[&str]) {
+    if HANDLING_KEY.get() {
+        LAST_COMMAND.with(|last| *last.borrow_mut() = Some(command_line(args)));
+    }
+}
+
+/// The last git command run for a key press, to show at the bottom of the screen.
+fn last_command() -> Option<String> {
+    LAST_COMMAND.with(|last| last.borrow().clone())
+}
+
+/// Quotes `arg` for a shell if it needs it, so that the last command can be copied and run.
+fn shell_quote(arg: &str) -> Cow<'_, str> {
+    if !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:@%+,^~".contains(c))
+    {
+        Cow::Borrowed(arg)
+    } else {
+        Cow::Owned(format!("'{}'", arg.replace('\'', r"'\''")))
+    }
+}
+
 fn run(clargs: &Clargs) -> Result<()> {
-    // Attempt to find a git repository at or above current path
-    let repo = if let Ok(repo) = Repository::discover(&clargs.path) {
+    // The options are passed on to the git commands gex runs through the variables git reads.
+    // Relative paths would be wrong once the working directory changes to the work tree.
+    for (var, option) in [
+        ("GIT_DIR", &clargs.git_dir),
+        ("GIT_WORK_TREE", &clargs.work_tree),
+    ] {
+        if let Some(path) = option
+            .as_deref()
+            .map(OsString::from)
+            .or_else(|| env::var_os(var))
+            .filter(|path| !path.is_empty())
+        {
+            let path = path::absolute(&path)
+                .with_context(|| format!("invalid {var} `{}`", path.to_string_lossy()))?;
+            env::set_var(var, path);
+        }
+    }
+
+    // With $GIT_DIR or $GIT_WORK_TREE, the repository is wherever they say, like it is for git.
+    // Otherwise, attempt to find a git repository at or above current path.
+    let work_tree = env::var_os("GIT_WORK_TREE");
+    let found = if env::var_os("GIT_DIR").is_some() || work_tree.is_some() {
+        // libgit2 leaves bare repositories without a work tree even when one is given, which is
+        // how repositories of dotfiles are usually set up.
+        Repository::open_from_env().and_then(|repo| {
+            if let Some(work_tree) = work_tree {
+                repo.set_workdir(Path::new(&work_tree), false)?;
+            }
+            Ok(repo)
+        })
+    } else {
+        Repository::discover(&clargs.path)
+    };
+    let repo = if let Ok(repo) = found {
         repo
     } else {
         print!("Not a git repository. Initialise one? [y/N]");
@@ -93,14 +465,21 @@ fn run(clargs: &Clargs) -> Result<()> {
         Repository::init(&clargs.path).context("failed to initialise git repository")?
     };
 
+    // Bare repositories have no work tree to show the status of.
+    let Some(workdir) = repo.workdir() else {
+        bail!(
+            "{} is a bare repository, so there's no working tree to show",
+            repo.path().display()
+        );
+    };
+
     // Set working directory in case the repository is not the current directory
-    std::env::set_current_dir(repo.path().parent().context("`.git` cannot be root dir")?)
-        .context("failed to set working directory")?;
+    env::set_current_dir(workdir).context("failed to set working directory")?;
 
     let minibuffer = MiniBuffer::new();
 
     let config = CONFIG.get_or_init(|| {
-        Config::read_from_file(&clargs.config_file)
+        Config::read_from_file(clargs.config_file.as_deref())
             .unwrap_or_else(|e| {
                 MiniBuffer::push(&format!("{e:?}"), MessageType::Error);
                 Some((Config::default(), Vec::new()))
@@ -118,8 +497,52 @@ fn run(clargs: &Clargs) -> Result<()> {
             })
     });
 
-    let status = Status::new(&repo, &config.options)?;
+    if clargs.no_color || std::env::var("NO_COLOR").is_ok_and(|v| !v.is_empty()) {
+        render::disable_color();
+    }
+
+    let git = std::env::var("GEX_GIT")
+        .ok()
+        .filter(|git| !git.is_empty())
+        .unwrap_or_else(|| config.options.git_path.clone());
+    let version = Command::new(&git)
+        .arg("--version")
+        .output()
+        .with_context(|| format!("couldn't run git at `{git}`"))?;
+    if !version.status.success() {
+        bail!("`{git} --version` failed, so it doesn't look like git");
+    }
+    GIT.set(git).expect("git is only set at startup");
+    VERBOSE
+        .set(clargs.verbose)
+        .expect("verbose is only set at startup");
+
+    if clargs.print || clargs.json {
+        let mut status = Status::new(&config.options);
+        status.wait_fetch(&repo, &config.options)?;
+        let output = if clargs.json {
+            serde_json::to_string(&status).context("failed to serialise the status")?
+        } else {
+            status.hide_cursor();
+            let mut renderer = Renderer::default();
+            status.render(&mut renderer)?;
+            renderer.take()
+        };
+        // Whatever's reading may stop early, like `head` does, which isn't worth an error.
+        drop(writeln!(stdout(), "{output}"));
+        return Ok(());
+    }
+
+    let status = Status::new(&config.options);
     let branch_list = BranchList::new()?;
+    let watcher = match repo.workdir() {
+        Some(workdir) if config.options.auto_refresh => {
+            Watcher::new(repo.path().to_path_buf(), workdir.to_path_buf())
+                .map_err(|e| MiniBuffer::push(&format!("{e:?}"), MessageType::Error))
+                .ok()
+        }
+        _ => None,
+    };
     let view = View::Status;
     let renderer = Renderer::default();
 
@@ -128,24 +551,22 @@ fn run(clargs: &Clargs) -> Result<()> {
         minibuffer,
         status,
         branch_list,
+        tag_list: TagList::default(),
+        stash_list: StashList::default(),
+        worktree_list: WorktreeList::default(),
+        log: Log::default(),
+        blame: Blame::default(),
+        output: CommandOutput::default(),
+        tag_target: None,
         repo,
         renderer,
+        no_gpg_sign: false,
+        no_verify: false,
+        clipboard: None,
+        watcher,
+        first_pending: false,
     };
 
-    // Non-English locale settings are currently unsupported. See
-    // https://github.com/Piturnah/gex/issues/13.
-    if !env::var("LANG")
-        .map(|s| s.starts_with("en"))
-        .unwrap_or(true)
-    {
-        MiniBuffer::push("WARNING: Non-English locale detected. For now, Gex only supports English locale setting.
-Set locale to English, e.g.:
-
-        $ LANG=en_GB gex
-
-See https://github.com/Piturnah/gex/issues/13.", MessageType::Error);
-    }
-
     // We are about to start messing with the terminal settings. So let's update the panic hook so
     // that the panic messages will be displayed cleanly.
     let panic = panic::take_hook();
@@ -158,6 +579,7 @@ See https://github.com/Piturnah/gex/issues/13.", MessageType::Error);
         .context("failed to enter alternate screen")?;
     terminal::enable_raw_mode().context("failed to put terminal in raw mode")?;
     print!("{}", cursor::Hide);
+    capture_mouse(true)?;
 
     // Structure of the event loop
     //
@@ -171,18 +593,61 @@ See https://github.com/Piturnah/gex/issues/13.", MessageType::Error);
         let (term_width, term_height) =
             terminal::size().context("failed to query terminal dimensions")?;
 
+        if let Some(query) = state.minibuffer.search_query() {
+            query.clone_into(&mut state.status.search);
+        }
+        if let Some(filter) = state.minibuffer.filter_query() {
+            state.status.set_filter(filter);
+        }
+
+        // Have the terminal show the frame all at once, if it can, rather than as it's drawn.
+        crossterm::queue!(stdout(), terminal::BeginSynchronizedUpdate)
+            .context("failed to begin synchronized update")?;
         print!("{ResetAttributes}");
         match state.view {
-            View::Status | View::Command(_) | View::Input(..) => {
+            View::Log | View::Command(GexCommand::Reset | GexCommand::Fixup) => {
+                state.log.render(&mut state.renderer)?;
+            }
+            View::Command(GexCommand::Tag) if state.tag_target.is_some() => {
+                state.log.render(&mut state.renderer)?;
+            }
+            View::Input(_, ref return_view) if matches!(**return_view, View::Log) => {
+                state.log.render(&mut state.renderer)?;
+            }
+            View::Input(_, ref return_view) if matches!(**return_view, View::StashList) => {
+                state.stash_list.render(&mut state.renderer)?;
+            }
+            View::Status | View::LineSelect | View::Help | View::Command(_) | View::Input(..) => {
                 state.status.render(&mut state.renderer)?;
             }
             View::BranchList => state.branch_list.render(&mut state.renderer)?,
+            View::TagList => state.tag_list.render(&mut state.renderer)?,
+            View::StashList => state.stash_list.render(&mut state.renderer)?,
+            View::WorktreeList => state.worktree_list.render(&mut state.renderer)?,
+            View::Blame => state.blame.render(&mut state.renderer)?,
+            View::Output => state.output.render(&mut state.renderer)?,
         }
+        let hints = hints(&state);
+        let last_command = last_command().filter(|_| config.options.show_last_command);
         state.renderer.show_and_clear(
             term_width as usize,
-            term_height as usize,
+            // Leave the last line for the hints and the last command.
+            term_height as usize - usize::from(!hints.is_empty() || last_command.is_some()),
             config.options.lookahead_lines,
-            config.options.truncate_lines,
+            if config.options.wrap_lines {
+                LongLines::Wrap
+            } else if config.options.truncate_lines {
+                LongLines::Truncate
+            } else {
+                LongLines::Overflow
+            },
+        );
+        draw_hints(
+            &hints,
+            last_command.as_deref(),
+            term_width as usize,
+            term_height,
+            config,
         );
         drop(stdout().flush());
 
@@ -191,30 +656,45 @@ See https://github.com/Piturnah/gex/issues/13.", MessageType::Error);
             let subcmds = cmd.subcommands();
             print!(
                 "{}{title:═^term_width$}{}{}{}",
-                cursor::MoveTo(0, term_height - 1 - subcmds.len() as u16),
+                cursor::MoveTo(0, term_height.saturating_sub(1 + subcmds.len() as u16)),
                 Clear(ClearType::FromCursorDown),
                 subcmds
                     .iter()
                     .map(|(k, v)| format!(
                         "\r\n {}{}{k}{} => {v}",
                         SetForegroundColor(config.colors.key),
-                        Attribute::Bold,
+                        SetAttribute(Attribute::Bold),
                         ResetAttributes,
                     ))
                     .collect::<String>(),
                 SetForegroundColor(config.colors.foreground),
                 term_width = term_width as usize,
-                title = format!(" {cmd:?} Options "),
+                title = menu_title(cmd, &state),
             );
+            state
+                .renderer
+                .invalidate_from(usize::from(term_height).saturating_sub(1 + subcmds.len()));
 
             drop(stdout().flush());
         }
 
+        if matches!(state.view, View::Help) {
+            draw_help(term_width as usize, term_height as usize, config);
+            state.renderer.invalidate_from(0);
+        }
+
         // Draw the current `debug!` window.
         debug_draw!();
 
         state.minibuffer.pop_message();
         state.minibuffer.render(term_width, term_height)?;
+        if let Some(height) = state.minibuffer.shown_height() {
+            state
+                .renderer
+                .invalidate_from(usize::from(term_height).saturating_sub(height));
+        }
+        crossterm::execute!(stdout(), terminal::EndSynchronizedUpdate)
+            .context("failed to end synchronized update")?;
 
         // Handle input
         //
@@ -223,152 +703,1037 @@ See https://github.com/Piturnah/gex/issues/13.", MessageType::Error);
         // the loop to avoid re-rendering. If it's a key event without KeyEventKind::Release,
         // handle it and break.
         loop {
-            let Event::Key(event) = event::read().context("failed to read a terminal event")?
-            else {
+            let pending = PENDING_EVENTS.with(|pending| pending.borrow_mut().pop_front());
+            // While the status is being fetched in the background, or the work tree is being
+            // watched, check on them every so often instead of waiting indefinitely for an event.
+            if pending.is_none()
+                && (state.status.is_loading() || state.watcher.is_some())
+                && !event::poll(Duration::from_millis(20))
+                    .context("failed to poll for terminal events")?
+            {
+                if state.watcher.as_ref().is_some_and(Watcher::changed) {
+                    state.status.fetch();
+                }
+                match state.status.poll_fetch(&state.repo, &config.options) {
+                    Ok(true) => break,
+                    Ok(false) => continue,
+                    Err(e) => {
+                        MiniBuffer::push(&format!("{e:?}"), MessageType::Error);
+                        break;
+                    }
+                }
+            }
+
+            let event = match pending {
+                Some(event) => event,
+                None => event::read().context("failed to read a terminal event")?,
+            };
+            if let Event::Mouse(event) = event {
+                // The mouse moving around doesn't change anything, so there's no need to redraw.
+                if !matches!(
+                    event.kind,
+                    MouseEventKind::Down(MouseButton::Left)
+                        | MouseEventKind::ScrollDown
+                        | MouseEventKind::ScrollUp
+                ) {
+                    continue;
+                }
+                state.minibuffer.clear_message();
+                if let Err(e) = handle_mouse_event(event, &mut state) {
+                    MiniBuffer::push(&format!("{e:?}"), MessageType::Error);
+                }
+                break;
+            }
+            let Event::Key(event) = event else {
                 break;
             };
             if event.kind == KeyEventKind::Release {
                 continue;
             }
+            // Messages stay up until a key is pressed, even if the screen is redrawn before that.
+            state.minibuffer.clear_message();
 
             if !MiniBuffer::is_empty() {
                 break;
             }
 
-            match state.view {
-                View::Status => match event.code {
-                    KeyCode::Char('j') | KeyCode::Down => state.status.down()?,
-                    KeyCode::Char('k') | KeyCode::Up => state.status.up()?,
-                    KeyCode::Char('J') => state.status.file_down()?,
-                    KeyCode::Char('K') => state.status.file_up()?,
-                    KeyCode::Char('G') => state.status.cursor_last()?,
-                    KeyCode::Char('g') => state.status.cursor_first()?,
-                    KeyCode::Char('s') => {
-                        if state.status.cursor
-                            < state.status.count_untracked + state.status.count_unstaged
-                        {
-                            state.status.stage()?;
-                            state.status.fetch(&state.repo, &config.options)?;
-                        }
-                    }
-                    KeyCode::Char('S') => {
-                        MiniBuffer::push_command_output(&git_process(&["add", "."])?);
-                        state.status.fetch(&state.repo, &config.options)?;
-                    }
-                    KeyCode::Char('u') => {
-                        if state.status.cursor
-                            >= state.status.count_untracked + state.status.count_unstaged
-                        {
-                            state.status.unstage()?;
-                            state.status.fetch(&state.repo, &config.options)?;
-                        }
-                    }
-                    KeyCode::Char('U') => {
-                        MiniBuffer::push_command_output(&git_process(&["reset"])?);
-                        state.status.fetch(&state.repo, &config.options)?;
-                    }
-                    KeyCode::Tab | KeyCode::Char(' ') => state.status.expand()?,
-                    KeyCode::Char('F') => {
-                        MiniBuffer::push_command_output(&git_process(&["pull"])?);
-                        state.status.fetch(&state.repo, &config.options)?;
-                    }
-                    KeyCode::Char('r') => state.status.fetch(&state.repo, &config.options)?,
-                    KeyCode::Char(':') => {
-                        state.minibuffer.command(true, &mut state.view);
-                        state.status.fetch(&state.repo, &config.options)?;
-                    }
-                    KeyCode::Char('!') => {
-                        state.minibuffer.command(false, &mut state.view);
-                        state.status.fetch(&state.repo, &config.options)?;
-                    }
-                    KeyCode::Char('q') => {
-                        terminal::disable_raw_mode().context("failed to disable raw mode")?;
-                        crossterm::execute!(
-                            stdout(),
-                            terminal::LeaveAlternateScreen,
-                            cursor::Show,
-                            cursor::MoveToColumn(0)
-                        )
-                        .context("failed to leave alternate screen")?;
-                        process::exit(0);
-                    }
-                    KeyCode::Char(c1) => {
-                        if let Some((_, cmd)) =
-                            GexCommand::commands().iter().find(|(c2, _)| c1 == *c2)
-                        {
-                            state.view = View::Command(*cmd);
+            HANDLING_KEY.set(true);
+            let handled = handle_key_event(event, &mut state, config);
+            HANDLING_KEY.set(false);
+            if let Err(e) = handled {
+                MiniBuffer::push(&format!("{e:?}"), MessageType::Error);
+            }
+            break;
+        }
+    }
+}
+
+/// The title of the menu for `cmd`, which for commits notes whether they'll be made unsigned or
+/// without running hooks.
+fn menu_title(cmd: GexCommand, state: &State) -> String {
+    let mut toggles = Vec::new();
+    if matches!(cmd, GexCommand::Commit) {
+        if state.no_gpg_sign {
+            toggles.push("unsigned");
+        }
+        if state.no_verify {
+            toggles.push("skipping hooks");
+        }
+    }
+    if toggles.is_empty() {
+        format!(" {cmd:?} Options ")
+    } else {
+        format!(" {cmd:?} Options ({}) ", toggles.join(", "))
+    }
+}
+
+/// Makes gex operate in the work tree at `path`, as if it had been started there. What's been
+/// fetched from the current one is left behind, keeping only how things are displayed.
+fn switch_worktree(state: &mut State, path: &str, config: &Config) -> Result<()> {
+    let repo =
+        Repository::open(path).with_context(|| format!("failed to open the worktree at {path}"))?;
+    // The working directory isn't changed, as the status may still be being fetched from the
+    // work tree being left, on another thread.
+    *WORKTREE.write().unwrap_or_else(PoisonError::into_inner) = Some(
+        Path::new(path)
+            .canonicalize()
+            .with_context(|| format!("failed to find the worktree at {path}"))?,
+    );
+
+    if state.watcher.is_some() {
+        state.watcher = Some(Watcher::new(repo.path().to_path_buf(), path.into())?);
+    }
+    state.repo = repo;
+    state.status = state.status.for_worktree();
+    state.branch_list = BranchList::new()?;
+    state.log = Log::default();
+    state.stash_list = StashList::default();
+    state.status.wait_fetch(&state.repo, &config.options)
+}
+
+/// Turns capturing the mouse on or off, if `options.mouse` is on. It's turned off while another
+/// program has the terminal, so that clicks don't turn up in it as text.
+pub fn capture_mouse(capture: bool) -> Result<()> {
+    if !CONFIG.get().is_some_and(|config| config.options.mouse) {
+        return Ok(());
+    }
+    if capture {
+        crossterm::execute!(stdout(), EnableMouseCapture)
+    } else {
+        crossterm::execute!(stdout(), DisableMouseCapture)
+    }
+    .context("failed to change mouse capture")
+}
+
+/// Clicking selects what was clicked on in the status, and the wheel moves the cursor, or scrolls
+/// through an opened commit or stash.
+fn handle_mouse_event(event: MouseEvent, state: &mut State) -> Result<()> {
+    if event.kind == MouseEventKind::Down(MouseButton::Left) {
+        if let (View::Status, Some((target, first_line))) =
+            (&state.view, state.renderer.target_at(event.row.into()))
+        {
+            state.status.click(target, first_line)?;
+        }
+        return Ok(());
+    }
+    let down = event.kind == MouseEventKind::ScrollDown;
+    match state.view {
+        View::Status if down => state.status.down()?,
+        View::Status => state.status.up()?,
+        View::Log if state.log.commit.is_some() => state.renderer.scroll(wheel_lines(down)),
+        View::Log if down => state.log.down(),
+        View::Log => state.log.up(),
+        View::StashList if state.stash_list.stash.is_some() => {
+            state.renderer.scroll(wheel_lines(down));
+        }
+        View::Output => state.renderer.scroll(wheel_lines(down)),
+        View::StashList if down => state.stash_list.down(),
+        View::StashList => state.stash_list.up(),
+        View::Blame if down => state.blame.down(),
+        View::Blame => state.blame.up(),
+        _ => {}
+    }
+    Ok(())
+}
+
+/// How far a turn of the mouse wheel scrolls.
+const fn wheel_lines(down: bool) -> isize {
+    if down {
+        3
+    } else {
+        -3
+    }
+}
+
+/// Half the height of the terminal, in lines.
+fn half_page() -> Result<usize> {
+    let (_, term_height) = terminal::size().context("failed to query terminal dimensions")?;
+    Ok(usize::from(term_height / 2).max(1))
+}
+
+/// Moves the status cursor to the first file or hunk at least half the height of the terminal
+/// away, or as far as it can go if there isn't one.
+fn move_half_page(state: &mut State, down: bool) -> Result<()> {
+    let half = half_page()?;
+    // Every file, directory and hunk the cursor can be on starts a target, so one render finds
+    // them all.
+    let (start, mut targets) = state.renderer.cursor_and_targets(&state.status)?;
+    if !down {
+        targets.reverse();
+    }
+    let ahead: Vec<_> = targets
+        .into_iter()
+        .filter(|&(line, _)| if down { line > start } else { line < start })
+        .collect();
+    let target = ahead
+        .iter()
+        .find(|(line, _)| line.abs_diff(start) >= half)
+        .or_else(|| ahead.last());
+    if let Some(&(_, target)) = target {
+        state.status.click(target, false)?;
+    }
+    Ok(())
+}
+
+/// The actions most likely to be wanted next, with a word or two about each, for the hint bar.
+fn hints(state: &State) -> Vec<(Action, &'static str)> {
+    let staged = state.status.cursor >= state.status.count_untracked + state.status.count_unstaged;
+    let staging = if staged {
+        (Action::Unstage, "unstage")
+    } else {
+        (Action::Stage, "stage")
+    };
+    match state.view {
+        View::Status if state.status.file_diffs.is_empty() => {
+            vec![(Action::Log, "log"), (Action::Help, "help")]
+        }
+        View::Status if state.status.cursor_hunk().is_some() => {
+            let mut hints = vec![
+                staging,
+                (Action::SelectLines, "select lines"),
+                (Action::Expand, "expand"),
+            ];
+            if !staged {
+                hints.push((Action::Discard, "discard"));
+            }
+            hints.extend([(Action::CopyHunk, "copy"), (Action::Help, "help")]);
+            hints
+        }
+        View::Status => {
+            let mut hints = vec![staging, (Action::Expand, "expand")];
+            if !staged {
+                hints.push((Action::Discard, "discard"));
+            }
+            hints.extend([
+                (Action::OpenEditor, "edit"),
+                (Action::Commit, "commit"),
+                (Action::Help, "help"),
+            ]);
+            hints
+        }
+        View::LineSelect => vec![
+            (Action::Mark, "mark line"),
+            staging,
+            (Action::SelectLines, "back"),
+        ],
+        _ => Vec::new(),
+    }
+}
+
+/// Draws `hints` on the last line of the screen, with the first key bound to each action.
+fn draw_hints(
+    hints: &[(Action, &str)],
+    last_command: Option<&str>,
+    term_width: usize,
+    term_height: u16,
+    config: &Config,
+) {
+    use std::fmt::Write as _;
+    if hints.is_empty() && last_command.is_none() {
+        return;
+    }
+    let mut line = String::new();
+    let mut width = 0;
+    for (action, hint) in hints {
+        let Some(key) = config.keys.keys(*action).first() else {
+            continue;
+        };
+        let key = key.to_string();
+        let separator = if width == 0 { " " } else { " · " };
+        width += separator.chars().count() + key.chars().count() + 1 + hint.chars().count();
+        if width > term_width {
+            break;
+        }
+        write!(
+            line,
+            "{}{separator}{ResetAttributes}{}{key}{ResetAttributes} {hint}",
+            SetAttribute(Attribute::Dim),
+            SetForegroundColor(config.colors.key),
+        )
+        .expect("writing to a String can't fail");
+    }
+    // The last command goes on the right, if there's room for it after the hints.
+    if let Some(command) = last_command {
+        let command_width = command.chars().count() + 1;
+        if width + 2 + command_width <= term_width {
+            write!(
+                line,
+                "{}{}{command}",
+                cursor::MoveToColumn((term_width - command_width) as u16),
+                SetAttribute(Attribute::Dim),
+            )
+            .expect("writing to a String can't fail");
+        }
+    }
+    print!(
+        "{}{}{line}{}{}",
+        cursor::MoveTo(0, term_height.saturating_sub(1)),
+        Clear(ClearType::CurrentLine),
+        ResetAttributes,
+        SetForegroundColor(config.colors.foreground),
+    );
+}
+
+/// Draws the keys bound to each action at the bottom of the screen, in as many columns as are
+/// needed to fit them in.
+fn draw_help(term_width: usize, term_height: usize, config: &Config) {
+    use std::fmt::Write as _;
+    let bindings = config
+        .keys
+        .bindings()
+        .into_iter()
+        .map(|(description, keys)| {
+            let keys = keys.iter().map(ToString::to_string).collect::<Vec<_>>();
+            (keys.join(" / "), description)
+        })
+        .collect::<Vec<_>>();
+    let rows = bindings.len().min(term_height.saturating_sub(2)).max(1);
+    let columns = bindings.chunks(rows).collect::<Vec<_>>();
+    let widths = columns
+        .iter()
+        .map(|column| {
+            column
+                .iter()
+                .map(|(keys, description)| keys.chars().count() + description.chars().count() + 6)
+                .max()
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>();
+
+    let mut lines = vec![String::new(); rows.min(bindings.len())];
+    let mut used = 0;
+    for (column, width) in columns.iter().zip(widths) {
+        // Columns that don't fit would wrap onto the next line and mess up the rest.
+        used += width;
+        if used > term_width {
+            break;
+        }
+        for (line, (keys, description)) in lines.iter_mut().zip(column.iter()) {
+            let padding = width - keys.chars().count() - description.chars().count() - 5;
+            write!(
+                line,
+                " {}{}{keys}{ResetAttributes} => {description}{:padding$}",
+                SetForegroundColor(config.colors.key),
+                SetAttribute(Attribute::Bold),
+                "",
+            )
+            .expect("writing to a String can't fail");
+        }
+    }
+
+    print!(
+        "{}{:═^term_width$}{}{}{}",
+        cursor::MoveTo(0, term_height.saturating_sub(1 + lines.len()) as u16),
+        " Help ",
+        Clear(ClearType::FromCursorDown),
+        lines
+            .iter()
+            .map(|line| format!("\r\n{line}"))
+            .collect::<String>(),
+        SetForegroundColor(config.colors.foreground),
+    );
+    drop(stdout().flush());
+}
+
+/// Update the state based on a key press.
+fn handle_key_event(event: KeyEvent, state: &mut State, config: &Config) -> Result<()> {
+    // Marking shares its key with expanding by default, and wins where there's something to mark.
+    let action = if matches!(state.view, View::Status | View::LineSelect)
+        && config
+            .keys
+            .keys(Action::Mark)
+            .iter()
+            .any(|key| key.matches(&event))
+    {
+        Some(Action::Mark)
+    } else {
+        config.keys.action(&event)
+    };
+    // Menus and the minibuffer take keys of their own, which may be the same as quitting.
+    if action == Some(Action::Quit)
+        && !matches!(state.view, View::Command(_) | View::Input(..) | View::Help)
+    {
+        capture_mouse(false)?;
+        terminal::disable_raw_mode().context("failed to disable raw mode")?;
+        crossterm::execute!(
+            stdout(),
+            terminal::LeaveAlternateScreen,
+            cursor::Show,
+            cursor::MoveToColumn(0)
+        )
+        .context("failed to leave alternate screen")?;
+        process::exit(0);
+    }
+
+    // These act on what's under the cursor, so a fetch still running in the background is waited
+    // for first, rather than acting on what it's about to replace.
+    if matches!(state.view, View::Status)
+        && matches!(
+            action,
+            Some(
+                Action::Stage
+                    | Action::Unstage
+                    | Action::StageDirectory
+                    | Action::UnstageDirectory
+                    | Action::Discard
+                    | Action::SelectLines
+                    | Action::SplitHunk
+                    | Action::TakeOurs
+                    | Action::TakeTheirs
+            )
+        )
+    {
+        state.status.wait_fetch(&state.repo, &config.options)?;
+    }
+
+    // Going to the top takes its key twice, like vim's `gg`, so it isn't done by accident.
+    let first_pending = std::mem::take(&mut state.first_pending);
+    if action == Some(Action::First)
+        && !first_pending
+        && !matches!(state.view, View::Command(_) | View::Input(..))
+    {
+        state.first_pending = true;
+        return Ok(());
+    }
+
+    match state.view {
+        View::Status => match action {
+            Some(Action::Down) => state.status.down()?,
+            Some(Action::Up) => state.status.up()?,
+            Some(Action::FileDown) => state.status.file_down(),
+            Some(Action::FileUp) => state.status.file_up()?,
+            Some(Action::Last) => state.status.cursor_last(),
+            Some(Action::First) => state.status.cursor_first(),
+            Some(Action::ScrollDown) => state.renderer.scroll(1),
+            Some(Action::ScrollUp) => state.renderer.scroll(-1),
+            Some(Action::HalfPageDown) => move_half_page(state, true)?,
+            Some(Action::HalfPageUp) => move_half_page(state, false)?,
+            Some(Action::Stage) => {
+                if state.status.any_marked()
+                    || state.status.cursor
+                        < state.status.count_untracked + state.status.count_unstaged
+                {
+                    state.status.stage()?;
+                    state.status.fetch();
+                }
+            }
+            Some(Action::StageAll) => {
+                state.status.stage_all()?;
+                state.status.fetch();
+            }
+            Some(Action::Unstage) => {
+                if state.status.any_marked()
+                    || state.status.cursor
+                        >= state.status.count_untracked + state.status.count_unstaged
+                {
+                    state.status.unstage()?;
+                    state.status.fetch();
+                }
+            }
+            Some(Action::UnstageAll) => {
+                state.status.unstage_all()?;
+                state.status.fetch();
+            }
+            Some(Action::Undo) => {
+                state.status.undo()?;
+                state.status.fetch();
+            }
+            Some(Action::StageDirectory) => {
+                state.status.stage_directory()?;
+                state.status.fetch();
+            }
+            Some(Action::UnstageDirectory) => {
+                state.status.unstage_directory()?;
+                state.status.fetch();
+            }
+            Some(Action::Discard) => {
+                let discard = |state: &mut State| {
+                    state.status.discard()?;
+                    state.status.fetch();
+                    Ok(())
+                };
+                if state.status.any_marked()
+                    || state.status.cursor
+                        < state.status.count_untracked + state.status.count_unstaged
+                {
+                    match state.status.discard_prompt() {
+                        Some(prompt) if config!().confirm.discard => {
+                            state.minibuffer.confirm(&prompt, &mut state.view, discard);
                         }
+                        _ => discard(state)?,
                     }
-                    _ => {}
-                },
-                View::BranchList => match event.code {
-                    KeyCode::Char('k') | KeyCode::Up => {
-                        state.branch_list.cursor = state.branch_list.cursor.saturating_sub(1);
-                    }
-                    KeyCode::Char('j') | KeyCode::Down => {
-                        state.branch_list.cursor = cmp::min(
-                            state.branch_list.cursor + 1,
-                            state.branch_list.branches.len() - 1,
-                        );
-                    }
-                    KeyCode::Char('g' | 'K') => state.branch_list.cursor = 0,
-                    KeyCode::Char('G' | 'J') => {
-                        state.branch_list.cursor = state.branch_list.branches.len() - 1;
-                    }
-                    KeyCode::Char(' ') | KeyCode::Enter => {
-                        MiniBuffer::push_command_output(&state.branch_list.checkout()?);
-                        state.status.fetch(&state.repo, &config.options)?;
+                } else if !state.status.file_diffs.is_empty() {
+                    MiniBuffer::push(
+                        "Only unstaged changes can be discarded. Unstage them first.",
+                        MessageType::Error,
+                    );
+                }
+            }
+            Some(Action::SelectLines) => {
+                if state.status.select_lines() {
+                    state.view = View::LineSelect;
+                }
+            }
+            Some(Action::SplitHunk) => state.status.split_hunk()?,
+            Some(Action::TakeOurs) => {
+                state.status.checkout_side("--ours")?;
+                state.status.fetch();
+            }
+            Some(Action::TakeTheirs) => {
+                state.status.checkout_side("--theirs")?;
+                state.status.fetch();
+            }
+            Some(Action::OpenEditor) => {
+                if let Some((path, line)) = state.status.cursor_location() {
+                    open_in_editor(path, line, &config.options)?;
+                    state.status.fetch();
+                }
+            }
+            Some(Action::CopyHunk) => {
+                if let Some(hunk) = state.status.cursor_hunk() {
+                    copy_to_clipboard(&mut state.clipboard, hunk);
+                }
+            }
+            Some(Action::CopyPath) => {
+                if let Some((path, _)) = state.status.cursor_location() {
+                    copy_to_clipboard(&mut state.clipboard, path);
+                }
+            }
+            Some(Action::ExportPatch) => match state.status.cursor_patch() {
+                Some(diff) => state.minibuffer.get_input(
+                    Rc::new(move |path: Option<&str>, _: &mut State| {
+                        let Some(path) = path.map(str::trim).filter(|path| !path.is_empty()) else {
+                            return Ok(());
+                        };
+                        // Rather than overwrite something by mistake, like another patch.
+                        let mut file = fs::OpenOptions::new()
+                            .write(true)
+                            .create_new(true)
+                            .open(worktree_path(path))
+                            .with_context(|| format!("couldn't create {path}"))?;
+                        file.write_all(diff.as_bytes())
+                            .with_context(|| format!("couldn't write to {path}"))?;
+                        MiniBuffer::push(&format!("Saved the patch to {path}."), MessageType::Note);
+                        Ok(())
+                    }),
+                    Some("Save patch to: "),
+                    &mut state.view,
+                ),
+                None if !state.status.file_diffs.is_empty() => MiniBuffer::push(
+                    "There's no diff here to save. Untracked files need staging first.",
+                    MessageType::Error,
+                ),
+                None => {}
+            },
+            Some(Action::PipeHunk) => {
+                if let Some(hunk) = state.status.cursor_hunk() {
+                    state.minibuffer.pipe(hunk.to_string(), &mut state.view);
+                }
+            }
+            Some(Action::MoreContext) => {
+                state.status.more_context();
+                state.status.fetch();
+            }
+            Some(Action::LessContext) => {
+                state.status.less_context();
+                state.status.fetch();
+            }
+            Some(Action::IgnoreWhitespace) => {
+                state.status.toggle_ignore_whitespace();
+                state.status.fetch();
+            }
+            Some(Action::Tree) => state.status.toggle_tree(),
+            Some(Action::SideBySide) => state.status.side_by_side = !state.status.side_by_side,
+            Some(Action::Untracked) => {
+                state.status.toggle_untracked();
+                state.status.fetch();
+            }
+            Some(Action::Expand) => state.status.expand()?,
+            Some(Action::Mark) => state.status.toggle_mark(),
+            Some(Action::CollapseAll) => state.status.collapse_all(),
+            Some(Action::ExpandAll) => state.status.expand_all(),
+            Some(Action::Refresh) => state.status.fetch(),
+            Some(Action::Search) => state.minibuffer.search(&mut state.view),
+            Some(Action::SearchNext) => state.status.search_next(false),
+            Some(Action::Filter) => state
+                .minibuffer
+                .filter(&state.status.filter, &mut state.view),
+            Some(Action::SearchPrevious) => state.status.search_next(true),
+            Some(Action::GitCommand) => state.minibuffer.command(true, &mut state.view),
+            Some(Action::ShellCommand) => state.minibuffer.command(false, &mut state.view),
+            Some(Action::Branch) => state.view = View::Command(GexCommand::Branch),
+            Some(Action::Tag) => {
+                state.tag_target = None;
+                state.view = View::Command(GexCommand::Tag);
+            }
+            Some(Action::Commit) => state.view = View::Command(GexCommand::Commit),
+            Some(Action::Fetch) => state.view = View::Command(GexCommand::Fetch),
+            Some(Action::Pull) => state.view = View::Command(GexCommand::Pull),
+            Some(Action::Push) => state.view = View::Command(GexCommand::Push),
+            Some(Action::Stash) => state.view = View::Command(GexCommand::Stash),
+            Some(Action::Operation) => state.view = View::Command(GexCommand::Operation),
+            Some(Action::ApplyPatch) => state.view = View::Command(GexCommand::Patch),
+            Some(Action::Log) => {
+                state.log.fetch()?;
+                state.view = View::Log;
+            }
+            Some(Action::Worktree) => {
+                state.worktree_list.fetch()?;
+                state.view = View::WorktreeList;
+            }
+            Some(Action::Blame) if state.status.cursor_on_directory() => {
+                MiniBuffer::push("Only files can be blamed.", MessageType::Error);
+            }
+            Some(Action::Blame) => {
+                if let Some((path, line)) = state.status.cursor_location() {
+                    let path = path.to_string();
+                    state.blame.fetch(&path, line.unwrap_or(1))?;
+                    state.view = View::Blame;
+                }
+            }
+            Some(Action::Rebase) => state.minibuffer.get_input(
+                Rc::new(|base: Option<&str>, state: &mut State| {
+                    let Some(base) = base.map(str::trim).filter(|base| !base.is_empty()) else {
+                        return Ok(());
+                    };
+                    command::rebase(state, &[base])
+                }),
+                Some("Rebase interactively onto: "),
+                &mut state.view,
+            ),
+            Some(Action::Help) => state.view = View::Help,
+            // Reverting and cherry-picking are only done from the log.
+            Some(
+                Action::Revert
+                | Action::CherryPick
+                | Action::Reset
+                | Action::Fixup
+                | Action::ApplyStash
+                | Action::PopStash
+                | Action::Quit,
+            )
+            | None => {}
+        },
+        View::LineSelect => match action {
+            Some(Action::Down) => state.status.line_down(),
+            Some(Action::Up) => state.status.line_up(),
+            Some(Action::Expand | Action::Mark) => state.status.toggle_line(),
+            Some(Action::Stage) => {
+                if state.status.cursor < state.status.count_untracked + state.status.count_unstaged
+                {
+                    state.status.stage_lines()?;
+                    state.status.fetch();
+                    state.view = View::Status;
+                }
+            }
+            Some(Action::Unstage) => {
+                if state.status.cursor >= state.status.count_untracked + state.status.count_unstaged
+                {
+                    state.status.unstage_lines()?;
+                    state.status.fetch();
+                    state.view = View::Status;
+                }
+            }
+            Some(Action::SelectLines) => {
+                state.status.clear_line_selection();
+                state.view = View::Status;
+            }
+            Some(Action::OpenEditor) => {
+                if let Some((path, line)) = state.status.cursor_location() {
+                    open_in_editor(path, line, &config.options)?;
+                    state.status.fetch();
+                }
+            }
+            _ if event.code == KeyCode::Esc => {
+                state.status.clear_line_selection();
+                state.view = View::Status;
+            }
+            _ => {}
+        },
+        View::BranchList => match action {
+            Some(Action::Up) => {
+                state.branch_list.cursor = state.branch_list.cursor.saturating_sub(1);
+            }
+            Some(Action::Down) => state.branch_list.down(),
+            Some(Action::First | Action::FileUp) => state.branch_list.cursor = 0,
+            Some(Action::Last | Action::FileDown) => state.branch_list.cursor_last(),
+            Some(Action::Discard) => {
+                let force_delete = |state: &mut State| {
+                    state.branch_list.delete(true)?;
+                    state.branch_list.fetch()
+                };
+                if state.branch_list.delete(false)? {
+                    state.branch_list.fetch()?;
+                } else if let Some(branch) = state
+                    .branch_list
+                    .selected()
+                    .filter(|_| config!().confirm.delete_branch)
+                {
+                    state.minibuffer.confirm(
+                        &format!("{branch} hasn't been merged. Delete it anyway? (y/N) "),
+                        &mut state.view,
+                        force_delete,
+                    );
+                } else {
+                    force_delete(state)?;
+                }
+            }
+            Some(Action::Search) => state.minibuffer.get_input(
+                Rc::new(|name: Option<&str>, state: &mut State| {
+                    let Some(name) = name.map(str::trim).filter(|name| !name.is_empty()) else {
+                        return Ok(());
+                    };
+                    if state.branch_list.contains(name) {
+                        MiniBuffer::push_command_output(&git_process(&["checkout", name])?);
+                        state.status.fetch();
                         state.view = View::Status;
+                        return Ok(());
                     }
-                    KeyCode::Esc => state.view = View::Status,
-                    KeyCode::Char('q') => {
-                        terminal::disable_raw_mode().context("failed to disable raw mode")?;
-                        crossterm::execute!(
-                            stdout(),
-                            terminal::LeaveAlternateScreen,
-                            cursor::Show,
-                            cursor::MoveToColumn(0)
-                        )
-                        .context("failed to leave alternate screen")?;
-                        process::exit(0);
-                    }
-                    _ => {}
-                },
-                View::Command(cmd) => match event.code {
-                    KeyCode::Esc => state.view = View::Status,
-                    KeyCode::Char('q') => {
-                        terminal::disable_raw_mode().context("failed to exit raw mode")?;
-                        crossterm::execute!(
-                            stdout(),
-                            terminal::LeaveAlternateScreen,
-                            cursor::Show,
-                            cursor::MoveToColumn(0)
-                        )
-                        .context("failed to leave alternate screen")?;
-                        process::exit(0);
-                    }
-                    KeyCode::Char(c) => cmd.handle_input(c, &mut state, config)?,
-                    _ => {}
-                },
-                View::Input(ref callback, ref return_view) => {
-                    // This clone should be very cheap as we should never be constructing a
-                    // View::Input with the return view as View::Input.
-                    //
-                    // NOTE: This all indicates there is probably a better way to represent the
-                    // View type, as it never actually needs to be recursive -- then we would also
-                    // be able to just #[derive(Copy)].
-                    debug_assert!(!matches!(**return_view, View::Input(..)));
-                    state.minibuffer.handle_input(
-                        event,
-                        &Rc::clone(callback),
-                        (**return_view).clone(),
+                    let name = name.to_string();
+                    state.minibuffer.confirm(
+                        "There's no branch with that name. Create it? (y/N) ",
                         &mut state.view,
-                    )?;
+                        move |state| {
+                            MiniBuffer::push_command_output(&git_process(&[
+                                "checkout", "-b", &name,
+                            ])?);
+                            state.status.fetch();
+                            state.view = View::Status;
+                            Ok(())
+                        },
+                    );
+                    Ok(())
+                }),
+                Some("Switch to branch: "),
+                &mut state.view,
+            ),
+            _ if action == Some(Action::Expand) || event.code == KeyCode::Enter => {
+                if let Some(output) = state.branch_list.checkout()? {
+                    MiniBuffer::push_command_output(&output);
+                    state.status.fetch();
+                    state.view = View::Status;
                 }
-            };
-            break;
+            }
+            _ if event.code == KeyCode::Esc => state.view = View::Status,
+            _ => {}
+        },
+        View::TagList => match action {
+            Some(Action::Up) => state.tag_list.cursor = state.tag_list.cursor.saturating_sub(1),
+            Some(Action::Down) => state.tag_list.down(),
+            Some(Action::First | Action::FileUp) => state.tag_list.cursor = 0,
+            Some(Action::Last | Action::FileDown) => state.tag_list.cursor_last(),
+            _ if action == Some(Action::Expand) || event.code == KeyCode::Enter => {
+                if let Some(output) = state.tag_list.checkout()? {
+                    MiniBuffer::push_command_output(&output);
+                    state.status.fetch();
+                    state.view = View::Status;
+                }
+            }
+            _ if event.code == KeyCode::Esc => state.view = View::Status,
+            _ => {}
+        },
+        View::Blame => match action {
+            Some(Action::Down) => state.blame.down(),
+            Some(Action::Up) => state.blame.up(),
+            Some(Action::First) => state.blame.cursor_first(),
+            Some(Action::Last) => state.blame.cursor_last(),
+            Some(Action::FileDown) => state.blame.next_commit(),
+            Some(Action::FileUp) => state.blame.previous_commit(),
+            Some(Action::HalfPageDown) => (0..half_page()?).for_each(|_| state.blame.down()),
+            Some(Action::HalfPageUp) => (0..half_page()?).for_each(|_| state.blame.up()),
+            _ if action == Some(Action::Expand) || event.code == KeyCode::Enter => {
+                let Some(hash) = state.blame.selected_hash().map(str::to_string) else {
+                    MiniBuffer::push("This line hasn't been committed yet.", MessageType::Note);
+                    return Ok(());
+                };
+                state.log.fetch()?;
+                if state.log.select(&hash) {
+                    state.log.open(&config.options, state.status.side_by_side)?;
+                    state.view = View::Log;
+                } else {
+                    MiniBuffer::push(
+                        &format!("{} is older than the commits in the log.", &hash[..7]),
+                        MessageType::Note,
+                    );
+                }
+            }
+            _ if action == Some(Action::Blame) || event.code == KeyCode::Esc => {
+                state.view = View::Status;
+            }
+            _ => {}
+        },
+        View::WorktreeList => match action {
+            Some(Action::Down) => state.worktree_list.down(),
+            Some(Action::Up) => state.worktree_list.up(),
+            _ if action == Some(Action::Expand) || event.code == KeyCode::Enter => {
+                if let Some(worktree) = state.worktree_list.selected() {
+                    let path = worktree.path.clone();
+                    switch_worktree(state, &path, config)?;
+                }
+                state.view = View::Status;
+            }
+            _ if action == Some(Action::Worktree) || event.code == KeyCode::Esc => {
+                state.view = View::Status;
+            }
+            _ => {}
+        },
+        View::StashList if state.stash_list.stash.is_some() => match action {
+            Some(Action::Down | Action::ScrollDown) => state.renderer.scroll(1),
+            Some(Action::Up | Action::ScrollUp) => state.renderer.scroll(-1),
+            Some(Action::HalfPageDown) => state.renderer.scroll(half_page()?.cast_signed()),
+            Some(Action::HalfPageUp) => state.renderer.scroll(-half_page()?.cast_signed()),
+            _ if action == Some(Action::Expand) || event.code == KeyCode::Esc => {
+                state.stash_list.stash = None;
+            }
+            _ => {}
+        },
+        View::StashList => match action {
+            Some(Action::Down) => state.stash_list.down(),
+            Some(Action::Up) => state.stash_list.up(),
+            Some(Action::First) => state.stash_list.cursor_first(),
+            Some(Action::Last) => state.stash_list.cursor_last(),
+            Some(Action::HalfPageDown) => (0..half_page()?).for_each(|_| state.stash_list.down()),
+            Some(Action::HalfPageUp) => (0..half_page()?).for_each(|_| state.stash_list.up()),
+            Some(Action::Refresh) => state.stash_list.fetch()?,
+            Some(action @ (Action::ApplyStash | Action::PopStash)) => {
+                let subcommand = if action == Action::ApplyStash {
+                    "apply"
+                } else {
+                    "pop"
+                };
+                state.stash_list.run_on_selected(subcommand)?;
+                state.stash_list.fetch()?;
+                state.status.fetch();
+                // Show the changes that were applied, or the conflicts.
+                state.view = View::Status;
+            }
+            Some(Action::Discard) => {
+                let drop_stash = |state: &mut State| {
+                    state.stash_list.run_on_selected("drop")?;
+                    state.stash_list.fetch()?;
+                    state.status.fetch();
+                    Ok(())
+                };
+                match state.stash_list.selected() {
+                    Some(stash) if config!().confirm.drop_stash => {
+                        state.minibuffer.confirm(
+                            &format!("Drop {}? (y/N) ", stash.name),
+                            &mut state.view,
+                            drop_stash,
+                        );
+                    }
+                    Some(_) => drop_stash(state)?,
+                    None => {}
+                }
+            }
+            _ if action == Some(Action::Expand) || event.code == KeyCode::Enter => {
+                state
+                    .stash_list
+                    .open(&config.options, state.status.side_by_side)?;
+                state.renderer.scroll_to_top();
+            }
+            _ if event.code == KeyCode::Esc => state.view = View::Status,
+            _ => {}
+        },
+        View::Log if state.log.commit.is_some() => match action {
+            Some(Action::Down | Action::ScrollDown) => state.renderer.scroll(1),
+            Some(Action::Up | Action::ScrollUp) => state.renderer.scroll(-1),
+            Some(Action::HalfPageDown) => state.renderer.scroll(half_page()?.cast_signed()),
+            Some(Action::HalfPageUp) => state.renderer.scroll(-half_page()?.cast_signed()),
+            _ if action == Some(Action::Expand) || event.code == KeyCode::Esc => {
+                state.log.commit = None;
+            }
+            _ => {}
+        },
+        View::Log => match action {
+            Some(Action::Down) => state.log.down(),
+            Some(Action::Up) => state.log.up(),
+            Some(Action::First) => state.log.cursor_first(),
+            Some(Action::Last) => state.log.cursor_last(),
+            Some(Action::HalfPageDown) => (0..half_page()?).for_each(|_| state.log.down()),
+            Some(Action::HalfPageUp) => (0..half_page()?).for_each(|_| state.log.up()),
+            Some(Action::Refresh) => state.log.fetch()?,
+            Some(Action::Reset) if !state.log.commits.is_empty() => {
+                state.view = View::Command(GexCommand::Reset);
+            }
+            Some(Action::Fixup) if !state.log.commits.is_empty() => {
+                state.view = View::Command(GexCommand::Fixup);
+            }
+            Some(Action::Rebase) => {
+                if let Some(hash) = state.log.selected_hash().map(str::to_string) {
+                    command::rebase(state, &[&hash])?;
+                }
+            }
+            Some(Action::Tag) if !state.log.commits.is_empty() => {
+                state.tag_target = state.log.selected_hash().map(str::to_string);
+                state.view = View::Command(GexCommand::Tag);
+            }
+            Some(action @ (Action::Revert | Action::CherryPick)) => {
+                let args: &[&str] = if action == Action::Revert {
+                    &["revert", "--no-edit"]
+                } else {
+                    &["cherry-pick"]
+                };
+                let applied = state.log.apply_selected(args)?;
+                state.status.fetch();
+                if applied {
+                    state.log.fetch()?;
+                } else {
+                    // Show the conflicts, and how to continue or abort.
+                    state.view = View::Status;
+                }
+            }
+            _ if action == Some(Action::Expand) || event.code == KeyCode::Enter => {
+                state.log.open(&config.options, state.status.side_by_side)?;
+                state.renderer.scroll_to_top();
+            }
+            _ if action == Some(Action::Log) || event.code == KeyCode::Esc => {
+                state.view = View::Status;
+            }
+            _ => {}
+        },
+        View::Output => match action {
+            Some(Action::Down | Action::ScrollDown) => state.renderer.scroll(1),
+            Some(Action::Up | Action::ScrollUp) => state.renderer.scroll(-1),
+            Some(Action::HalfPageDown) => state.renderer.scroll(half_page()?.cast_signed()),
+            Some(Action::HalfPageUp) => state.renderer.scroll(-half_page()?.cast_signed()),
+            _ if event.code == KeyCode::Esc => state.view = View::Status,
+            _ => {}
+        },
+        View::Help => state.view = View::Status,
+        View::Command(cmd) => match event.code {
+            KeyCode::Esc => {
+                // Skipping hooks is only for a commit made from the menu it was turned on in.
+                if matches!(cmd, GexCommand::Commit) {
+                    state.no_verify = false;
+                }
+                // Menus opened from the log go back to it.
+                state.view = match cmd {
+                    GexCommand::Reset | GexCommand::Fixup => View::Log,
+                    GexCommand::Tag if state.tag_target.take().is_some() => View::Log,
+                    _ => View::Status,
+                };
+            }
+            KeyCode::Char(c) => cmd.handle_input(c, state)?,
+            _ => {}
+        },
+        View::Input(ref callback, ref return_view) => {
+            // This clone should be very cheap as we should never be constructing a
+            // View::Input with the return view as View::Input.
+            //
+            // NOTE: This all indicates there is probably a better way to represent the
+            // View type, as it never actually needs to be recursive -- then we would also
+            // be able to just #[derive(Copy)].
+            debug_assert!(!matches!(**return_view, View::Input(..)));
+            let callback = Rc::clone(callback);
+            let return_view = (**return_view).clone();
+            match state.minibuffer.handle_input(event) {
+                Input::Pending => {}
+                Input::Submitted(input) => {
+                    state.view = return_view;
+                    callback(Some(&input), state)?;
+                }
+                Input::Cancelled => {
+                    state.view = return_view;
+                    callback(None, state)?;
+                }
+            }
         }
     }
+    Ok(())
+}
+
+/// Copies `text` to the system clipboard, or says why it couldn't, e.g. when there's no display
+/// to get a clipboard from over SSH.
+fn copy_to_clipboard(clipboard: &mut Option<Clipboard>, text: &str) {
+    let result = match clipboard {
+        Some(clipboard) => clipboard.set_text(text),
+        None => Clipboard::new().and_then(|c| clipboard.insert(c).set_text(text)),
+    };
+    match result {
+        Ok(()) => MiniBuffer::push("Copied to the clipboard.", MessageType::Note),
+        Err(e) => MiniBuffer::push(
+            &format!("Couldn't copy to the clipboard: {e}"),
+            MessageType::Error,
+        ),
+    }
+}
+
+/// The editor to open files in: `$VISUAL`, then `$EDITOR`, then the one git uses for commit
+/// messages, from `core.editor` or git's default.
+fn editor() -> Option<String> {
+    ["VISUAL", "EDITOR"]
+        .into_iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|editor| !editor.trim().is_empty())
+        .or_else(|| {
+            let output = git_command(&["var", "GIT_EDITOR"]).output().ok()?;
+            output
+                .status
+                .success()
+                .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        })
+}
+
+/// Opens `path` in the user's editor, at `line` if given and `options.editor_line_number` is on.
+/// The terminal is handed over to the editor until it exits.
+fn open_in_editor(path: &str, line: Option<usize>, options: &Options) -> Result<()> {
+    let editor = editor().unwrap_or_default();
+    let mut words = editor.split_whitespace();
+    let Some(program) = words.next() else {
+        MiniBuffer::push(
+            "Set $VISUAL, $EDITOR or core.editor to open files in your editor.",
+            MessageType::Error,
+        );
+        return Ok(());
+    };
+    let mut command = Command::new(program);
+    command.args(words);
+    in_worktree(&mut command);
+    if let Some(line) = line.filter(|_| options.editor_line_number) {
+        command.arg(format!("+{line}"));
+    }
+    command.arg(path);
+
+    capture_mouse(false)?;
+    render::repaint_all();
+    terminal::disable_raw_mode().context("failed to disable raw mode")?;
+    crossterm::execute!(stdout(), terminal::LeaveAlternateScreen, cursor::Show)
+        .context("failed to leave alternate screen")?;
+    let status = command
+        .status()
+        .with_context(|| format!("failed to run `{program}`"));
+    terminal::enable_raw_mode().context("failed to enable raw mode")?;
+    crossterm::execute!(stdout(), terminal::EnterAlternateScreen, cursor::Hide)
+        .context("failed to enter alternate screen")?;
+    capture_mouse(true)?;
+    if !status?.success() {
+        MiniBuffer::push(
+            &format!("`{program}` exited unsuccessfully"),
+            MessageType::Error,
+        );
+    }
+    Ok(())
 }
 
 /// Restore the terminal to its original state from before we messed with it.
@@ -376,6 +1741,7 @@ fn restore_terminal() {
     drop(terminal::disable_raw_mode());
     drop(crossterm::execute!(
         stdout(),
+        DisableMouseCapture,
         terminal::LeaveAlternateScreen,
         cursor::Show,
         cursor::MoveToColumn(0)
@@ -383,8 +1749,5 @@ fn restore_terminal() {
 }
 
 fn main() -> Result<()> {
-    run(&Clargs::parse()).map_err(|e| {
-        restore_terminal();
-        e
-    })
+    run(&Clargs::parse()).inspect_err(|_| restore_terminal())
 }
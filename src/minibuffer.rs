@@ -4,28 +4,33 @@
 use std::{
     env,
     io::{stdout, Write},
-    process::{Command, Output},
+    process::{Command, Output, Stdio},
     rc::Rc,
     str,
     sync::Mutex,
+    thread,
 };
 
 use anyhow::{Context, Result};
 use crossterm::{
     cursor::{self, SetCursorStyle},
     event::{KeyCode, KeyEvent, KeyModifiers},
-    style::SetForegroundColor,
     terminal::{self, ClearType},
 };
-use itertools::Itertools;
+use unicode_width::UnicodeWidthStr;
 
-use crate::{config, git_process, render::Clear, View};
+use crate::{
+    command::git_with_editor,
+    config, git_process, in_worktree,
+    render::{self, Clear, SetForegroundColor},
+    split_subcommand, View,
+};
 
 /// The messages to be sent to the buffer are maintained in this mutex as a stack.
 pub static MESSAGES: Mutex<Vec<(String, MessageType)>> = Mutex::new(Vec::new());
 
-/// The callback type for getting input.
-pub type Callback = Rc<dyn Fn(Option<&str>) -> Result<()>>;
+/// The callback type for getting input. It receives `None` if the input was cancelled.
+pub type Callback = Rc<dyn Fn(Option<&str>, &mut crate::State) -> Result<()>>;
 
 #[derive(PartialEq, Eq, Default)]
 enum State {
@@ -37,8 +42,11 @@ enum State {
 #[derive(Default)]
 enum History {
     #[default]
+    None,
     Command,
     Git,
+    Search,
+    Filter,
 }
 
 #[derive(Default)]
@@ -49,9 +57,13 @@ pub struct MiniBuffer {
     git_command_history: Vec<String>,
     /// History of commands sent via `!`.
     command_history: Vec<String>,
+    /// History of searches made with `/`.
+    search_history: Vec<String>,
+    /// History of filters typed with `&`.
+    filter_history: Vec<String>,
 
     buffer: String,
-    prompt: &'static str,
+    prompt: String,
     cursor: usize,
     history_cursor: usize,
     // Which history to use.
@@ -59,6 +71,14 @@ pub struct MiniBuffer {
     state: State,
 }
 
+/// The result of passing a key event to [`MiniBuffer::handle_input`].
+pub enum Input {
+    /// The user hasn't finished entering their input yet.
+    Pending,
+    Submitted(String),
+    Cancelled,
+}
+
 #[derive(Debug)]
 pub enum MessageType {
     Note,
@@ -85,72 +105,80 @@ impl MiniBuffer {
     }
 
     pub fn push_command_output(output: &Output) {
-        match str::from_utf8(&output.stdout) {
-            Ok(s) => Self::push(s, MessageType::Note),
-            Err(e) => Self::push(
-                &format!("Received invalid UTF8 stdout from git: {e}"),
-                MessageType::Error,
-            ),
+        Self::push(&String::from_utf8_lossy(&output.stdout), MessageType::Note);
+        Self::push(&String::from_utf8_lossy(&output.stderr), MessageType::Error);
+    }
+
+    /// Shows why a command failed, if it did. Its output is otherwise left out, for commands that
+    /// are expected to work quietly.
+    pub fn push_failure(output: &Output) {
+        if output.status.success() {
+            return;
         }
-        match str::from_utf8(&output.stderr) {
-            Ok(s) => Self::push(s, MessageType::Error),
-            Err(e) => Self::push(
-                &format!("Received invalid UTF8 stderr from git: {e}"),
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if stderr.trim().is_empty() {
+            Self::push(
+                &format!("Failed with {}", output.status),
                 MessageType::Error,
-            ),
+            );
+        } else {
+            Self::push(&stderr, MessageType::Error);
         }
     }
 
     /// Get some user input from this minibuffer and run `callback` on it.
-    pub fn get_input(&mut self, callback: Callback, prompt: Option<&'static str>, view: &mut View) {
+    pub fn get_input(&mut self, callback: Callback, prompt: Option<&str>, view: &mut View) {
         self.cursor = 0;
         self.buffer.clear();
         self.history_cursor = 0;
+        self.history = History::None;
         self.state = State::Input;
-        self.prompt = prompt.unwrap_or("");
+        self.prompt = prompt.unwrap_or_default().to_string();
         // This clone should be very cheap as we should never be calling this method while already
         // in View::Input.
         debug_assert!(!matches!(view, View::Input(..)));
         *view = View::Input(callback, Box::new(view.clone()));
     }
 
-    /// `return_view`: the [`View`](crate::View) to switch to after exiting `View::Input`.
+    /// Puts `text` in the input, with the cursor at its end, for input that starts from something
+    /// to edit rather than from nothing.
+    pub fn set_input(&mut self, text: &str) {
+        text.clone_into(&mut self.buffer);
+        self.cursor = self.buffer.len();
+    }
+
+    /// Once the user has finished with the input, the caller is responsible for running the
+    /// [`Callback`].
     ///
     /// # Notes
     ///
     /// Should only be called as part of the main event loop.
-    pub fn handle_input(
-        &mut self,
-        key_event: KeyEvent,
-        callback: &Callback,
-        return_view: View,
-        view: &mut View,
-    ) -> Result<()> {
+    pub fn handle_input(&mut self, key_event: KeyEvent) -> Input {
         let Self {
             ref mut buffer,
             ref mut cursor,
             ref mut history_cursor,
             ..
         } = self;
+        let mut no_history = Vec::new();
         let history = match self.history {
+            History::None => &mut no_history,
             History::Command => &mut self.command_history,
             History::Git => &mut self.git_command_history,
+            History::Search => &mut self.search_history,
+            History::Filter => &mut self.filter_history,
         };
         match (key_event.code, key_event.modifiers) {
             (KeyCode::Enter, _) => {
                 history.push(self.buffer.clone());
-                callback(Some(&self.buffer))?;
                 self.state = State::Normal;
-                self.buffer.clear();
-                *view = return_view;
+                return Input::Submitted(std::mem::take(&mut self.buffer));
             }
             (KeyCode::Left, _) | (KeyCode::Char('b'), KeyModifiers::CONTROL) => {
-                *cursor = cursor.saturating_sub(1);
+                *cursor = prev_boundary(buffer, *cursor);
             }
             (KeyCode::Right, _) | (KeyCode::Char('f'), KeyModifiers::CONTROL) => {
-                if *cursor < buffer.len() {
-                    *cursor += 1;
-                }
+                *cursor = next_boundary(buffer, *cursor);
             }
             (KeyCode::Up, _) | (KeyCode::Char('p'), KeyModifiers::CONTROL) => {
                 if *history_cursor < history.len() {
@@ -172,7 +200,7 @@ impl MiniBuffer {
             (KeyCode::End, _) => *cursor = buffer.len(),
             (KeyCode::Char('b'), KeyModifiers::ALT) => {
                 while *cursor > 0 {
-                    *cursor = cursor.saturating_sub(1);
+                    *cursor = prev_boundary(buffer, *cursor);
                     if word_boundary(buffer, *cursor) {
                         break;
                     }
@@ -180,7 +208,7 @@ impl MiniBuffer {
             }
             (KeyCode::Char('f'), KeyModifiers::ALT) => {
                 while *cursor < buffer.len() {
-                    *cursor += 1;
+                    *cursor = next_boundary(buffer, *cursor);
                     if word_boundary(buffer, *cursor) {
                         break;
                     }
@@ -188,11 +216,11 @@ impl MiniBuffer {
             }
             (KeyCode::Char(c), _) => {
                 buffer.insert(*cursor, c);
-                *cursor += 1;
+                *cursor += c.len_utf8();
             }
             (KeyCode::Backspace, _) => {
                 if *cursor > 0 {
-                    *cursor -= 1;
+                    *cursor = prev_boundary(buffer, *cursor);
                     buffer.remove(*cursor);
                 }
             }
@@ -201,18 +229,17 @@ impl MiniBuffer {
                     buffer.remove(*cursor);
                 } else if !buffer.is_empty() {
                     buffer.pop();
-                    *cursor -= 1;
+                    *cursor = buffer.len();
                 }
             }
             (KeyCode::Esc, _) => {
-                callback(None)?;
                 self.state = State::Normal;
                 self.buffer.clear();
-                *view = return_view;
+                return Input::Cancelled;
             }
             _ => {}
         }
-        Ok(())
+        Input::Pending
     }
 
     /// Get a git command or shell command from the user and execute it.
@@ -222,28 +249,20 @@ impl MiniBuffer {
         } else {
             ("!", History::Command)
         };
-        self.history = history;
         self.get_input(
-            Rc::new(move |cmd: Option<&str>| {
+            Rc::new(move |cmd: Option<&str>, state: &mut crate::State| {
                 crossterm::execute!(stdout(), cursor::MoveToColumn(0))?;
                 terminal::disable_raw_mode().context("failed to disable raw mode")?;
+                render::repaint_all();
                 if let Some(cmd) = cmd {
-                    let cmd_output = if git_cmd {
-                        let output = git_process(&cmd.split_whitespace().collect::<Vec<_>>());
-                        Some(output)
+                    let args = cmd.split_whitespace().collect::<Vec<_>>();
+                    let cmd_output = if git_cmd && needs_terminal(&args) {
+                        git_with_editor(&args).err().map(Err)
+                    } else if git_cmd {
+                        Some(git_process(&args))
                     } else {
-                        // TODO: lazy_static the shell or something.
-                        let output = env::var("SHELL").map_or_else(
-                            |_| {
-                                let mut words = cmd.split_whitespace();
-                                words
-                                    .next()
-                                    .map(|cmd| Command::new(cmd).args(words).output())
-                            },
-                            |sh| Some(Command::new(sh).args(["-c", cmd]).output()),
-                        );
-
-                        output.map(|o| o.context("failed to run command"))
+                        shell_command(cmd)
+                            .map(|mut command| command.output().context("failed to run command"))
                     };
                     match cmd_output {
                         Some(Ok(cmd_output)) => Self::push_command_output(&cmd_output),
@@ -253,8 +272,142 @@ impl MiniBuffer {
                 }
                 terminal::enable_raw_mode().context("failed to enable raw mode")?;
                 print!("{}", cursor::Hide);
+                state.status.fetch();
+                Ok(())
+            }),
+            Some(prompt),
+            view,
+        );
+        self.history = history;
+    }
+
+    /// Get a shell command from the user and run it with `hunk` as its input, e.g. to save it or
+    /// send it somewhere. Its output is shown, or if it has none, how it exited.
+    pub fn pipe(&mut self, hunk: String, view: &mut View) {
+        self.get_input(
+            Rc::new(move |cmd: Option<&str>, state: &mut crate::State| {
+                let Some(cmd) = cmd.filter(|cmd| !cmd.trim().is_empty()) else {
+                    return Ok(());
+                };
+                let Some(mut command) = shell_command(cmd) else {
+                    return Ok(());
+                };
+                crossterm::execute!(stdout(), cursor::MoveToColumn(0))?;
+                terminal::disable_raw_mode().context("failed to disable raw mode")?;
+                render::repaint_all();
+                let output = (|| {
+                    let mut child = command
+                        .stdin(Stdio::piped())
+                        .stdout(Stdio::piped())
+                        .stderr(Stdio::piped())
+                        .spawn()?;
+                    // The hunk is written on another thread, as the command may write output
+                    // before it's read all of it, and would stop once the pipe is full until that
+                    // was read.
+                    let input = format!("{}\n", hunk.trim_end_matches('\n'));
+                    let writer = child.stdin.take().map(|mut stdin| {
+                        thread::spawn(move || {
+                            // Commands are free to stop reading early, like `head` does.
+                            drop(stdin.write_all(input.as_bytes()));
+                        })
+                    });
+                    let output = child.wait_with_output();
+                    if let Some(writer) = writer {
+                        drop(writer.join());
+                    }
+                    output
+                })();
+                terminal::enable_raw_mode().context("failed to enable raw mode")?;
+                print!("{}", cursor::Hide);
+
+                let output = output.with_context(|| format!("failed to run `{cmd}`"))?;
+                if output.stdout.is_empty() && output.stderr.is_empty() {
+                    if output.status.success() {
+                        Self::push(&format!("`{cmd}` finished."), MessageType::Note);
+                    } else {
+                        Self::push(
+                            &format!("`{cmd}` failed with {}", output.status),
+                            MessageType::Error,
+                        );
+                    }
+                } else {
+                    Self::push_command_output(&output);
+                }
+                state.status.fetch();
+                Ok(())
+            }),
+            Some("Pipe hunk to: "),
+            view,
+        );
+        self.history = History::Command;
+    }
+
+    /// Get a search query from the user and jump to the first match. Until it's submitted, the
+    /// query so far is available from [`MiniBuffer::search_query`] so matches can be shown as
+    /// it's typed.
+    pub fn search(&mut self, view: &mut View) {
+        self.get_input(
+            Rc::new(|query: Option<&str>, state: &mut crate::State| {
+                if let Some(query) = query {
+                    state.status.search = query.to_string();
+                    state.status.search_next(false);
+                } else {
+                    state.status.search.clear();
+                }
+                Ok(())
+            }),
+            Some("/"),
+            view,
+        );
+        self.history = History::Search;
+    }
+
+    /// The search query being typed, if there is one.
+    pub fn search_query(&self) -> Option<&str> {
+        (self.state == State::Input && matches!(self.history, History::Search))
+            .then_some(self.buffer.as_str())
+    }
+
+    /// Get a filter from the user, starting from the `current` one, to list only the files whose
+    /// paths contain it. Until it's submitted, the filter so far is available from
+    /// [`MiniBuffer::filter_query`] so the list narrows as it's typed. Cancelling lists every file
+    /// again.
+    pub fn filter(&mut self, current: &str, view: &mut View) {
+        self.get_input(
+            Rc::new(|filter: Option<&str>, state: &mut crate::State| {
+                state.status.set_filter(filter.unwrap_or_default());
                 Ok(())
             }),
+            Some("&"),
+            view,
+        );
+        self.set_input(current);
+        self.history = History::Filter;
+    }
+
+    /// The filter being typed, if there is one.
+    pub fn filter_query(&self) -> Option<&str> {
+        (self.state == State::Input && matches!(self.history, History::Filter))
+            .then_some(self.buffer.as_str())
+    }
+
+    /// Ask the user a yes/no question, running `on_yes` only if they answer yes.
+    pub fn confirm(
+        &mut self,
+        prompt: &str,
+        view: &mut View,
+        on_yes: impl Fn(&mut crate::State) -> Result<()> + 'static,
+    ) {
+        self.get_input(
+            Rc::new(move |input: Option<&str>, state: &mut crate::State| {
+                if input.is_some_and(|input| {
+                    input.eq_ignore_ascii_case("y") || input.eq_ignore_ascii_case("yes")
+                }) {
+                    on_yes(state)
+                } else {
+                    Ok(())
+                }
+            }),
             Some(prompt),
             view,
         );
@@ -275,7 +428,7 @@ impl MiniBuffer {
 
         let (border, prompt) = match self.state {
             State::Normal => ("─", ""),
-            State::Input => ("\u{2574}", self.prompt),
+            State::Input => ("\u{2574}", self.prompt.as_str()),
         };
 
         print!(
@@ -294,13 +447,14 @@ impl MiniBuffer {
         match self.state {
             State::Normal => {
                 terminal::enable_raw_mode().context("failed to enable raw mode")?;
-                self.buffer.clear();
             }
             State::Input => {
                 print!(
                     "{}{}{}",
                     cursor::Show,
-                    cursor::MoveToColumn((self.cursor + prompt.len()) as u16),
+                    cursor::MoveToColumn(
+                        (self.buffer[..self.cursor].width() + prompt.width()) as u16
+                    ),
                     if self.buffer.len() == self.cursor {
                         SetCursorStyle::DefaultUserShape
                     } else {
@@ -314,6 +468,19 @@ impl MiniBuffer {
         Ok(())
     }
 
+    /// How many rows at the bottom of the screen the minibuffer was last drawn over, if it was
+    /// drawn at all.
+    pub fn shown_height(&self) -> Option<usize> {
+        (self.state == State::Input || !self.buffer.is_empty()).then_some(self.current_height)
+    }
+
+    /// Stops showing the current message, if there is one.
+    pub fn clear_message(&mut self) {
+        if self.state == State::Normal {
+            self.buffer.clear();
+        }
+    }
+
     /// Pops the most recent message sent into the minibuffer.
     pub fn pop_message(&mut self) {
         let Some((msg, msg_type)) = MESSAGES.try_lock().expect("couldn't get mutex lock").pop()
@@ -327,14 +494,166 @@ impl MiniBuffer {
     }
 }
 
-/// Checks if idx is on an Emacs-style word boundary in the buffer.
+/// Checks if the byte index idx is on an Emacs-style word boundary in the buffer.
 /// <https://www.gnu.org/software/emacs/manual/html_node/elisp/Syntax-Class-Table.html>
 fn word_boundary(buffer: &str, idx: usize) -> bool {
-    buffer
+    let (before, after) = buffer.split_at(idx);
+    before
         .chars()
-        .tuple_windows()
-        .nth(idx.saturating_sub(1))
-        .map_or(true, |(c1, c2)| {
-            !c1.is_alphanumeric() && c2.is_alphanumeric()
+        .next_back()
+        .zip(after.chars().next())
+        .is_none_or(|(c1, c2)| !c1.is_alphanumeric() && c2.is_alphanumeric())
+}
+
+/// The byte index of the character before the one at `idx` in `buffer`, or 0 at the start.
+fn prev_boundary(buffer: &str, idx: usize) -> usize {
+    buffer[..idx]
+        .char_indices()
+        .next_back()
+        .map_or(0, |(i, _)| i)
+}
+
+/// The byte index of the character after the one at `idx` in `buffer`, or `idx` at the end.
+fn next_boundary(buffer: &str, idx: usize) -> usize {
+    buffer[idx..]
+        .chars()
+        .next()
+        .map_or(idx, |c| idx + c.len_utf8())
+}
+
+/// A `Command` to run `cmd` with `$SHELL`, or if that isn't set, as a program followed by its
+/// arguments. `None` if `cmd` is empty.
+fn shell_command(cmd: &str) -> Option<Command> {
+    // TODO: lazy_static the shell or something.
+    env::var("SHELL").map_or_else(
+        |_| {
+            let mut words = cmd.split_whitespace();
+            words.next().map(|program| {
+                let mut command = Command::new(program);
+                command.args(words);
+                in_worktree(&mut command);
+                command
+            })
+        },
+        |sh| {
+            let mut command = Command::new(sh);
+            command.args(["-c", cmd]);
+            in_worktree(&mut command);
+            Some(command)
+        },
+    )
+}
+
+/// Whether the git command with `args` needs the terminal, because it's interactive or opens the
+/// user's editor for a message.
+fn needs_terminal(args: &[&str]) -> bool {
+    let Some((subcommand, options)) = split_subcommand(args) else {
+        return false;
+    };
+    // Short options can be bundled together, like `-am`.
+    let has = |short: char, long: &str| {
+        options.iter().any(|option| {
+            option.strip_prefix("--").map_or_else(
+                || option.strip_prefix('-').is_some_and(|o| o.contains(short)),
+                |o| o == long || o.starts_with(&format!("{long}=")),
+            )
         })
+    };
+    let has_message = has('m', "message") || has('F', "file");
+    match subcommand {
+        "add" => has('i', "interactive") || has('p', "patch") || has('e', "edit"),
+        "rebase" => has('i', "interactive") || options.contains(&"--continue"),
+        "clean" => has('i', "interactive"),
+        "checkout" | "reset" | "restore" | "stash" => has('p', "patch"),
+        "commit" => {
+            options.contains(&"--interactive")
+                || has('p', "patch")
+                || has('e', "edit")
+                || !(has_message
+                    || has('C', "reuse-message")
+                    || options.contains(&"--no-edit")
+                    || options.iter().any(|o| o.starts_with("--fixup")))
+        }
+        "tag" => (has('a', "annotate") || has('s', "sign")) && !has_message,
+        "merge" | "revert" | "cherry-pick" => has('e', "edit") || options.contains(&"--continue"),
+        "pull" => has('e', "edit"),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+    use test_case::test_case;
+
+    use super::{Input, MiniBuffer};
+
+    #[test_case("", false ; "nothing")]
+    #[test_case("status", false ; "not interactive")]
+    #[test_case("commit", true ; "commit opens the editor")]
+    #[test_case("commit -m x", false ; "commit with a message")]
+    #[test_case("commit -am x", false ; "commit with bundled options")]
+    #[test_case("commit --message=x", false ; "commit with a long message option")]
+    #[test_case("commit -F msg", false ; "commit with a message file")]
+    #[test_case("commit -m x -e", true ; "commit editing the message")]
+    #[test_case("commit --amend --no-edit", false ; "amend without editing")]
+    #[test_case("commit --fixup=HEAD", false ; "fixup")]
+    #[test_case("commit -p -m x", true ; "commit choosing hunks")]
+    #[test_case("rebase main", false ; "rebase")]
+    #[test_case("rebase -i main", true ; "interactive rebase")]
+    #[test_case("rebase --interactive main", true ; "long interactive rebase")]
+    #[test_case("rebase --continue", true ; "continue rebase")]
+    #[test_case("add -p", true ; "add hunks")]
+    #[test_case("add .", false ; "add")]
+    #[test_case("tag -a v1", true ; "annotated tag")]
+    #[test_case("tag -a v1 -m x", false ; "annotated tag with a message")]
+    #[test_case("tag v1", false ; "lightweight tag")]
+    #[test_case("-c core.editor=vim commit", true ; "config before commit")]
+    #[test_case("-c core.editor=vim commit -m x", false ; "config before commit with a message")]
+    #[test_case("-C sub rebase -i main", true ; "directory before interactive rebase")]
+    #[test_case("--no-pager commit", true ; "option before commit")]
+    #[test_case("-c rebase.autosquash=true", false ; "config without a subcommand")]
+    fn needs_terminal(command: &str, expected: bool) {
+        assert_eq!(
+            super::needs_terminal(&command.split_whitespace().collect::<Vec<_>>()),
+            expected
+        );
+    }
+
+    #[test_case(&[KeyCode::Backspace], "caf" ; "backspace")]
+    #[test_case(&[KeyCode::Left, KeyCode::Char('x')], "cafxé" ; "left then type")]
+    #[test_case(&[KeyCode::Left, KeyCode::Right, KeyCode::Char('!')], "café!" ; "left then right")]
+    #[test_case(&[KeyCode::Left, KeyCode::Delete], "caf" ; "delete")]
+    #[test_case(&[KeyCode::Delete], "caf" ; "delete at the end")]
+    #[test_case(&[KeyCode::Home, KeyCode::Char('é')], "écafé" ; "type multibyte at the start")]
+    #[test_case(&[KeyCode::Char('é'), KeyCode::Backspace, KeyCode::Backspace], "caf" ; "backspace after typing")]
+    fn edit_multibyte(keys: &[KeyCode], expected: &str) {
+        let mut minibuffer = MiniBuffer::default();
+        minibuffer.set_input("café");
+        for &key in keys {
+            minibuffer.handle_input(KeyEvent::new(key, KeyModifiers::NONE));
+        }
+        let Input::Submitted(input) =
+            minibuffer.handle_input(KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE))
+        else {
+            panic!("input wasn't submitted");
+        };
+        assert_eq!(input, expected);
+    }
+
+    #[test]
+    fn word_motion_multibyte() {
+        let mut minibuffer = MiniBuffer::default();
+        minibuffer.set_input("naïve café");
+        minibuffer.handle_input(KeyEvent::new(KeyCode::Char('b'), KeyModifiers::ALT));
+        minibuffer.handle_input(KeyEvent::new(KeyCode::Char('b'), KeyModifiers::ALT));
+        minibuffer.handle_input(KeyEvent::new(KeyCode::Char('f'), KeyModifiers::ALT));
+        minibuffer.handle_input(KeyEvent::new(KeyCode::Char('_'), KeyModifiers::NONE));
+        let Input::Submitted(input) =
+            minibuffer.handle_input(KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE))
+        else {
+            panic!("input wasn't submitted");
+        };
+        assert_eq!(input, "naïve _café");
+    }
 }
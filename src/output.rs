@@ -0,0 +1,53 @@
+//! Module for the output view, which shows what a git command said when that's too long for the
+//! minibuffer, like the output of a pre-commit hook that refused a commit.
+
+use std::fmt;
+
+use crate::{
+    config::CONFIG,
+    render::{self, Renderer, ResetAttributes, SetForegroundColor},
+};
+
+#[derive(Default)]
+pub struct CommandOutput {
+    /// What the output is of, e.g. "The commit was refused:".
+    heading: String,
+    text: String,
+}
+
+impl render::Render for CommandOutput {
+    fn render(&self, f: &mut Renderer) -> fmt::Result {
+        use fmt::Write;
+        let config = CONFIG.get().expect("config wasn't initialised");
+
+        // The whole output is the selected item, so that it can be scrolled through.
+        f.insert_cursor();
+        write!(
+            f,
+            "\r{}{}{ResetAttributes}\r\n",
+            SetForegroundColor(config.colors.heading),
+            self.heading,
+        )?;
+        for line in self.text.lines() {
+            write!(f, "\r\n{line}")?;
+        }
+        write!(f, "{ResetAttributes}")?;
+        f.insert_item_end();
+        Ok(())
+    }
+}
+
+impl CommandOutput {
+    pub fn new(heading: &str, text: &str) -> Self {
+        Self {
+            heading: heading.to_string(),
+            text: text.trim_end().to_string(),
+        }
+    }
+
+    /// Whether `text` has more lines than fit in the minibuffer without covering most of a
+    /// terminal `term_height` rows tall, so it's better shown in the output view.
+    pub fn too_long(text: &str, term_height: u16) -> bool {
+        text.trim_end().lines().count() > usize::from(term_height / 2)
+    }
+}
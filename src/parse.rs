@@ -1,32 +1,151 @@
-use std::collections::HashMap;
+use std::{borrow::Cow, collections::HashMap};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use itertools::Itertools;
-use nom::{bytes::complete::tag, character::complete::not_line_ending, IResult};
 
-/// The returned hashmap associates a filename with a `Vec` of `String` where the strings contain
-/// the content of each hunk.
-pub fn parse_diff(input: &str) -> Result<HashMap<&str, Vec<String>>> {
-    let mut diffs = HashMap::new();
-    for diff in input
+/// The diff of a single file.
+#[derive(Debug)]
+pub struct Diff {
+    /// The lines before the first hunk, starting with the `diff --git` line. Needed to turn hunks
+    /// back into patches that `git apply` accepts.
+    pub header: String,
+    /// The content of each hunk, including its `@@` header.
+    pub hunks: Vec<String>,
+    /// Whether git found the file to be binary, in which case there are no hunks.
+    pub binary: bool,
+    /// How similar, in percent, the file is to the one it was renamed or copied from.
+    pub similarity: Option<u8>,
+    /// The path the file was renamed or copied from.
+    pub old_path: Option<String>,
+}
+
+/// The returned hashmap associates a filename with its `Diff`.
+pub fn parse_diff(input: &str) -> Result<HashMap<Cow<'_, str>, Diff>> {
+    // `git diff --cached` notes files with merge conflicts between the diffs of other files.
+    let lines = input
         .lines()
-        .collect::<Vec<_>>()
-        .split(|l| l.starts_with("diff"))
-        .skip(1)
+        .filter(|l| !l.starts_with("* Unmerged path "))
+        .collect::<Vec<_>>();
+    let mut diffs = HashMap::new();
+    for (start, end) in lines
+        .iter()
+        .positions(|l| l.starts_with("diff"))
+        .chain(std::iter::once(lines.len()))
+        .tuple_windows()
     {
-        diffs.insert(get_path(diff)?, get_hunks(diff)?);
+        let diff = &lines[start..end];
+        diffs.insert(
+            get_path(diff),
+            Diff {
+                header: diff.iter().take_while(|l| !l.starts_with("@@")).join("\n"),
+                hunks: get_hunks(diff)?,
+                binary: diff.iter().any(|l| l.starts_with("Binary files ")),
+                similarity: diff
+                    .iter()
+                    .take_while(|l| !l.starts_with("@@"))
+                    .find_map(|l| l.strip_prefix("similarity index "))
+                    .and_then(|s| s.trim_end_matches('%').parse().ok()),
+                old_path: diff
+                    .iter()
+                    .take_while(|l| !l.starts_with("@@"))
+                    .find_map(|l| {
+                        l.strip_prefix("rename from ")
+                            .or_else(|| l.strip_prefix("copy from "))
+                    })
+                    .map(|p| unquote_path(p).into_owned()),
+            },
+        );
     }
     Ok(diffs)
 }
 
-fn get_path<'a>(diff: &[&'a str]) -> Result<&'a str> {
-    let diff: IResult<&str, &str> = tag("+++ b/")(diff.get(2).unwrap_or(&""));
-    let Ok((diff, _)) = diff else { return Ok("") };
-    let path: IResult<&str, &str> = not_line_ending(diff);
-    let (_, path) = path
-        .map_err(|e| e.to_owned())
-        .context("failed to parse a path from diff")?;
-    Ok(path)
+fn get_path<'a>(diff: &[&'a str]) -> Cow<'a, str> {
+    let mut path = Cow::Borrowed("");
+    for line in diff.iter().take_while(|l| !l.starts_with("@@")) {
+        if let Some(new_path) = line
+            .strip_prefix("+++ ")
+            .and_then(|p| strip_path_prefix(p, "b/"))
+        {
+            return new_path;
+        } else if let Some(new_path) = line
+            .strip_prefix("rename to ")
+            .or_else(|| line.strip_prefix("copy to "))
+        {
+            path = unquote_path(new_path);
+        } else if let Some(old_path) = line
+            .strip_prefix("--- ")
+            .and_then(|p| strip_path_prefix(p, "a/"))
+        {
+            // Deleted files have no new path.
+            path = old_path;
+        } else if let Some((old_path, new_path)) = line
+            .strip_prefix("Binary files ")
+            .and_then(|l| l.strip_suffix(" differ"))
+            .and_then(|l| l.rsplit_once(" and "))
+        {
+            // Binary diffs have no `---` and `+++` lines, only "Binary files a/x and b/x differ".
+            if let Some(path) =
+                strip_path_prefix(new_path, "b/").or_else(|| strip_path_prefix(old_path, "a/"))
+            {
+                return path;
+            }
+        }
+    }
+    path
+}
+
+/// Takes a path from a `---` or `+++` line of a diff and removes the `prefix` (`a/` or `b/`).
+/// Returns `None` if it doesn't have the prefix, e.g. for `/dev/null`.
+fn strip_path_prefix<'a>(path: &'a str, prefix: &str) -> Option<Cow<'a, str>> {
+    // git puts a tab after paths containing spaces so that they can be told apart from whatever
+    // comes after.
+    let path = path.strip_suffix('\t').unwrap_or(path);
+    match unquote_path(path) {
+        Cow::Borrowed(path) => path.strip_prefix(prefix).map(Cow::Borrowed),
+        Cow::Owned(path) => path.strip_prefix(prefix).map(|p| Cow::Owned(p.to_string())),
+    }
+}
+
+/// git surrounds paths containing unusual characters with quotes and escapes them like a C
+/// string literal. This undoes that, or leaves the path alone if it isn't quoted.
+/// E.g. "\303\244.txt" --> ä.txt
+pub fn unquote_path(path: &str) -> Cow<'_, str> {
+    let Some(quoted) = path.strip_prefix('"').and_then(|p| p.strip_suffix('"')) else {
+        return Cow::Borrowed(path);
+    };
+
+    let mut bytes = Vec::with_capacity(quoted.len());
+    let mut iter = quoted.bytes();
+    while let Some(b) = iter.next() {
+        if b != b'\\' {
+            bytes.push(b);
+            continue;
+        }
+        match iter.next() {
+            Some(b'a') => bytes.push(0x07),
+            Some(b'b') => bytes.push(0x08),
+            Some(b't') => bytes.push(b'\t'),
+            Some(b'n') => bytes.push(b'\n'),
+            Some(b'v') => bytes.push(0x0b),
+            Some(b'f') => bytes.push(0x0c),
+            Some(b'r') => bytes.push(b'\r'),
+            // Octal escapes are always three digits.
+            Some(d @ b'0'..=b'3') => {
+                let mut value = d - b'0';
+                for _ in 0..2 {
+                    match iter.next() {
+                        Some(d @ b'0'..=b'7') => value = value * 8 + (d - b'0'),
+                        _ => break,
+                    }
+                }
+                bytes.push(value);
+            }
+            // '"' and '\\'.
+            Some(c) => bytes.push(c),
+            None => {}
+        }
+    }
+    Cow::Owned(String::from_utf8_lossy(&bytes).into_owned())
 }
 
 fn get_hunks(diff: &[&str]) -> Result<Vec<String>> {
@@ -79,6 +198,310 @@ pub fn parse_hunk_new(header: &str) -> Result<&str> {
     Ok(old)
 }
 
+/// Gets the start line and line count from the `old` or `new` part of a hunk header. The count
+/// is left out of the header when it's 1.
+/// E.g. "305,6" --> (305, 6), "305" --> (305, 1)
+pub fn parse_hunk_range(range: &str) -> Result<(usize, usize)> {
+    let (start, count) = range.split_once(',').unwrap_or((range, "1"));
+    Ok((
+        start
+            .parse()
+            .with_context(|| format!("strange hunk range: {range}"))?,
+        count
+            .parse()
+            .with_context(|| format!("strange hunk range: {range}"))?,
+    ))
+}
+
+/// Counts the lines added and removed by a hunk, like `git diff --numstat`.
+pub fn count_changes(hunk: &str) -> (usize, usize) {
+    hunk.lines()
+        .skip(1)
+        .fold((0, 0), |(insertions, deletions), line| {
+            match line.chars().next() {
+                Some('+') => (insertions + 1, deletions),
+                Some('-') => (insertions, deletions + 1),
+                _ => (insertions, deletions),
+            }
+        })
+}
+
+/// Gets the old and new line numbers of each line of a hunk after its header. Added lines only
+/// have a new line number and removed lines only an old one.
+pub fn hunk_line_numbers(hunk: &str) -> Result<Vec<(Option<usize>, Option<usize>)>> {
+    let mut lines = hunk.lines();
+    let header = lines.next().context("found an empty hunk")?;
+    if header.starts_with("@@@") {
+        bail!("combined diffs have more than one old side: {header}");
+    }
+    let (mut old, _) = parse_hunk_range(parse_hunk_old(header)?)?;
+    let (mut new, _) = parse_hunk_range(parse_hunk_new(header)?)?;
+    Ok(lines
+        .map(|line| match line.chars().next() {
+            Some('+') => {
+                new += 1;
+                (None, Some(new - 1))
+            }
+            Some('-') => {
+                old += 1;
+                (Some(old - 1), None)
+            }
+            Some('\\') => (None, None),
+            _ => {
+                old += 1;
+                new += 1;
+                (Some(old - 1), Some(new - 1))
+            }
+        })
+        .collect())
+}
+
+/// Gets the commits a submodule was changed from and to, from the hunk of its diff, which is like
+/// "-Subproject commit <old>" and "+Subproject commit <new>". Either is `None` if there wasn't
+/// one, e.g. when the submodule was added. The "-dirty" git adds to the new commit when the
+/// submodule has changes of its own is left off. Returns `None` if it isn't a submodule's hunk.
+pub fn parse_submodule_hunk(hunk: &str) -> Option<(Option<&str>, Option<&str>)> {
+    let mut commits = (None, None);
+    for line in hunk.lines().skip(1) {
+        if let Some(old) = line.strip_prefix("-Subproject commit ") {
+            commits.0 = Some(old);
+        } else if let Some(new) = line.strip_prefix("+Subproject commit ") {
+            commits.1 = Some(new.trim_end_matches("-dirty"));
+        } else {
+            return None;
+        }
+    }
+    (commits != (None, None)).then_some(commits)
+}
+
+/// A file entry from `git status --porcelain=v2`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum StatusEntry<'a> {
+    /// A tracked file that has been changed, with the status codes for the index and the work
+    /// tree, e.g. `M` for modified or `.` for unmodified. `orig_path` is given for renames and
+    /// copies, and `submodule` for submodules.
+    Changed {
+        index: char,
+        worktree: char,
+        /// The file's modes in HEAD, the index and the work tree, and its object names in HEAD
+        /// and the index, as git gave them. These change whenever what's staged does.
+        objects: &'a str,
+        path: &'a str,
+        orig_path: Option<&'a str>,
+        submodule: Option<SubmoduleState>,
+    },
+    /// A file with merge conflicts.
+    Unmerged(&'a str),
+    Untracked(&'a str),
+}
+
+/// Whether a submodule's work tree has changes of its own, from the `S<c><m><u>` field of
+/// `git status --porcelain=v2`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubmoduleState {
+    /// Whether tracked files in the submodule have been changed.
+    pub modified: bool,
+    /// Whether the submodule has untracked files.
+    pub untracked: bool,
+}
+
+/// The parsed output of `git status --porcelain=v2 --branch -z`.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PorcelainStatus<'a> {
+    /// The upstream of the current branch and how far ahead and behind it the branch is. `None` if
+    /// there is no upstream, or if it is gone.
+    pub tracking: Option<(&'a str, usize, usize)>,
+    pub entries: Vec<StatusEntry<'a>>,
+}
+
+/// Parses the output of `git status --porcelain=v2 --branch -z`.
+/// <https://git-scm.com/docs/git-status#_porcelain_format_version_2>
+pub fn parse_status(input: &str) -> Result<PorcelainStatus<'_>> {
+    let mut status = PorcelainStatus::default();
+    let mut upstream = None;
+    let mut records = input.split('\0').filter(|r| !r.is_empty());
+    while let Some(record) = records.next() {
+        let (kind, rest) = record
+            .split_once(' ')
+            .with_context(|| format!("strange `git status` record: {record}"))?;
+        match kind {
+            "#" => {
+                if let Some(name) = rest.strip_prefix("branch.upstream ") {
+                    upstream = Some(name);
+                } else if let Some(ab) = rest.strip_prefix("branch.ab ") {
+                    // +<ahead> -<behind>
+                    let (ahead, behind) = ab
+                        .split_once(' ')
+                        .with_context(|| format!("strange `git status` record: {record}"))?;
+                    status.tracking = Some((
+                        upstream.context("got ahead/behind counts without an upstream")?,
+                        ahead.trim_start_matches('+').parse()?,
+                        behind.trim_start_matches('-').parse()?,
+                    ));
+                }
+            }
+            // 1 <XY> <sub> <mH> <mI> <mW> <hH> <hI> <path>
+            // 2 <XY> <sub> <mH> <mI> <mW> <hH> <hI> <X><score> <path>\0<origPath>
+            "1" | "2" => {
+                let mut fields = rest.splitn(3, ' ');
+                let mut xy = fields.next().unwrap_or_default().chars();
+                // `N...` for files, or `S<c><m><u>` for submodules.
+                let submodule = fields
+                    .next()
+                    .and_then(|sub| sub.strip_prefix('S'))
+                    .map(|sub| SubmoduleState {
+                        modified: sub.chars().nth(1) == Some('M'),
+                        untracked: sub.chars().nth(2) == Some('U'),
+                    });
+                // The modes and object names are the five fields before the path.
+                let rest = fields.next().unwrap_or_default();
+                let objects_end = rest.match_indices(' ').nth(4).map(|(i, _)| i);
+                let (Some(index), Some(worktree), Some(objects_end)) =
+                    (xy.next(), xy.next(), objects_end)
+                else {
+                    bail!("strange `git status` record: {record}");
+                };
+                let objects = &rest[..objects_end];
+                let path = &rest[objects_end + 1..];
+                // Renames and copies have their score before the path.
+                let path = if kind == "2" {
+                    path.split_once(' ')
+                        .with_context(|| format!("strange `git status` record: {record}"))?
+                        .1
+                } else {
+                    path
+                };
+                let orig_path = if kind == "2" {
+                    Some(
+                        records
+                            .next()
+                            .context("expected the original path of a rename")?,
+                    )
+                } else {
+                    None
+                };
+                status.entries.push(StatusEntry::Changed {
+                    index,
+                    worktree,
+                    objects,
+                    path,
+                    orig_path,
+                    submodule,
+                });
+            }
+            // u <XY> <sub> <m1> <m2> <m3> <mW> <h1> <h2> <h3> <path>
+            "u" => status.entries.push(StatusEntry::Unmerged(
+                rest.splitn(10, ' ')
+                    .nth(9)
+                    .with_context(|| format!("strange `git status` record: {record}"))?,
+            )),
+            "?" => status.entries.push(StatusEntry::Untracked(rest)),
+            // Ignored files, or something from a newer version of git.
+            _ => {}
+        }
+    }
+    Ok(status)
+}
+
+/// A commit from `git log --graph`, with the graph drawn beside it.
+#[derive(Debug, PartialEq, Eq)]
+pub struct GraphCommit<'a> {
+    /// The rails on the commit's own line, e.g. "| * ".
+    pub graph: &'a str,
+    /// What the format gave for the commit, split at the NULs between its fields.
+    pub fields: Vec<&'a str>,
+    /// The lines of only rails that come after the commit, where branches fork or merge, e.g. "|/".
+    pub connectors: Vec<&'a str>,
+}
+
+/// Parses the output of `git log --graph` with a format that starts with a NUL and separates its
+/// fields with them, like `--format=%x00%h%x00%s`, so the graph can be told apart from the
+/// commit on each line.
+pub fn parse_log_graph(input: &str) -> Vec<GraphCommit<'_>> {
+    let mut commits: Vec<GraphCommit> = Vec::new();
+    for line in input.lines() {
+        if let Some((graph, fields)) = line.split_once('\0') {
+            commits.push(GraphCommit {
+                graph,
+                fields: fields.split('\0').collect(),
+                connectors: Vec::new(),
+            });
+        } else if let Some(commit) = commits.last_mut() {
+            commit.connectors.push(line.trim_end());
+        }
+    }
+    commits
+}
+
+/// A line of a file, along with the commit that last changed it.
+#[derive(Debug, PartialEq, Eq)]
+pub struct BlameLine<'a> {
+    /// The full hash of the commit, or all zeros if the line has changed since the last commit.
+    pub hash: &'a str,
+    pub author: &'a str,
+    /// The day the commit was authored, in the author's time zone, like "2023-10-14".
+    pub date: String,
+    pub content: &'a str,
+}
+
+/// Parses the output of `git blame --line-porcelain`, where each line of the file comes after a
+/// header naming the commit it's from, then describing that commit a field per line.
+/// <https://git-scm.com/docs/git-blame#_the_porcelain_format>
+pub fn parse_blame(input: &str) -> Vec<BlameLine<'_>> {
+    let mut lines = Vec::new();
+    let (mut hash, mut author, mut time, mut tz) = ("", "", 0, "+0000");
+    for line in input.lines() {
+        if let Some(content) = line.strip_prefix('\t') {
+            lines.push(BlameLine {
+                hash,
+                author,
+                date: format_date(time, tz),
+                content,
+            });
+        } else if let Some(name) = line.strip_prefix("author ") {
+            author = name;
+        } else if let Some(t) = line.strip_prefix("author-time ") {
+            time = t.parse().unwrap_or_default();
+        } else if let Some(t) = line.strip_prefix("author-tz ") {
+            tz = t;
+        } else if let Some(first) = line.split(' ').next() {
+            // <hash> <line in the commit> <line in the file> [<lines from this commit>]
+            if first.len() >= 40 && first.bytes().all(|b| b.is_ascii_hexdigit()) {
+                hash = first;
+            }
+        }
+    }
+    lines
+}
+
+/// Formats the Unix `time` as the day it is in the time zone `tz`, like "+0100", as "YYYY-MM-DD".
+fn format_date(time: i64, tz: &str) -> String {
+    let sign = if tz.starts_with('-') { -1 } else { 1 };
+    let offset = tz
+        .get(1..3)
+        .zip(tz.get(3..5))
+        .map_or(0, |(hours, minutes)| {
+            sign * (hours.parse::<i64>().unwrap_or_default() * 3600
+                + minutes.parse::<i64>().unwrap_or_default() * 60)
+        });
+    // Howard Hinnant's `civil_from_days`, counting in 400 year eras starting on 0000-03-01.
+    let days = (time + offset).div_euclid(86400) + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
 #[cfg(test)]
 mod tests {
     use test_case::test_case;
@@ -119,4 +542,354 @@ index d79df71..e2d1e9f 100644
         let parsed = parsed.unwrap();
         assert_eq!(parsed.len(), 1);
     }
+
+    #[test_case("diff --git a/my report.txt b/my report.txt
+index 587be6b..ae45b0e 100644
+--- a/my report.txt	
++++ b/my report.txt	
+@@ -1 +1,2 @@
+ x
++y", "my report.txt" ; "path with spaces")]
+    #[test_case("diff --git a/new.txt b/new.txt
+new file mode 100644
+index 0000000..587be6b
+--- /dev/null
++++ b/new.txt
+@@ -0,0 +1 @@
++x", "new.txt" ; "new file")]
+    #[test_case("diff --git a/old.txt b/old.txt
+deleted file mode 100644
+index 587be6b..0000000
+--- a/old.txt
++++ /dev/null
+@@ -1 +0,0 @@
+-x", "old.txt" ; "deleted file")]
+    #[test_case("diff --git \"a/\\303\\244.txt\" \"b/\\303\\244.txt\"
+index 587be6b..b77b4eb 100644
+--- \"a/\\303\\244.txt\"
++++ \"b/\\303\\244.txt\"
+@@ -1 +1,2 @@
+ x
++y", "ä.txt" ; "quoted path")]
+    #[test_case("diff --cc c.txt
+index 3b6f40a,f4ea702..0000000
+--- a/c.txt
++++ b/c.txt
+@@@ -1,3 -1,3 +1,7 @@@
+  a
+++<<<<<<< HEAD
+ +B2
+++=======
++ B1
+++>>>>>>> other
+  c", "c.txt" ; "merge conflict")]
+    fn path(diff: &str, path: &str) {
+        let parsed = super::parse_diff(diff).unwrap();
+        assert_eq!(parsed.keys().collect::<Vec<_>>(), [path]);
+        assert_eq!(parsed[path].hunks.len(), 1);
+    }
+
+    #[test_case("diff --git a/image.png b/image.png
+index 1b2c3d4..5e6f7a8 100644
+Binary files a/image.png and b/image.png differ", "image.png" ; "modified")]
+    #[test_case("diff --git a/new image.png b/new image.png
+new file mode 100644
+index 0000000..5e6f7a8
+Binary files /dev/null and b/new image.png differ", "new image.png" ; "new file")]
+    #[test_case("diff --git a/old.png b/old.png
+deleted file mode 100644
+index 1b2c3d4..0000000
+Binary files a/old.png and /dev/null differ", "old.png" ; "deleted file")]
+    fn binary(diff: &str, path: &str) {
+        let parsed = super::parse_diff(diff).unwrap();
+        assert_eq!(parsed.keys().collect::<Vec<_>>(), [path]);
+        assert!(parsed[path].binary);
+        assert!(parsed[path].hunks.is_empty());
+    }
+
+    #[test]
+    fn header() {
+        let parsed = super::parse_diff(ISSUE_62).unwrap();
+        assert_eq!(
+            parsed["asteroid-loop/index.html"].header,
+            "diff --git a/asteroid-loop/index.html b/asteroid-loop/index.html
+index d79df71..e2d1e9f 100644
+--- a/asteroid-loop/index.html
++++ b/asteroid-loop/index.html"
+        );
+        assert_eq!(parsed["asteroid-loop/index.html"].hunks.len(), 2);
+    }
+
+    #[test_case("diff --git a/old.txt b/new.txt
+similarity index 86%
+rename from old.txt
+rename to new.txt
+index 587be6b..ae45b0e 100644
+--- a/old.txt
++++ b/new.txt
+@@ -1 +1,2 @@
+ x
++y", Some(86) ; "renamed")]
+    #[test_case("diff --git a/old.txt b/new.txt
+similarity index 100%
+rename from old.txt
+rename to new.txt", Some(100) ; "renamed unchanged")]
+    #[test_case("diff --git a/a.txt b/a.txt
+index 587be6b..ae45b0e 100644
+--- a/a.txt
++++ b/a.txt
+@@ -1 +1,2 @@
+ x
++similarity index 50%", None ; "not renamed")]
+    fn similarity(diff: &str, similarity: Option<u8>) {
+        let parsed = super::parse_diff(diff).unwrap();
+        assert_eq!(parsed.values().next().unwrap().similarity, similarity);
+    }
+
+    #[test_case("diff --git a/old.txt b/new.txt
+similarity index 100%
+rename from old.txt
+rename to new.txt", "new.txt", Some("old.txt") ; "renamed")]
+    #[test_case("diff --git a/a.txt b/b.txt
+similarity index 90%
+copy from a.txt
+copy to b.txt", "b.txt", Some("a.txt") ; "copied")]
+    #[test_case("diff --git \"a/\\303\\244.txt\" b/a.txt
+similarity index 100%
+rename from \"\\303\\244.txt\"
+rename to a.txt", "a.txt", Some("ä.txt") ; "quoted")]
+    #[test_case("diff --git a/a.txt b/a.txt
+index 587be6b..ae45b0e 100644
+--- a/a.txt
++++ b/a.txt
+@@ -1 +1,2 @@
+ x
++rename from b.txt", "a.txt", None ; "not renamed")]
+    fn old_path(diff: &str, path: &str, old_path: Option<&str>) {
+        let parsed = super::parse_diff(diff).unwrap();
+        assert_eq!(parsed[path].old_path.as_deref(), old_path);
+    }
+
+    #[test]
+    fn unmerged_path() {
+        let parsed = super::parse_diff(
+            "diff --git a/a.txt b/a.txt
+index 587be6b..ae45b0e 100644
+--- a/a.txt
++++ b/a.txt
+@@ -1 +1,2 @@
+ x
++y
+* Unmerged path c.txt",
+        )
+        .unwrap();
+        assert_eq!(parsed["a.txt"].hunks, ["@@ -1 +1,2 @@\n x\n+y"]);
+    }
+
+    #[test_case("305,6", (305, 6) ; "with count")]
+    #[test_case("305", (305, 1) ; "without count")]
+    #[test_case("0,0", (0, 0) ; "empty")]
+    fn hunk_range(range: &str, expected: (usize, usize)) {
+        assert_eq!(super::parse_hunk_range(range).unwrap(), expected);
+    }
+
+    #[test_case("@@ -1,2 +1,3 @@\n a\n-b\n+c\n+d", (2, 1) ; "mixed")]
+    #[test_case("@@ -0,0 +1 @@\n+x\n\\ No newline at end of file", (1, 0) ; "no newline")]
+    #[test_case("@@ -1,3 +1,3 @@ fn main() {\n a\n b\n c", (0, 0) ; "context only")]
+    fn count_changes(hunk: &str, expected: (usize, usize)) {
+        assert_eq!(super::count_changes(hunk), expected);
+    }
+
+    #[test_case("@@ -4,3 +4,3 @@\n a\n-b\n+c\n d", &[(Some(4), Some(4)), (Some(5), None), (None, Some(5)), (Some(6), Some(6))] ; "mixed")]
+    #[test_case("@@ -0,0 +1 @@\n+x\n\\ No newline at end of file", &[(None, Some(1)), (None, None)] ; "no newline")]
+    fn hunk_line_numbers(hunk: &str, expected: &[(Option<usize>, Option<usize>)]) {
+        assert_eq!(super::hunk_line_numbers(hunk).unwrap(), expected);
+    }
+
+    #[test_case("@@ -1 +1 @@\n-Subproject commit 3f4b5a6\n+Subproject commit e69de29", Some((Some("3f4b5a6"), Some("e69de29"))) ; "changed")]
+    #[test_case("@@ -0,0 +1 @@\n+Subproject commit e69de29", Some((None, Some("e69de29"))) ; "added")]
+    #[test_case("@@ -1 +1 @@\n-Subproject commit 3f4b5a6\n+Subproject commit 3f4b5a6-dirty", Some((Some("3f4b5a6"), Some("3f4b5a6"))) ; "dirty")]
+    #[test_case("@@ -1 +1 @@\n-Subproject commit 3f4b5a6\n+x", None ; "not a submodule")]
+    fn submodule_hunk(hunk: &str, expected: Option<(Option<&str>, Option<&str>)>) {
+        assert_eq!(super::parse_submodule_hunk(hunk), expected);
+    }
+
+    #[test]
+    fn porcelain_status() {
+        use super::{PorcelainStatus, StatusEntry, SubmoduleState};
+        const INPUT: &str = "# branch.oid 8be5290c64d8b8d1e8a1e80a6a7e1a1f54e5bd4a\0\
+# branch.head main\0\
+# branch.upstream origin/main\0\
+# branch.ab +2 -1\0\
+1 .M N... 100644 100644 100644 3f4b5a6 3f4b5a6 src/main.rs\0\
+1 A. N... 000000 100644 100644 0000000 e69de29 my report.txt\0\
+1 .M S.MU 160000 160000 160000 3f4b5a6 3f4b5a6 lib/sub\0\
+2 R. N... 100644 100644 100644 e69de29 e69de29 R100 new name.rs\0old name.rs\0\
+u UU N... 100644 100644 100644 100644 e69de29 e69de29 e69de29 conflict.rs\0\
+? untracked file\0";
+        assert_eq!(
+            super::parse_status(INPUT).unwrap(),
+            PorcelainStatus {
+                tracking: Some(("origin/main", 2, 1)),
+                entries: vec![
+                    StatusEntry::Changed {
+                        index: '.',
+                        worktree: 'M',
+                        objects: "100644 100644 100644 3f4b5a6 3f4b5a6",
+                        path: "src/main.rs",
+                        orig_path: None,
+                        submodule: None,
+                    },
+                    StatusEntry::Changed {
+                        index: 'A',
+                        worktree: '.',
+                        objects: "000000 100644 100644 0000000 e69de29",
+                        path: "my report.txt",
+                        orig_path: None,
+                        submodule: None,
+                    },
+                    StatusEntry::Changed {
+                        index: '.',
+                        worktree: 'M',
+                        objects: "160000 160000 160000 3f4b5a6 3f4b5a6",
+                        path: "lib/sub",
+                        orig_path: None,
+                        submodule: Some(SubmoduleState {
+                            modified: true,
+                            untracked: true,
+                        }),
+                    },
+                    StatusEntry::Changed {
+                        index: 'R',
+                        worktree: '.',
+                        objects: "100644 100644 100644 e69de29 e69de29",
+                        path: "new name.rs",
+                        orig_path: Some("old name.rs"),
+                        submodule: None,
+                    },
+                    StatusEntry::Unmerged("conflict.rs"),
+                    StatusEntry::Untracked("untracked file"),
+                ]
+            }
+        );
+    }
+
+    #[test_case("# branch.oid (initial)\0# branch.head main\0" ; "no upstream")]
+    #[test_case("# branch.oid (initial)\0# branch.head main\0# branch.upstream origin/main\0" ; "upstream gone")]
+    fn porcelain_no_tracking(input: &str) {
+        assert_eq!(super::parse_status(input).unwrap().tracking, None);
+    }
+
+    #[test]
+    fn log_graph() {
+        use super::GraphCommit;
+        const INPUT: &str = "\
+*   \0e3a1b2c\0Merge branch 'feature'
+|\\  
+| * \0d4c3b2a\0Add the feature
+* | \0c2b1a09\0Fix a typo
+|/  
+* \0b1a0987\0First commit
+";
+        assert_eq!(
+            super::parse_log_graph(INPUT),
+            [
+                GraphCommit {
+                    graph: "*   ",
+                    fields: vec!["e3a1b2c", "Merge branch 'feature'"],
+                    connectors: vec!["|\\"],
+                },
+                GraphCommit {
+                    graph: "| * ",
+                    fields: vec!["d4c3b2a", "Add the feature"],
+                    connectors: vec![],
+                },
+                GraphCommit {
+                    graph: "* | ",
+                    fields: vec!["c2b1a09", "Fix a typo"],
+                    connectors: vec!["|/"],
+                },
+                GraphCommit {
+                    graph: "* ",
+                    fields: vec!["b1a0987", "First commit"],
+                    connectors: vec![],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn blame() {
+        use super::BlameLine;
+        const INPUT: &str = "\
+8be5290c64d8b8d1e8a1e80a6a7e1a1f54e5bd4a 1 1 2
+author Ada Lovelace
+author-mail <ada@example.com>
+author-time 1697241600
+author-tz +0100
+committer Ada Lovelace
+summary Add the engine
+filename engine.rs
+\tfn main() {
+8be5290c64d8b8d1e8a1e80a6a7e1a1f54e5bd4a 2 2
+author Ada Lovelace
+author-mail <ada@example.com>
+author-time 1697241600
+author-tz +0100
+committer Ada Lovelace
+summary Add the engine
+filename engine.rs
+\t\tcompute();
+0000000000000000000000000000000000000000 3 3 1
+author Not Committed Yet
+author-mail <not.committed.yet>
+author-time 1697328000
+author-tz -0500
+committer Not Committed Yet
+summary Version of engine.rs from engine.rs
+filename engine.rs
+\t}
+";
+        assert_eq!(
+            super::parse_blame(INPUT),
+            [
+                BlameLine {
+                    hash: "8be5290c64d8b8d1e8a1e80a6a7e1a1f54e5bd4a",
+                    author: "Ada Lovelace",
+                    date: "2023-10-14".to_string(),
+                    content: "fn main() {",
+                },
+                BlameLine {
+                    hash: "8be5290c64d8b8d1e8a1e80a6a7e1a1f54e5bd4a",
+                    author: "Ada Lovelace",
+                    date: "2023-10-14".to_string(),
+                    content: "\tcompute();",
+                },
+                BlameLine {
+                    hash: "0000000000000000000000000000000000000000",
+                    author: "Not Committed Yet",
+                    date: "2023-10-14".to_string(),
+                    content: "}",
+                },
+            ]
+        );
+    }
+
+    #[test_case(0, "+0000", "1970-01-01" ; "epoch")]
+    #[test_case(951_782_400, "+0000", "2000-02-29" ; "leap day")]
+    #[test_case(1_704_067_199, "+0000", "2023-12-31" ; "end of year")]
+    #[test_case(1_704_067_199, "+0100", "2024-01-01" ; "ahead of utc")]
+    #[test_case(1_704_067_200, "-0130", "2023-12-31" ; "behind utc")]
+    fn format_date(time: i64, tz: &str, expected: &str) {
+        assert_eq!(super::format_date(time, tz), expected);
+    }
+
+    #[test_case("src/main.rs", "src/main.rs" ; "unquoted")]
+    #[test_case("\"\\303\\244.txt\"", "ä.txt" ; "octal")]
+    #[test_case("\"tab\\there\"", "tab\there" ; "tab")]
+    #[test_case("\"new\\nline\"", "new\nline" ; "newline")]
+    #[test_case("\"\\\"quoted\\\" \\\\\"", "\"quoted\" \\" ; "quotes and backslashes")]
+    fn unquote_path(quoted: &str, path: &str) {
+        assert_eq!(super::unquote_path(quoted), path);
+    }
 }
@@ -0,0 +1,393 @@
+//! Module for building patches out of parts of a diff and applying them, so that single hunks or
+//! lines can be staged.
+
+use std::{
+    io::Write,
+    ops::Range,
+    process::{Output, Stdio},
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    git_command,
+    parse::{parse_hunk_new, parse_hunk_old, parse_hunk_range},
+    record_command,
+};
+
+/// Builds a hunk containing only the changed lines of `hunk` for which `selected` returns true,
+/// given the index of the line counting from the line after the `@@` header.
+///
+/// Unselected changes are dropped if they aren't on the side the patch applies to, or kept as
+/// context if they are. When `reverse` is set, the patch is built to be applied with `--reverse`,
+/// i.e. to take changes back out of the index.
+pub fn select_lines(hunk: &str, selected: impl Fn(usize) -> bool, reverse: bool) -> Result<String> {
+    let mut lines = hunk.lines();
+    let head = lines.next().context("found an empty hunk")?;
+    let (old_start, old_count) = parse_hunk_range(parse_hunk_old(head)?)?;
+    let (new_start, new_count) = parse_hunk_range(parse_hunk_new(head)?)?;
+
+    let mut body = String::new();
+    let (mut old, mut new) = (0, 0);
+    let mut any_selected = false;
+    // Whether the previous line was dropped, in which case a following "\ No newline at end of
+    // file" has to go too.
+    let mut dropped = false;
+    for (i, line) in lines.enumerate() {
+        let line = match line.chars().next() {
+            Some(c @ ('+' | '-')) if selected(i) => {
+                any_selected = true;
+                if c == '+' {
+                    new += 1;
+                } else {
+                    old += 1;
+                }
+                Some(line)
+            }
+            Some('+') if !reverse => None,
+            Some('-') if reverse => None,
+            Some('+' | '-') => {
+                old += 1;
+                new += 1;
+                body.push(' ');
+                Some(&line[1..])
+            }
+            Some('\\') => (!dropped).then_some(line),
+            _ => {
+                old += 1;
+                new += 1;
+                Some(line)
+            }
+        };
+        if let Some(line) = line {
+            body.push_str(line);
+            body.push('\n');
+        }
+        dropped = line.is_none();
+    }
+
+    if !any_selected {
+        bail!("no changed lines selected");
+    }
+
+    Ok(format!(
+        "@@ -{},{old} +{},{new} @@\n{body}",
+        start(old_start, old_count, old),
+        start(new_start, new_count, new),
+    ))
+}
+
+/// Splits `hunk` into a hunk for each run of changed lines in it, like `git add -p` does, so that
+/// they can be staged separately. The context between two runs goes in the hunks on both sides of
+/// it. A hunk with only one run of changes comes back as it is.
+pub fn split(hunk: &str) -> Result<Vec<String>> {
+    let mut lines = hunk.lines();
+    let head = lines.next().context("found an empty hunk")?;
+    let (old_start, _) = parse_hunk_range(parse_hunk_old(head)?)?;
+    let (new_start, _) = parse_hunk_range(parse_hunk_new(head)?)?;
+    // Whatever follows the ranges, usually the function the hunk is in.
+    let heading = head.splitn(3, "@@").nth(2).unwrap_or_default();
+    let lines: Vec<&str> = lines.collect();
+
+    // A "\ No newline at end of file" belongs with the line before it.
+    let mut runs: Vec<Range<usize>> = Vec::new();
+    let mut in_run = false;
+    for (i, line) in lines.iter().enumerate() {
+        in_run = match line.chars().next() {
+            Some('+' | '-') => true,
+            Some('\\') => in_run,
+            _ => false,
+        };
+        if in_run {
+            match runs.last_mut() {
+                Some(run) if run.end == i => run.end = i + 1,
+                _ => runs.push(i..i + 1),
+            }
+        }
+    }
+    if runs.len() < 2 {
+        return Ok(vec![hunk.to_string()]);
+    }
+
+    let old_lines = |lines: &[&str]| {
+        lines
+            .iter()
+            .filter(|l| !l.starts_with('+') && !l.starts_with('\\'))
+            .count()
+    };
+    let new_lines = |lines: &[&str]| {
+        lines
+            .iter()
+            .filter(|l| !l.starts_with('-') && !l.starts_with('\\'))
+            .count()
+    };
+    Ok((0..runs.len())
+        .map(|i| {
+            let start = i.checked_sub(1).map_or(0, |prev| runs[prev].end);
+            let end = runs.get(i + 1).map_or(lines.len(), |next| next.start);
+            let (before, body) = (&lines[..start], &lines[start..end]);
+            let mut hunk = format!(
+                "@@ -{},{} +{},{} @@{heading}\n",
+                old_start + old_lines(before),
+                old_lines(body),
+                new_start + new_lines(before),
+                new_lines(body),
+            );
+            for line in body {
+                hunk.push_str(line);
+                hunk.push('\n');
+            }
+            hunk
+        })
+        .collect())
+}
+
+/// Gets the start line for one side of a hunk whose line count has changed from `count` to
+/// `new_count`. An empty side of a hunk starts at the line before where it would be.
+const fn start(start: usize, count: usize, new_count: usize) -> usize {
+    let first_line = if count == 0 { start + 1 } else { start };
+    if new_count == 0 {
+        first_line - 1
+    } else {
+        first_line
+    }
+}
+
+/// The hunks git gave for a file, from its `hunks` each paired with the hunk it was split from by
+/// [`split`], if it was. The pieces of a split hunk overlap in their context, so they can't be put
+/// in a patch together, but the hunk they came from can.
+pub fn unsplit<'a>(hunks: impl IntoIterator<Item = (&'a str, Option<&'a str>)>) -> Vec<&'a str> {
+    let mut hunks: Vec<_> = hunks
+        .into_iter()
+        .map(|(hunk, unsplit)| unsplit.unwrap_or(hunk))
+        .collect();
+    // The pieces of a split hunk are next to each other.
+    hunks.dedup();
+    hunks
+}
+
+/// Builds a patch out of a file's diff `header` and some of its `hunks`, which can be applied
+/// with `git apply`.
+pub fn build(header: &str, hunks: &[&str]) -> String {
+    let mut patch = format!("{}\n", header.trim_end_matches('\n'));
+    for hunk in hunks {
+        patch.push_str(hunk.trim_end_matches('\n'));
+        patch.push('\n');
+    }
+    patch
+}
+
+/// Runs `git apply` with `args` on the patch made from a file's diff `header` and a `hunk`.
+pub fn apply(header: &str, hunk: &str, args: &[&str]) -> Result<Output> {
+    let args = [&["apply"], args, &["-"]].concat();
+    let mut child = git_command(&args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("failed to run `git apply`")?;
+
+    let mut stdin = child.stdin.take().context("failed to open child stdin")?;
+    // `git apply` reads the whole patch before writing anything, so this can't deadlock.
+    stdin
+        .write_all(build(header, &[hunk]).as_bytes())
+        .context("failed to write patch to `git apply`")?;
+    drop(stdin);
+
+    let output = child
+        .wait_with_output()
+        .context("failed to wait for `git apply`")?;
+    record_command(&args);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    const HUNK: &str = "@@ -1,4 +1,4 @@
+ a
+-b
+-c
++B
++C
+ d";
+
+    #[test_case(&[1], false, "@@ -1,4 +1,3 @@
+ a
+-b
+ c
+ d
+" ; "stage a deletion")]
+    #[test_case(&[3], false, "@@ -1,4 +1,5 @@
+ a
+ b
+ c
++B
+ d
+" ; "stage an addition")]
+    #[test_case(&[1, 3], true, "@@ -1,4 +1,4 @@
+ a
+-b
++B
+ C
+ d
+" ; "unstage a change")]
+    #[test_case(&[4], true, "@@ -1,3 +1,4 @@
+ a
+ B
++C
+ d
+" ; "unstage an addition")]
+    fn select_lines(selected: &[usize], reverse: bool, expected: &str) {
+        assert_eq!(
+            super::select_lines(HUNK, |i| selected.contains(&i), reverse).unwrap(),
+            expected
+        );
+    }
+
+    #[test_case("@@ -0,0 +1,2 @@
++a
++b", &[1], "@@ -0,0 +1,1 @@
++b
+" ; "new file")]
+    #[test_case("@@ -1,2 +0,0 @@
+-a
+-b", &[0], "@@ -1,2 +1,1 @@
+-a
+ b
+" ; "emptied file")]
+    #[test_case("@@ -1 +1 @@
+-a
++b
+\\ No newline at end of file", &[0], "@@ -1,1 +0,0 @@
+-a
+" ; "no newline at end of file")]
+    fn select_lines_edge_cases(hunk: &str, selected: &[usize], expected: &str) {
+        assert_eq!(
+            super::select_lines(hunk, |i| selected.contains(&i), false).unwrap(),
+            expected
+        );
+    }
+
+    #[test_case(HUNK, &[HUNK] ; "one run")]
+    #[test_case("@@ -1,5 +1,5 @@ fn main() {
+-a
++A
+ b
+ c
+-d
++D
+ e", &["@@ -1,3 +1,3 @@ fn main() {
+-a
++A
+ b
+ c
+", "@@ -2,4 +2,4 @@ fn main() {
+ b
+ c
+-d
++D
+ e
+"] ; "two runs")]
+    #[test_case("@@ -3,4 +3,5 @@
+ a
++b
+ c
+-d
+ e
++f
+\\ No newline at end of file", &["@@ -3,2 +3,3 @@
+ a
++b
+ c
+", "@@ -4,3 +5,2 @@
+ c
+-d
+ e
+", "@@ -6,1 +6,2 @@
+ e
++f
+\\ No newline at end of file
+"] ; "three runs")]
+    fn split(hunk: &str, expected: &[&str]) {
+        assert_eq!(super::split(hunk).unwrap(), expected);
+    }
+
+    #[test_case(&[HUNK], "diff --git a/f b/f
+--- a/f
++++ b/f
+@@ -1,4 +1,4 @@
+ a
+-b
+-c
++B
++C
+ d
+" ; "one hunk")]
+    #[test_case(&["@@ -1 +1 @@\n-a\n+b\n", "@@ -9 +9 @@\n-c\n+d"], "diff --git a/f b/f
+--- a/f
++++ b/f
+@@ -1 +1 @@
+-a
++b
+@@ -9 +9 @@
+-c
++d
+" ; "two hunks")]
+    fn build(hunks: &[&str], expected: &str) {
+        assert_eq!(
+            super::build("diff --git a/f b/f\n--- a/f\n+++ b/f", hunks),
+            expected
+        );
+    }
+
+    const SPLIT_FILE: &str = "diff --git a/f b/f
+index 1111111..2222222 100644
+--- a/f
++++ b/f
+@@ -1,4 +1,4 @@
+-a
++A
+ b
+ c
+-d
++D
+@@ -10,4 +10,4 @@
+ j
+-k
++K
+ l
+-m
++M
+";
+
+    #[test_case(&[] ; "nothing split")]
+    #[test_case(&[0] ; "first hunk split")]
+    #[test_case(&[0, 1] ; "both hunks split")]
+    fn unsplit(split: &[usize]) {
+        let diffs = crate::parse::parse_diff(SPLIT_FILE).unwrap();
+        let diff = &diffs["f"];
+        let mut hunks = Vec::new();
+        for (i, hunk) in diff.hunks.iter().enumerate() {
+            if split.contains(&i) {
+                let pieces = super::split(hunk).unwrap();
+                assert!(pieces.len() > 1);
+                hunks.extend(pieces.into_iter().map(|piece| (piece, Some(hunk.as_str()))));
+            } else {
+                hunks.push((hunk.clone(), None));
+            }
+        }
+        let hunks = super::unsplit(
+            hunks
+                .iter()
+                .map(|(hunk, unsplit)| (hunk.as_str(), *unsplit)),
+        );
+        assert_eq!(super::build(&diff.header, &hunks), SPLIT_FILE);
+    }
+
+    #[test]
+    fn select_no_lines() {
+        assert!(super::select_lines(HUNK, |i| i == 0, false).is_err());
+    }
+}
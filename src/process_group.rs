@@ -0,0 +1,73 @@
+//! Running git in a process group of its own, so that when it has to be stopped, whatever it
+//! started, like hooks, ssh and credential helpers, is stopped with it rather than left running.
+
+use std::process::{Child, Command};
+
+/// Makes `command` start a process group of its own.
+pub fn isolate(command: &mut Command) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    #[cfg(not(unix))]
+    let _ = command;
+}
+
+/// Kills `child` and everything else in its process group, if it was given one with [`isolate`].
+pub fn kill(child: &mut Child) {
+    #[cfg(unix)]
+    if let Ok(pid) = libc::pid_t::try_from(child.id()) {
+        // SAFETY: `kill` only sends a signal, here to the group `child` leads.
+        unsafe {
+            libc::kill(-pid, libc::SIGKILL);
+        }
+    }
+    drop(child.kill());
+}
+
+/// Puts the process group of a child given one with [`isolate`] in the foreground of the terminal
+/// while this is around, so that it can read from it, e.g. to ask for a password. The terminal
+/// goes back to gex once this is dropped.
+pub struct Foreground {
+    #[cfg(unix)]
+    previous_handler: libc::sighandler_t,
+}
+
+impl Foreground {
+    pub fn new(child: &Child) -> Self {
+        #[cfg(unix)]
+        {
+            let pid = libc::pid_t::try_from(child.id()).unwrap_or_default();
+            // SAFETY: these only change which process group the terminal belongs to and how
+            // SIGTTOU is handled, which is put back on drop. SIGTTOU is ignored as it would
+            // otherwise stop gex for changing the terminal's process group from the background.
+            unsafe {
+                let previous_handler = libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+                if pid != 0 && libc::tcsetpgrp(libc::STDIN_FILENO, pid) == 0 {
+                    // The child may have tried to read from the terminal before it was given it,
+                    // which stops it, so it's started again.
+                    libc::kill(-pid, libc::SIGCONT);
+                }
+                Self { previous_handler }
+            }
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = child;
+            Self {}
+        }
+    }
+}
+
+impl Drop for Foreground {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        // SAFETY: as in `new`, this gives the terminal back to gex's process group and puts back
+        // how SIGTTOU was handled.
+        unsafe {
+            libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpgrp());
+            libc::signal(libc::SIGTTOU, self.previous_handler);
+        }
+    }
+}
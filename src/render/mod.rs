@@ -1,5 +1,8 @@
 mod renderer;
 mod terminal;
 
-pub use renderer::{Render, Renderer};
-pub use terminal::{Clear, ResetAttributes, ResetColor};
+pub use renderer::{repaint_all, LongLines, Render, Renderer};
+pub use terminal::{
+    color_enabled, disable_color, Clear, ResetAttributes, ResetColor, SetAttribute,
+    SetBackgroundColor, SetCursorStyle, SetForegroundColor,
+};
@@ -1,8 +1,13 @@
-use std::fmt;
+use std::{
+    borrow::Cow,
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
 use crossterm::{cursor::MoveTo, terminal::ClearType};
+use unicode_width::UnicodeWidthChar;
 
-use crate::render::{Clear, ResetAttributes};
+use crate::render::{color_enabled, Clear, ResetAttributes};
 
 /// The [`Renderer`] type contains a buffer to be rendered to the screen. It handles scrolling based
 /// on the cursor's position and will only write the lines that should be visible.
@@ -15,8 +20,50 @@ pub struct Renderer {
     /// show. Can also be thought of as a "target" starting line, which will be updated based on
     /// where the cursor is.
     start_line: usize,
+    /// The lines that start each item that can be clicked on, with the item and part of it they
+    /// start, or `None` for lines that aren't part of any.
+    targets: Vec<(usize, Option<(usize, usize)>)>,
+    /// The targets of what was last shown, with the line shown at the top of the screen and how
+    /// many lines were shown, for finding what's been clicked on.
+    shown_targets: Vec<(usize, Option<(usize, usize)>)>,
+    shown: (usize, usize),
+    /// The rows on the screen as they were last shown, so that only the ones that change need to
+    /// be drawn again. Rows that something else has been drawn over are left out.
+    previous_rows: Vec<String>,
+    /// The width and height of the screen when it was last shown.
+    previous_size: (usize, usize),
 }
 
+/// Set when something else has had the terminal, like the user's editor, so everything needs to
+/// be drawn again rather than just what's changed.
+static REPAINT: AtomicBool = AtomicBool::new(false);
+
+/// Makes the next [`Renderer::show_and_clear`] draw the whole screen again, for after something
+/// else has had the terminal.
+pub fn repaint_all() {
+    REPAINT.store(true, Ordering::Relaxed);
+}
+
+/// An item that can be clicked on, and the part of it, like a file and one of its hunks.
+pub type Target = (usize, usize);
+
+/// What to do with lines too long to fit on the screen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LongLines {
+    /// Cut them off at the edge of the screen, ending in "…".
+    Truncate,
+    /// Carry them on over the following rows, indented.
+    Wrap,
+    /// Print them as they are and let the terminal deal with them.
+    Overflow,
+}
+
+/// How far the rows a long line is wrapped onto are indented.
+const WRAP_INDENT: usize = 2;
+
+/// How many columns apart terminals put tab stops.
+const TAB_STOP: usize = 8;
+
 /// Types implementing [`Render`] can write to the given [`Renderer`] and update its cursor
 /// position.
 pub trait Render {
@@ -35,24 +82,119 @@ impl fmt::Write for Renderer {
     }
 }
 
-/// Truncates a string to the given `length`, ignoring ANSI escape sequences.
-fn truncate_ansi(s: &str, length: usize) -> &str {
-    struct Performer(usize);
-    impl vte::Perform for Performer {
-        fn print(&mut self, _c: char) {
-            self.0 += 1;
+/// What a byte given to the parser finished, if anything.
+enum Parsed {
+    /// A character to be shown.
+    Char(char),
+    /// A control character, like a tab or a carriage return.
+    Control(u8),
+    /// An escape sequence, like one setting the colour.
+    Escape,
+}
+
+/// Parses strings with escape sequences in them, into what each byte finishes.
+struct Performer(Option<Parsed>);
+
+impl vte::Perform for Performer {
+    fn print(&mut self, c: char) {
+        self.0 = Some(Parsed::Char(c));
+    }
+
+    fn execute(&mut self, byte: u8) {
+        self.0 = Some(Parsed::Control(byte));
+    }
+
+    fn csi_dispatch(
+        &mut self,
+        _params: &vte::Params,
+        _intermediates: &[u8],
+        _ignore: bool,
+        _action: char,
+    ) {
+        self.0 = Some(Parsed::Escape);
+    }
+
+    fn esc_dispatch(&mut self, _intermediates: &[u8], _ignore: bool, _byte: u8) {
+        self.0 = Some(Parsed::Escape);
+    }
+}
+
+impl Parsed {
+    /// The number of columns this takes up on the screen when it starts at `column`. Characters
+    /// like those of CJK scripts take up two, and a tab goes on to the terminal's next tab stop.
+    fn width(&self, column: usize) -> usize {
+        match *self {
+            Self::Char(c) => c.width().unwrap_or(0),
+            Self::Control(b'\t') => TAB_STOP - column % TAB_STOP,
+            Self::Control(_) | Self::Escape => 0,
         }
     }
-    let mut performer = Performer(0);
+}
+
+/// Truncates a string to the given `length`, ignoring ANSI escape sequences. If anything was cut
+/// off, the last character is replaced with "…" to show it.
+fn truncate_ansi(s: &str, length: usize) -> Cow<'_, str> {
+    let mut performer = Performer(None);
     let mut parser = vte::Parser::new();
-    let bytes = s.as_bytes().iter().enumerate();
-    for (i, b) in bytes {
-        parser.advance(&mut performer, *b);
-        if performer.0 > length {
-            return &s[0..i];
+    let mut columns = 0;
+    // Where the text that fits along with the "…" ends.
+    let mut fits = 0;
+    for (i, b) in s.bytes().enumerate() {
+        parser.advance(&mut performer, b);
+        let Some(parsed) = performer.0.take() else {
+            continue;
+        };
+        columns += parsed.width(columns);
+        if columns > length {
+            return if length == 0 {
+                Cow::Borrowed("")
+            } else {
+                Cow::Owned(format!("{}…", &s[..fits]))
+            };
+        }
+        if columns < length {
+            fits = i + 1;
         }
     }
-    s
+    Cow::Borrowed(s)
+}
+
+/// Splits `s` into rows of at most `width` columns, ignoring ANSI escape sequences. The rows after
+/// the first are indented, and start with all the escape sequences that came before them so that
+/// they keep the colours of the line.
+fn wrap_ansi(s: &str, width: usize) -> Vec<String> {
+    use fmt::Write;
+    let indent = if width > WRAP_INDENT { WRAP_INDENT } else { 0 };
+    let mut rows = Vec::new();
+    let mut row = String::new();
+    let mut escapes = String::new();
+    let mut columns = 0;
+    let mut start = 0;
+    let mut performer = Performer(None);
+    let mut parser = vte::Parser::new();
+    for (i, b) in s.bytes().enumerate() {
+        parser.advance(&mut performer, b);
+        let Some(parsed) = performer.0.take() else {
+            continue;
+        };
+        // Something too wide for even an empty row is left to be cut off, rather than wrapped for
+        // ever.
+        if columns + parsed.width(columns) > width && columns > indent {
+            write!(row, "{ResetAttributes}").expect("writing to a String can't fail");
+            rows.push(std::mem::take(&mut row));
+            row = format!("{}{escapes}", " ".repeat(indent));
+            columns = indent;
+        }
+        columns += parsed.width(columns);
+        if matches!(parsed, Parsed::Escape) {
+            escapes.push_str(&s[start..=i]);
+        }
+        row.push_str(&s[start..=i]);
+        start = i + 1;
+    }
+    row.push_str(&s[start..]);
+    rows.push(row);
+    rows
 }
 
 impl Renderer {
@@ -74,21 +216,181 @@ impl Renderer {
         self.selected_item.1 = self.buffer.lines().count() - 1;
     }
 
-    /// Render to stdout and clear the buffer.
+    /// Makes the next line, and those after it, part of `item` when clicked on.
+    pub fn insert_target(&mut self, item: usize) {
+        let next_line = self.buffer.lines().count();
+        self.targets.push((next_line, Some((item, 0))));
+    }
+
+    /// Makes the next line, and those after it, part `part` of the item started by the last call
+    /// to [`Renderer::insert_target`], like a hunk of a file.
+    pub fn insert_sub_target(&mut self, part: usize) {
+        let next_line = self.buffer.lines().count();
+        let item = self
+            .targets
+            .iter()
+            .rev()
+            .find_map(|(_, target)| target.map(|(item, _)| item))
+            .unwrap_or_default();
+        self.targets.push((next_line, Some((item, part))));
+    }
+
+    /// Makes the next line, and those after it, not part of anything that can be clicked on.
+    pub fn clear_target(&mut self) {
+        let next_line = self.buffer.lines().count();
+        self.targets.push((next_line, None));
+    }
+
+    /// The item and part of it shown at `row` of the screen, and whether it's the first line of
+    /// them, which is usually a header that can be clicked to expand or collapse it.
+    pub fn target_at(&self, row: usize) -> Option<((usize, usize), bool)> {
+        let (start_line, count) = self.shown;
+        if row >= count {
+            return None;
+        }
+        let line = start_line + row;
+        let &(first_line, target) = self
+            .shown_targets
+            .iter()
+            .rev()
+            .find(|(first_line, _)| *first_line <= line)?;
+        target.map(|target| (target, first_line == line))
+    }
+
+    /// Scroll the view by `lines`. The view still follows the cursor, so this can't scroll the
+    /// selected item out of sight, but it can move through an item taller than the terminal.
+    pub const fn scroll(&mut self, lines: isize) {
+        self.start_line = self.start_line.saturating_add_signed(lines);
+    }
+
+    /// Renders `item` without showing it, to find which line its cursor is on, and the line each
+    /// item and part of one that can be clicked on starts at, as [`Renderer::target_at`] gives
+    /// them.
+    pub fn cursor_and_targets(
+        &mut self,
+        item: &impl Render,
+    ) -> Result<(usize, Vec<(usize, Target)>), fmt::Error> {
+        item.render(self)?;
+        self.buffer.clear();
+        let targets = self
+            .targets
+            .drain(..)
+            .filter_map(|(line, target)| Some((line, target?)))
+            .collect();
+        Ok((self.selected_item.0, targets))
+    }
+
+    /// Takes what has been rendered, for printing it rather than showing it on the screen.
+    /// Carriage returns are left out, and so are escape sequences if colour is disabled.
+    pub fn take(&mut self) -> String {
+        struct Performer(String);
+        impl vte::Perform for Performer {
+            fn print(&mut self, c: char) {
+                self.0.push(c);
+            }
+
+            fn execute(&mut self, byte: u8) {
+                if matches!(byte, b'\n' | b'\t') {
+                    self.0.push(char::from(byte));
+                }
+            }
+        }
+        let buffer = std::mem::take(&mut self.buffer);
+        self.targets.clear();
+        if color_enabled() {
+            return format!("{}{ResetAttributes}", buffer.replace('\r', ""));
+        }
+        let mut performer = Performer(String::new());
+        let mut parser = vte::Parser::new();
+        for b in buffer.bytes() {
+            parser.advance(&mut performer, b);
+        }
+        performer.0
+    }
+
+    /// Wraps the lines of the buffer that are wider than `width` onto the rows after them, moving
+    /// the selected item to match.
+    fn wrap(&mut self, width: usize) {
+        let (cursor_start_idx, cursor_end_idx) = self.selected_item;
+        let mut wrapped = String::with_capacity(self.buffer.len());
+        let mut rows = 0;
+        // The row each line starts on once it's wrapped.
+        let mut line_rows = Vec::new();
+        for (i, line) in self.buffer.lines().enumerate() {
+            line_rows.push(rows);
+            if i == cursor_start_idx {
+                self.selected_item.0 = rows;
+            }
+            // Other than tabs, no character takes up more columns than it has bytes, so a line
+            // without them that has no more bytes than fit needs no wrapping.
+            let line_rows = if line.len() <= width && !line.contains('\t') {
+                vec![Cow::Borrowed(line)]
+            } else {
+                wrap_ansi(line, width).into_iter().map(Cow::Owned).collect()
+            };
+            for row in line_rows {
+                if rows > 0 {
+                    wrapped.push('\n');
+                }
+                wrapped.push_str(&row);
+                rows += 1;
+            }
+            if i == cursor_end_idx {
+                self.selected_item.1 = rows - 1;
+            }
+        }
+        for (line, _) in &mut self.targets {
+            *line = line_rows.get(*line).copied().unwrap_or(rows);
+        }
+        self.buffer = wrapped;
+    }
+
+    /// Forgets what was shown on the rows from `row` down, as something else has been drawn over
+    /// them, like a menu or a message.
+    pub fn invalidate_from(&mut self, row: usize) {
+        self.previous_rows.truncate(row);
+    }
+
+    /// Go back to the top of the buffer, for when showing something new.
+    pub const fn scroll_to_top(&mut self) {
+        self.start_line = 0;
+    }
+
+    /// Render to stdout and clear the buffer. Only the rows that have changed since last time are
+    /// drawn, to keep the screen from flickering.
     pub fn show_and_clear(
         &mut self,
         width: usize,
         height: usize,
         lookahead: usize,
-        truncate: bool,
+        long_lines: LongLines,
     ) {
-        print!("{}", Clear(ClearType::All));
+        // Lines that overflow carry on onto the rows after them, so the rows can't be compared.
+        if REPAINT.swap(false, Ordering::Relaxed)
+            || self.previous_size != (width, height)
+            || long_lines == LongLines::Overflow
+        {
+            print!("{}", Clear(ClearType::All));
+            self.previous_rows.clear();
+            self.previous_size = (width, height);
+        }
+
+        if long_lines == LongLines::Wrap {
+            self.wrap(width);
+        }
 
         let (cursor_start_idx, cursor_end_idx) = self.selected_item;
         let count_lines = self.buffer.lines().count();
 
+        // Selection bigger than the terminal height. Keep the view within it, wherever it has been
+        // scrolled to.
+        if cursor_end_idx - cursor_start_idx >= height {
+            self.start_line = self
+                .start_line
+                .clamp(cursor_start_idx, cursor_end_idx + 1 - height);
+        }
         // Going down.
-        if cursor_end_idx + lookahead >= self.start_line + height {
+        else if cursor_end_idx + lookahead >= self.start_line + height {
             self.start_line = (cursor_end_idx + lookahead)
                 .min(count_lines - 1)
                 .saturating_sub(height - 1);
@@ -98,38 +400,136 @@ impl Renderer {
             self.start_line = cursor_start_idx.saturating_sub(lookahead);
         }
 
-        // Selection bigger than the terminal height.
-        if cursor_end_idx - cursor_start_idx >= height {
-            self.start_line = cursor_start_idx;
-        }
         // Distance to end of buffer is less than the terminal height.
-        else if count_lines - self.start_line < height {
+        if count_lines - self.start_line < height {
             self.start_line = count_lines.saturating_sub(height);
         }
 
-        if truncate {
-            for (row, l) in self
-                .buffer
-                .lines()
-                .skip(self.start_line)
-                .take(height)
-                .map(|l| truncate_ansi(l, width))
-                .enumerate()
-            {
-                print!("{}{l}{}", MoveTo(0, row as u16), ResetAttributes);
+        self.shown_targets = std::mem::take(&mut self.targets);
+        self.shown = (self.start_line, (count_lines - self.start_line).min(height));
+
+        let rows = self
+            .buffer
+            .lines()
+            .skip(self.start_line)
+            .take(height)
+            .map(|l| {
+                if long_lines == LongLines::Truncate {
+                    truncate_ansi(l, width).into_owned()
+                } else {
+                    l.to_string()
+                }
+            })
+            .collect::<Vec<_>>();
+        for (i, row) in rows.iter().enumerate() {
+            if self.previous_rows.get(i) != Some(row) {
+                print!(
+                    "{}{}{row}{ResetAttributes}",
+                    MoveTo(0, i as u16),
+                    Clear(ClearType::CurrentLine)
+                );
             }
-        } else {
-            for (row, l) in self
-                .buffer
-                .lines()
-                .skip(self.start_line)
-                .take(height)
-                .enumerate()
-            {
-                print!("{}{l}", MoveTo(0, row as u16));
+        }
+        // Clear what was below the end of the buffer, if it's shorter than before.
+        for i in rows.len()..height {
+            if self.previous_rows.get(i).is_none_or(|row| !row.is_empty()) {
+                print!("{}{}", MoveTo(0, i as u16), Clear(ClearType::CurrentLine));
             }
-            print!("{ResetAttributes}");
         }
+        self.previous_rows = rows;
+        self.previous_rows.resize(height, String::new());
         self.buffer.clear();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use crate::{
+        config::{Config, CONFIG},
+        render::ResetAttributes,
+    };
+
+    #[test_case("abc", 5, "abc" ; "fits")]
+    #[test_case("abcde", 5, "abcde" ; "fits exactly")]
+    #[test_case("abcdef", 5, "abcd…" ; "cut off")]
+    #[test_case("abc", 0, "" ; "no room")]
+    #[test_case("\x1b[31mabc\x1b[m", 3, "\x1b[31mabc\x1b[m" ; "escapes take no room")]
+    #[test_case("\x1b[31mabcdef\x1b[m", 4, "\x1b[31mabc…" ; "escapes kept before the cut")]
+    #[test_case("日本語", 6, "日本語" ; "wide characters fit")]
+    #[test_case("日本語", 5, "日本…" ; "wide characters cut off")]
+    #[test_case("日本語", 4, "日…" ; "wide character cut in half")]
+    #[test_case("a\tb", 9, "a\tb" ; "tab fits")]
+    #[test_case("a\tb", 8, "a…" ; "tab takes up to the tab stop")]
+    fn truncate_ansi(s: &str, length: usize, expected: &str) {
+        assert_eq!(super::truncate_ansi(s, length), expected);
+    }
+
+    #[test_case("abc", 5, &["abc"] ; "fits")]
+    #[test_case("abcdefgh", 5, &["abcde{R}", "  fgh"] ; "wrapped")]
+    #[test_case("abcdefghijk", 5, &["abcde{R}", "  fgh{R}", "  ijk"] ; "wrapped twice")]
+    #[test_case("\x1b[31mabcdef\x1b[m", 5, &["\x1b[31mabcde{R}", "  \x1b[31mf\x1b[m"] ; "escapes carried over")]
+    #[test_case("日本語", 5, &["日本{R}", "  語"] ; "wide characters not split")]
+    #[test_case("ab\tc", 8, &["ab\t{R}", "  c"] ; "tab takes up to the tab stop")]
+    #[test_case("a\tbcdefghijk", 10, &["a\tbc{R}", "  defghijk"] ; "tab not carried over")]
+    #[test_case("abc", 2, &["ab{R}", "c"] ; "too narrow to indent")]
+    fn wrap_ansi(s: &str, width: usize, expected: &[&str]) {
+        // Resetting the colours at the end of each row needs to know what to reset them to.
+        CONFIG.get_or_init(Config::default);
+        let expected: Vec<_> = expected
+            .iter()
+            .map(|row| row.replace("{R}", &ResetAttributes.to_string()))
+            .collect();
+        assert_eq!(super::wrap_ansi(s, width), expected);
+    }
+
+    #[test_case("abc\ndef", 5, "abc\ndef" ; "short lines")]
+    #[test_case("\t\tx\ny", 10, "\t{R}\n  \tx\ny" ; "short line wider with tabs")]
+    fn wrap(buffer: &str, width: usize, expected: &str) {
+        CONFIG.get_or_init(Config::default);
+        let mut renderer = super::Renderer {
+            buffer: buffer.to_string(),
+            ..super::Renderer::default()
+        };
+        renderer.wrap(width);
+        assert_eq!(
+            renderer.buffer,
+            expected.replace("{R}", &ResetAttributes.to_string())
+        );
+    }
+
+    #[test_case(0, 0, Some(((0, 0), true)) ; "file header")]
+    #[test_case(0, 1, Some(((0, 1), true)) ; "hunk header")]
+    #[test_case(0, 2, Some(((0, 1), false)) ; "hunk line")]
+    #[test_case(0, 3, None ; "empty row")]
+    #[test_case(0, 4, Some(((1, 0), true)) ; "first row of a wrapped line")]
+    #[test_case(0, 5, Some(((1, 0), false)) ; "wrapped row")]
+    #[test_case(0, 6, Some(((1, 1), true)) ; "hunk after a wrapped line")]
+    #[test_case(0, 7, None ; "below what's shown")]
+    #[test_case(3, 1, Some(((1, 0), true)) ; "scrolled")]
+    #[test_case(3, 2, Some(((1, 0), false)) ; "scrolled onto a wrapped row")]
+    #[test_case(3, 4, None ; "scrolled below what's shown")]
+    fn target_at(start_line: usize, row: usize, expected: Option<((usize, usize), bool)>) {
+        use std::fmt::Write;
+
+        CONFIG.get_or_init(Config::default);
+        let mut renderer = super::Renderer::default();
+        renderer.insert_target(0);
+        writeln!(renderer, "a.txt").unwrap();
+        renderer.insert_sub_target(1);
+        writeln!(renderer, "@@ -1 +1 @@\n-a").unwrap();
+        renderer.clear_target();
+        writeln!(renderer).unwrap();
+        renderer.insert_target(1);
+        writeln!(renderer, "a/very/long/path.txt").unwrap();
+        renderer.insert_sub_target(1);
+        writeln!(renderer, "@@ -1 +1 @@").unwrap();
+        renderer.wrap(12);
+        // Like showing it does, with the rows from `start_line` down on the screen.
+        let count = renderer.buffer.lines().count();
+        renderer.shown_targets = std::mem::take(&mut renderer.targets);
+        renderer.shown = (start_line, count - start_line);
+        assert_eq!(renderer.target_at(row), expected);
+    }
+}
@@ -3,22 +3,93 @@
 //! This exists because when resetting the terminal colours or clearing the screen we may have to
 //! handle the case where the user has set custom FG/BG colours specially.
 
-use std::fmt;
+use std::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
 use crossterm::{
     cursor,
-    style::{self, Color},
+    style::{self, Attribute, Color},
     terminal::{self, ClearType},
 };
 
 use crate::config;
 
+static COLOR: AtomicBool = AtomicBool::new(true);
+
+/// Stop writing colours and decorative attributes (bold and dim). Reverse video and underlines
+/// are still used, as they're what shows the cursor and selected lines.
+pub fn disable_color() {
+    COLOR.store(false, Ordering::Relaxed);
+}
+
+pub fn color_enabled() -> bool {
+    COLOR.load(Ordering::Relaxed)
+}
+
+/// See [`SetForegroundColor`](`crossterm::style::SetForegroundColor`). Writes nothing if colour
+/// is disabled.
+pub struct SetForegroundColor(pub Color);
+
+impl fmt::Display for SetForegroundColor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if color_enabled() {
+            write!(f, "{}", style::SetForegroundColor(self.0))?;
+        }
+        Ok(())
+    }
+}
+
+/// See [`SetBackgroundColor`](`crossterm::style::SetBackgroundColor`). Writes nothing if colour
+/// is disabled.
+pub struct SetBackgroundColor(pub Color);
+
+impl fmt::Display for SetBackgroundColor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if color_enabled() {
+            write!(f, "{}", style::SetBackgroundColor(self.0))?;
+        }
+        Ok(())
+    }
+}
+
+/// See [`SetAttribute`](`crossterm::style::SetAttribute`). For decorative attributes like bold
+/// and dim, which are left out if colour is disabled.
+pub struct SetAttribute(pub Attribute);
+
+impl fmt::Display for SetAttribute {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if color_enabled() {
+            write!(f, "{}", self.0)?;
+        }
+        Ok(())
+    }
+}
+
+/// Starts highlighting the cursor, with `colors.cursor` as the background if it's set, or in
+/// reverse video otherwise. Reverse video is also used when colour is disabled, so the cursor can
+/// still be seen.
+pub struct SetCursorStyle;
+
+impl fmt::Display for SetCursorStyle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match config!().colors.cursor {
+            color if color_enabled() && color != Color::Reset => {
+                write!(f, "{}", style::SetBackgroundColor(color))
+            }
+            _ => write!(f, "{}", Attribute::Reverse),
+        }
+    }
+}
+
 /// See [`Clear`](`crossterm::terminal::Clear`).
 pub struct Clear(pub ClearType);
 
 impl fmt::Display for Clear {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match config!().colors.background {
+            _ if !color_enabled() => write!(f, "{}", crossterm::terminal::Clear(self.0)),
             Color::Reset => write!(f, "{}", crossterm::terminal::Clear(self.0)),
             color => {
                 let Ok((cols, rows)) = terminal::size() else {
@@ -28,7 +99,7 @@ impl fmt::Display for Clear {
                 write!(f, "{}", style::SetBackgroundColor(color))?;
                 match self.0 {
                     ClearType::All => {
-                        write!(f, "{}{}", cursor::SavePosition, cursor::MoveTo(0, 0),)?;
+                        write!(f, "{}{}", cursor::SavePosition, cursor::MoveTo(0, 0))?;
                         for _ in 0..rows {
                             write!(f, "{:width$}", ' ', width = cols as usize)?;
                         }
@@ -94,8 +165,8 @@ impl fmt::Display for ResetColor {
         write!(
             f,
             "{}{}",
-            crossterm::style::SetForegroundColor(config!().colors.foreground),
-            crossterm::style::SetBackgroundColor(config!().colors.background),
+            SetForegroundColor(config!().colors.foreground),
+            SetBackgroundColor(config!().colors.background),
         )
     }
 }
@@ -107,6 +178,6 @@ pub struct ResetAttributes;
 
 impl fmt::Display for ResetAttributes {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}{}", crossterm::style::Attribute::Reset, ResetColor,)
+        write!(f, "{}{}", crossterm::style::Attribute::Reset, ResetColor)
     }
 }
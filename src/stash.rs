@@ -0,0 +1,180 @@
+//! Module for the stash list, which lists stashes and shows the diff of one when it's opened.
+
+use std::fmt;
+
+use anyhow::{Context, Result};
+use crossterm::style::Attribute;
+
+use crate::{
+    config::{Options, CONFIG},
+    git_process,
+    minibuffer::MiniBuffer,
+    parse,
+    render::{self, Renderer, ResetAttributes, SetAttribute, SetCursorStyle, SetForegroundColor},
+    status::FileDiff,
+};
+
+pub struct Stash {
+    /// How git refers to the stash, e.g. "stash@{0}".
+    pub name: String,
+    /// The stash message, e.g. "WIP on main: 1234567 Fix typo".
+    message: String,
+    /// When the stash was made, relative to now, e.g. "2 days ago".
+    date: String,
+}
+
+/// A stash opened from the stash list.
+pub struct StashDiff {
+    /// The name and message of the stash.
+    heading: String,
+    files: Vec<FileDiff>,
+    /// Whether to show hunks side by side, as they were in the status when this was opened.
+    side_by_side: bool,
+}
+
+#[derive(Default)]
+pub struct StashList {
+    /// The stashes, newest first.
+    pub stashes: Vec<Stash>,
+    pub cursor: usize,
+    /// The stash under the cursor, once it's been opened.
+    pub stash: Option<StashDiff>,
+}
+
+impl render::Render for StashList {
+    fn render(&self, f: &mut Renderer) -> fmt::Result {
+        use fmt::Write;
+        let config = CONFIG.get().expect("config wasn't initialised");
+
+        if let Some(ref stash) = self.stash {
+            // The whole stash is the selected item, so that it can be scrolled through.
+            f.insert_cursor();
+            write!(
+                f,
+                "\r{}{}{ResetAttributes}",
+                SetForegroundColor(config.colors.heading),
+                stash.heading,
+            )?;
+            if !stash.files.is_empty() {
+                write!(f, "\r\n")?;
+            }
+            for file in &stash.files {
+                write!(f, "\r\n")?;
+                file.render(f, "", stash.side_by_side)?;
+                write!(f, "{ResetAttributes}")?;
+            }
+            f.insert_item_end();
+            return Ok(());
+        }
+
+        if self.stashes.is_empty() {
+            return write!(
+                f,
+                "{}No stashes.{ResetAttributes}",
+                SetForegroundColor(config.colors.heading),
+            );
+        }
+
+        for (i, stash) in self.stashes.iter().enumerate() {
+            if i == self.cursor {
+                f.insert_cursor();
+            }
+            writeln!(
+                f,
+                "\r{}{}{ResetAttributes} {}{}{ResetAttributes} {}({}){ResetAttributes}",
+                SetAttribute(Attribute::Dim),
+                stash.name,
+                if i == self.cursor {
+                    SetCursorStyle.to_string()
+                } else {
+                    String::new()
+                },
+                stash.message,
+                SetAttribute(Attribute::Dim),
+                stash.date,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl StashList {
+    pub fn fetch(&mut self) -> Result<()> {
+        let output = git_process(&["stash", "list", "--format=%gd%x00%gs%x00%cr"])?;
+
+        self.stashes = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split('\0');
+                Some(Stash {
+                    name: fields.next()?.to_string(),
+                    message: fields.next()?.to_string(),
+                    date: fields.next()?.to_string(),
+                })
+            })
+            .collect();
+        self.cursor = self.cursor.min(self.stashes.len().saturating_sub(1));
+        self.stash = None;
+        Ok(())
+    }
+
+    pub const fn down(&mut self) {
+        if self.cursor + 1 < self.stashes.len() {
+            self.cursor += 1;
+        }
+    }
+
+    pub const fn up(&mut self) {
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    pub const fn cursor_first(&mut self) {
+        self.cursor = 0;
+    }
+
+    pub const fn cursor_last(&mut self) {
+        self.cursor = self.stashes.len().saturating_sub(1);
+    }
+
+    /// The stash under the cursor.
+    pub fn selected(&self) -> Option<&Stash> {
+        self.stashes.get(self.cursor)
+    }
+
+    /// Runs `git stash <subcommand>` on the stash under the cursor, e.g. to pop or drop it, and
+    /// shows git's output, including any conflicts.
+    pub fn run_on_selected(&self, subcommand: &str) -> Result<()> {
+        let Some(stash) = self.selected() else {
+            return Ok(());
+        };
+        let output = git_process(&["stash", subcommand, &stash.name])?;
+        MiniBuffer::push_command_output(&output);
+        Ok(())
+    }
+
+    /// Opens the stash under the cursor, showing its diff with hunks side by side if
+    /// `side_by_side`.
+    pub fn open(&mut self, options: &Options, side_by_side: bool) -> Result<()> {
+        let Some(stash) = self.selected() else {
+            return Ok(());
+        };
+        let output = git_process(&["stash", "show", "--patch", "--no-ext-diff", &stash.name])?;
+        let show = String::from_utf8_lossy(&output.stdout);
+
+        let mut diffs = parse::parse_diff(&show)
+            .context("failed to parse stash diff")?
+            .into_iter()
+            .collect::<Vec<_>>();
+        diffs.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        self.stash = Some(StashDiff {
+            heading: format!("{}: {}", stash.name, stash.message),
+            files: diffs
+                .iter()
+                .map(|(path, diff)| FileDiff::from_diff(path, diff, options))
+                .collect(),
+            side_by_side,
+        });
+        Ok(())
+    }
+}
@@ -2,22 +2,38 @@
 
 use std::{
     borrow::Cow,
+    cell::OnceCell,
+    collections::{BTreeSet, HashMap, HashSet},
     fmt, fs,
-    io::{stdout, Read, Write},
-    process::{Command, Output, Stdio},
+    io::{stdout, Write},
+    iter,
+    ops::Range,
+    path::Path,
+    process::Output,
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+    time::{Duration, SystemTime},
 };
 
-use anyhow::{anyhow, Context, Error, Result};
-use crossterm::style::{self, Attribute};
-use git2::{ErrorCode::UnbornBranch, Repository};
-use nom::{bytes::complete::take_until, IResult};
+use anyhow::{bail, Context, Error, Result};
+use crossterm::style::{Attribute, Color};
+use git2::{ErrorCode::UnbornBranch, Repository, RepositoryState};
+use itertools::Itertools;
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+use syntect::parsing::SyntaxReference;
+use unicode_width::UnicodeWidthChar;
 
 use crate::{
     config::{Config, Options, CONFIG},
-    git_process,
+    git_process, highlight,
     minibuffer::{MessageType, MiniBuffer},
-    parse::{self, parse_hunk_new, parse_hunk_old},
-    render::{self, Renderer, ResetAttributes, ResetColor},
+    parse::{self, parse_hunk_new, parse_hunk_old, StatusEntry},
+    patch,
+    render::{
+        self, Renderer, ResetAttributes, ResetColor, SetAttribute, SetBackgroundColor,
+        SetCursorStyle, SetForegroundColor,
+    },
+    worktree_path,
 };
 
 pub trait Expand {
@@ -25,23 +41,138 @@ pub trait Expand {
     fn expanded(&self) -> bool;
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
 enum DiffType {
     Modified,
     Created,
     Untracked,
     Renamed,
     Deleted,
+    TypeChanged,
+    Copied,
+    /// Has merge conflicts.
+    Conflicted,
+}
+
+impl DiffType {
+    /// Gets the `DiffType` for a status code from `git status --porcelain`.
+    const fn from_status_code(code: char) -> Self {
+        match code {
+            'A' => Self::Created,
+            'D' => Self::Deleted,
+            'R' => Self::Renamed,
+            'T' => Self::TypeChanged,
+            'C' => Self::Copied,
+            // 'M', or a code we don't know about. Whatever it is, the file has been changed
+            // somehow.
+            _ => Self::Modified,
+        }
+    }
+}
+
+/// A git operation that has stopped partway, usually because of conflicts, and can be continued
+/// or aborted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    Merge,
+    CherryPick,
+    Revert,
+    /// With the number of the commit being applied and how many there are in total, if known.
+    Rebase(Option<(usize, usize)>),
+}
+
+impl Operation {
+    pub fn from_repo(repo: &Repository) -> Option<Self> {
+        match repo.state() {
+            RepositoryState::Merge => Some(Self::Merge),
+            RepositoryState::CherryPick | RepositoryState::CherryPickSequence => {
+                Some(Self::CherryPick)
+            }
+            RepositoryState::Revert | RepositoryState::RevertSequence => Some(Self::Revert),
+            // The apply backend keeps its files in `rebase-apply`, the merge backend (the
+            // default) in `rebase-merge`.
+            RepositoryState::Rebase => Some(Self::Rebase(rebase_progress(
+                repo,
+                "rebase-apply/next",
+                "rebase-apply/last",
+            ))),
+            RepositoryState::RebaseInteractive | RepositoryState::RebaseMerge => {
+                Some(Self::Rebase(rebase_progress(
+                    repo,
+                    "rebase-merge/msgnum",
+                    "rebase-merge/end",
+                )))
+            }
+            _ => None,
+        }
+    }
+
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Merge => "MERGE",
+            Self::CherryPick => "CHERRY-PICK",
+            Self::Revert => "REVERT",
+            Self::Rebase(_) => "REBASE",
+        }
+    }
+
+    /// The git command that continues or aborts the operation.
+    pub const fn command(self) -> &'static str {
+        match self {
+            Self::Merge => "merge",
+            Self::CherryPick => "cherry-pick",
+            Self::Revert => "revert",
+            Self::Rebase(_) => "rebase",
+        }
+    }
+}
+
+/// Reads how far through a rebase is from the files git keeps in the repository for the number of
+/// the commit being applied, `done`, and how many there are in total, `total`.
+fn rebase_progress(repo: &Repository, done: &str, total: &str) -> Option<(usize, usize)> {
+    let read = |path: &str| -> Option<usize> {
+        fs::read_to_string(repo.path().join(path))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    };
+    read(done).zip(read(total))
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Hunk {
     diff: String,
+    #[serde(skip)]
     expanded: bool,
+    /// Set while choosing individual lines of this hunk to stage or unstage.
+    #[serde(skip)]
+    selection: Option<LineSelection>,
+    /// The syntax of the file the hunk is from, if it should be highlighted.
+    #[serde(skip)]
+    syntax: Option<&'static SyntaxReference>,
+    /// The highlighted content of each line, worked out the first time the hunk is shown.
+    #[serde(skip)]
+    highlighted: OnceCell<Vec<String>>,
+    /// The hunk git gave, if this is one of the pieces it was split into.
+    #[serde(skip)]
+    unsplit: Option<String>,
+}
+
+/// The lines chosen within a hunk. Lines are counted from the one after the `@@` header.
+#[derive(Debug, Clone, Default)]
+struct LineSelection {
+    cursor: usize,
+    marked: BTreeSet<usize>,
 }
 
-impl fmt::Display for Hunk {
-    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+impl Hunk {
+    /// Renders the hunk, highlighting matches of `search`, with the old and new versions next to
+    /// each other if `side_by_side`. While choosing lines, the cursor is put on the chosen line.
+    /// Otherwise it's left for the `FileDiff` to place around the whole hunk.
+    fn render(&self, f: &mut Renderer, search: &str, side_by_side: bool) -> fmt::Result {
         use fmt::Write;
         let config = CONFIG.get().expect("config wasn't initialised");
 
@@ -49,87 +180,674 @@ impl fmt::Display for Hunk {
         let Some(head) = lines.next() else {
             return Ok(());
         };
-        let mut outbuf = format!(
+        write!(
+            f,
             "{}{}{}",
-            style::SetForegroundColor(config.colors.hunk_head),
-            if self.expanded { "⌄" } else { "›" },
-            head.replace(" @@", &format!(" @@{ResetAttributes}"))
-        );
+            SetForegroundColor(config.colors.hunk_head),
+            config.symbols.get(self.expanded),
+            format_hunk_head(head, search, config)
+        )?;
 
         if self.expanded {
-            let ws_error_highlight = CONFIG
-                .get()
-                .expect("config is initialised at the start of the program")
+            let highlighted = self.syntax.map(|syntax| {
+                self.highlighted
+                    .get_or_init(|| highlight::highlight_lines(syntax, self.diff.lines().skip(1)))
+            });
+            let line_numbers = config
                 .options
-                .ws_error_highlight;
-            for line in lines {
-                match line.chars().next() {
-                    Some('+') => write!(
-                        &mut outbuf,
-                        "\r\n{}{}",
-                        style::SetForegroundColor(config.colors.addition),
-                        if ws_error_highlight.new {
-                            format_trailing_whitespace(line, config)
-                        } else {
-                            Cow::Borrowed(line)
-                        }
-                    ),
-                    Some('-') => write!(
-                        &mut outbuf,
-                        "\r\n{}{}",
-                        style::SetForegroundColor(config.colors.deletion),
-                        if ws_error_highlight.old {
-                            format_trailing_whitespace(line, config)
-                        } else {
-                            Cow::Borrowed(line)
-                        }
-                    ),
-                    Some(c) => write!(
-                        &mut outbuf,
-                        "\r\n{}{c}{}",
-                        style::SetForegroundColor(config.colors.foreground),
-                        if ws_error_highlight.context {
-                            format_trailing_whitespace(&line[1..], config)
-                        } else {
-                            Cow::Borrowed(&line[1..])
-                        }
-                    ),
-                    // I think this case never happens, but if it does, it just means the line was
-                    // empty.
-                    None => {
-                        outbuf.push('\n');
-                        Ok(())
-                    }
-                }?;
+                .line_numbers
+                .then(|| parse::hunk_line_numbers(&self.diff).ok())
+                .flatten();
+            let lines: Vec<(char, String)> = lines
+                .enumerate()
+                .map(|(i, line)| {
+                    let highlighted = highlighted.and_then(|h| h.get(i)).map(String::as_str);
+                    (
+                        line.chars().next().unwrap_or(' '),
+                        shown_content(line, highlighted, search, config),
+                    )
+                })
+                .collect();
+            // Lines can't be chosen side by side, as a row can have two lines in it.
+            if side_by_side && self.selection.is_none() {
+                render_side_by_side(f, &lines, line_numbers.as_deref(), config)?;
+            } else {
+                self.render_unified(f, &lines, line_numbers.as_deref(), config)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Hunk {
+    /// Renders the prepared `lines` of the hunk one after the other, like `git diff` does.
+    fn render_unified(
+        &self,
+        f: &mut Renderer,
+        lines: &[(char, String)],
+        line_numbers: Option<&[(Option<usize>, Option<usize>)]>,
+        config: &Config,
+    ) -> fmt::Result {
+        use fmt::Write;
+        let width = number_width(line_numbers);
+        for (i, (c, content)) in lines.iter().enumerate() {
+            if let Some(ref selection) = self.selection {
+                if selection.cursor == i {
+                    f.insert_cursor();
+                }
+                f.write_str("\r\n")?;
+                if selection.marked.contains(&i) {
+                    write!(f, "{}", Attribute::Underlined)?;
+                }
+                if selection.cursor == i {
+                    write!(f, "{SetCursorStyle}")?;
+                }
+            } else {
+                f.write_str("\r\n")?;
+            }
+            if let Some(&(old, new)) = line_numbers.and_then(|n| n.get(i)) {
+                write!(
+                    f,
+                    "{}{:>width$} {:>width$} {}",
+                    SetAttribute(Attribute::Dim),
+                    line_number(old),
+                    line_number(new),
+                    SetAttribute(Attribute::NormalIntensity),
+                )?;
+            }
+            write!(
+                f,
+                "{}{c}{content}",
+                SetForegroundColor(line_color(*c, config))
+            )?;
+            if self.selection.is_some() {
+                write!(f, "{}{}", Attribute::NoReverse, Attribute::NoUnderline)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Renders the prepared `lines` of a hunk with the old version on the left and the new one on the
+/// right. Unchanged lines are on both sides, and removed and added lines are paired up, leaving a
+/// gap on the other side when there are more of one than the other.
+fn render_side_by_side(
+    f: &mut Renderer,
+    lines: &[(char, String)],
+    line_numbers: Option<&[(Option<usize>, Option<usize>)]>,
+    config: &Config,
+) -> fmt::Result {
+    use fmt::Write;
+    let mut rows = Vec::new();
+    let (mut removed, mut added) = (Vec::new(), Vec::new());
+    let flush = |rows: &mut Vec<(Option<usize>, Option<usize>)>,
+                 removed: &mut Vec<usize>,
+                 added: &mut Vec<usize>| {
+        for k in 0..removed.len().max(added.len()) {
+            rows.push((removed.get(k).copied(), added.get(k).copied()));
+        }
+        removed.clear();
+        added.clear();
+    };
+    // A "\\ No newline at end of file" goes on the same side as the line before it.
+    let mut side = ' ';
+    for (i, &(c, _)) in lines.iter().enumerate() {
+        side = if c == '\\' { side } else { c };
+        match side {
+            '-' => {
+                if !added.is_empty() {
+                    flush(&mut rows, &mut removed, &mut added);
+                }
+                removed.push(i);
+            }
+            '+' => added.push(i),
+            _ => {
+                flush(&mut rows, &mut removed, &mut added);
+                rows.push((Some(i), Some(i)));
+            }
+        }
+    }
+    flush(&mut rows, &mut removed, &mut added);
+
+    let term_width = crossterm::terminal::size().map_or(80, |(width, _)| usize::from(width));
+    let number_width = number_width(line_numbers);
+    let gutter = if line_numbers.is_some() {
+        number_width + 1
+    } else {
+        0
+    };
+    // Each side has its line number and the `+`, `-` or ` ` before its content.
+    let content_width = (term_width.saturating_sub(1) / 2).saturating_sub(gutter + 1);
+    let cell = |f: &mut Renderer, line: Option<usize>, old: bool| -> fmt::Result {
+        let Some(i) = line else {
+            return write!(f, "{}", " ".repeat(gutter + 1 + content_width));
+        };
+        if let Some(&(old_number, new_number)) = line_numbers.and_then(|n| n.get(i)) {
+            write!(
+                f,
+                "{}{:>number_width$} {}",
+                SetAttribute(Attribute::Dim),
+                line_number(if old { old_number } else { new_number }),
+                SetAttribute(Attribute::NormalIntensity),
+            )?;
+        }
+        let (c, content) = &lines[i];
+        write!(
+            f,
+            "{}{c}{}",
+            SetForegroundColor(line_color(*c, config)),
+            fit(content, content_width)
+        )
+    };
+    for (old, new) in rows {
+        f.write_str("\r\n")?;
+        cell(f, old, true)?;
+        write!(f, "{}│", SetForegroundColor(config.colors.foreground))?;
+        cell(f, new, false)?;
+    }
+    Ok(())
+}
+
+/// The content of a line of a hunk as it's shown, i.e. without the `+`, `-` or ` ` at the start,
+/// with matches of `search` or else syntax highlighting, whitespace errors, and tabs expanded.
+fn shown_content(line: &str, highlighted: Option<&str>, search: &str, config: &Config) -> String {
+    let raw_content = line.get(1..).unwrap_or_default();
+    // Matches are shown on the raw content, as the syntax highlighting would get in the way.
+    let search_matches = highlight_matches(raw_content, search, config);
+    let content = search_matches
+        .as_deref()
+        .unwrap_or_else(|| highlighted.unwrap_or(raw_content));
+    let ws_error_highlight = config.options.ws_error_highlight;
+    let ws_errors = match line.chars().next() {
+        Some('+') => ws_error_highlight.new,
+        Some('-') => ws_error_highlight.old,
+        _ => ws_error_highlight.context,
+    };
+    let content = if ws_errors {
+        format_whitespace_errors(content, config)
+    } else {
+        Cow::Borrowed(content)
+    };
+    expand_tabs(&content, config.options.tab_width).into_owned()
+}
+
+/// The colour of a line of a diff that starts with `c`.
+const fn line_color(c: char, config: &Config) -> Color {
+    match c {
+        '+' => config.colors.addition,
+        '-' => config.colors.deletion,
+        _ => config.colors.foreground,
+    }
+}
+
+/// How many columns the biggest of `line_numbers` takes up.
+fn number_width(line_numbers: Option<&[(Option<usize>, Option<usize>)]>) -> usize {
+    line_numbers
+        .into_iter()
+        .flatten()
+        .filter_map(|&(old, new)| old.max(new))
+        .max()
+        .map_or(0, |max| max.to_string().len())
+}
+
+/// A line number for the gutter, which is blank if the line isn't on that side.
+fn line_number(n: Option<usize>) -> String {
+    n.map_or_else(String::new, |n| n.to_string())
+}
+
+/// The `@@` header of a hunk as shown, with matches of `search` highlighted. Only the line ranges
+/// take the hunk header colour, not the heading after them, like the function a hunk is in.
+fn format_hunk_head(head: &str, search: &str, config: &Config) -> String {
+    // Split before highlighting matches, so the escapes of a match can't get in the way of
+    // finding the end of the ranges.
+    let (ranges, heading) = head.find(" @@").map_or((head, ""), |i| {
+        let end = head[i + 1..]
+            .find(|c| c != '@')
+            .map_or(head.len(), |n| i + 1 + n);
+        head.split_at(end)
+    });
+    let highlight = |s| highlight_matches(s, search, config).map_or(Cow::Borrowed(s), Cow::Owned);
+    format!(
+        "{}{ResetAttributes}{}",
+        highlight(ranges),
+        highlight(heading)
+    )
+}
+
+/// Whether `s` contains `query`. Case is ignored unless `query` has uppercase letters in it.
+fn matches_search(s: &str, query: &str) -> bool {
+    if query.chars().any(char::is_uppercase) {
+        s.contains(query)
+    } else {
+        s.to_ascii_lowercase().contains(query)
+    }
+}
+
+/// Gives `s` with each match of `query` given the search match background, or `None` if there are
+/// no matches. Case is ignored unless `query` has uppercase letters in it.
+fn highlight_matches(s: &str, query: &str, config: &Config) -> Option<String> {
+    use fmt::Write;
+    if query.is_empty() {
+        return None;
+    }
+    // Lowercasing only ASCII keeps the byte offsets the same as in `s`.
+    let haystack = if query.chars().any(char::is_uppercase) {
+        Cow::Borrowed(s)
+    } else {
+        Cow::Owned(s.to_ascii_lowercase())
+    };
+
+    let mut highlighted = String::new();
+    let mut last = 0;
+    for (start, matched) in haystack.match_indices(query) {
+        let end = start + matched.len();
+        highlighted.push_str(&s[last..start]);
+        write!(
+            highlighted,
+            "{}{}{}",
+            SetBackgroundColor(config.colors.search_match),
+            &s[start..end],
+            SetBackgroundColor(Color::Reset),
+        )
+        .expect("writing to a String can't fail");
+        last = end;
+    }
+    if last == 0 {
+        return None;
+    }
+    highlighted.push_str(&s[last..]);
+    Some(highlighted)
+}
+
+/// Counts the lines added and removed across `hunks`.
+fn sum_changes(hunks: &[String]) -> (usize, usize) {
+    hunks
+        .iter()
+        .map(|hunk| parse::count_changes(hunk))
+        .fold((0, 0), |(i, d), (hi, hd)| (i + hi, d + hd))
+}
+
+/// Writes the numbers of lines added and removed, like "+3 -1".
+fn write_stats(
+    f: &mut Renderer,
+    (insertions, deletions): (usize, usize),
+    config: &Config,
+) -> fmt::Result {
+    use fmt::Write;
+    write!(
+        f,
+        "{}+{insertions} {}-{deletions}{ResetAttributes}",
+        SetForegroundColor(config.colors.addition),
+        SetForegroundColor(config.colors.deletion),
+    )
+}
+
+/// What changed in a submodule, which is shown in place of its diff.
+#[derive(Debug, Default, Serialize)]
+struct Submodule {
+    /// The commit the submodule was at, or `None` if it was added.
+    old: Option<String>,
+    /// The commit the submodule is at now, or `None` if it was removed.
+    new: Option<String>,
+    /// The commits from `old` to `new` like `git diff --submodule=log` lists them, "> " and the
+    /// subject of each one added, or "< " and the subject of each one taken away.
+    commits: Vec<String>,
+    /// Whether tracked files in the submodule have been changed.
+    modified: bool,
+    /// Whether the submodule has untracked files.
+    untracked: bool,
+}
+
+impl Submodule {
+    /// Writes the lines shown in place of the submodule's diff.
+    fn render(&self, f: &mut Renderer, config: &Config) -> fmt::Result {
+        use fmt::Write;
+        let short = |hash: &str| hash[..7.min(hash.len())].to_string();
+        let commit = match (&self.old, &self.new) {
+            (Some(old), Some(new)) if old != new => {
+                Some(format!("Commit {} → {}", short(old), short(new)))
+            }
+            (None, Some(new)) => Some(format!("Added at {}", short(new))),
+            (Some(old), None) => Some(format!("Removed at {}", short(old))),
+            _ => None,
+        };
+        if let Some(commit) = commit {
+            write!(
+                f,
+                "\r\n{}{commit}{ResetAttributes}",
+                SetAttribute(Attribute::Dim)
+            )?;
+        }
+        for commit in &self.commits {
+            write!(
+                f,
+                "\r\n{}  {commit}{ResetAttributes}",
+                SetForegroundColor(if commit.starts_with('<') {
+                    config.colors.deletion
+                } else {
+                    config.colors.addition
+                })
+            )?;
+        }
+        for (changed, what) in [(self.modified, "Modified"), (self.untracked, "Untracked")] {
+            if changed {
+                write!(
+                    f,
+                    "\r\n{}{what} content{ResetAttributes}",
+                    SetAttribute(Attribute::Dim)
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How long ago a file in the work tree must have been modified for its diff to be reused.
+const RACY_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Above this many files, the whole diff is fetched rather than listing each file to diff.
+const MAX_PATHSPECS: usize = 1000;
+
+/// Runs `git <command> <diff_args>` for `files`, leaving out those in `unchanged`, and returns its
+/// output. Nothing is run if every file is unchanged.
+fn diff_changed(
+    command: &[&str],
+    diff_args: &[&str],
+    files: &[(&str, Option<&str>)],
+    unchanged: &HashSet<String>,
+) -> Result<String> {
+    let paths: Vec<&str> = files
+        .iter()
+        .filter(|(path, _)| !unchanged.contains(*path))
+        .flat_map(|&(path, orig_path)| iter::once(path).chain(orig_path))
+        .collect();
+    if paths.is_empty() {
+        return Ok(String::new());
+    }
+    let mut args = [&["--literal-pathspecs"], command, diff_args].concat();
+    if !unchanged.is_empty() && paths.len() <= MAX_PATHSPECS {
+        args.push("--");
+        args.extend(paths);
+    }
+    let output = git_process(&args)?;
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The subjects of the commits between the old and new commit of each submodule changed in
+/// `diffs`, keyed by the range, e.g. "3f4b5a6...e69de29". Submodules that aren't checked out, or
+/// don't have the commits, are left out.
+fn submodule_logs(diffs: &[&str]) -> HashMap<String, Vec<String>> {
+    let mut logs = HashMap::new();
+    for diff in diffs {
+        let Ok(diffs) = parse::parse_diff(diff) else {
+            continue;
+        };
+        for (path, diff) in &diffs {
+            let Some((Some(old), Some(new))) = diff
+                .hunks
+                .iter()
+                .find_map(|h| parse::parse_submodule_hunk(h))
+            else {
+                continue;
+            };
+            let range = format!("{old}...{new}");
+            if old == new || logs.contains_key(&range) {
+                continue;
             }
+            let Ok(output) =
+                git_process(&["-C", path, "log", "--left-right", "--format=%m %s", &range])
+            else {
+                continue;
+            };
+            if output.status.success() {
+                logs.insert(
+                    range,
+                    String::from_utf8_lossy(&output.stdout)
+                        .lines()
+                        .map(str::to_string)
+                        .collect(),
+                );
+            }
+        }
+    }
+    logs
+}
+
+/// Writes the line shown in place of the content of binary files.
+fn write_binary(f: &mut Renderer) -> fmt::Result {
+    use fmt::Write;
+    write!(
+        f,
+        "\r\n{}Binary file{ResetAttributes}",
+        SetAttribute(Attribute::Dim)
+    )
+}
+
+/// The characters of `s` that are shown, skipping escape sequences, with the byte offset each one
+/// ends at.
+fn shown_chars(s: &str) -> Vec<(char, usize)> {
+    struct Performer(Vec<(char, usize)>, usize);
+    impl vte::Perform for Performer {
+        fn print(&mut self, c: char) {
+            self.0.push((c, self.1));
+        }
+
+        fn execute(&mut self, byte: u8) {
+            self.0.push((char::from(byte), self.1));
+        }
+    }
+    let mut performer = Performer(Vec::new(), 0);
+    let mut parser = vte::Parser::new();
+    for (i, b) in s.bytes().enumerate() {
+        performer.1 = i + 1;
+        parser.advance(&mut performer, b);
+    }
+    performer.0
+}
+
+/// Gives `s` with each tab replaced by spaces up to the next multiple of `tab_width` columns,
+/// counting from the start of `s`. Escape sequences in `s` take up no columns, and wide
+/// characters, like CJK, take up two.
+pub fn expand_tabs(s: &str, tab_width: usize) -> Cow<'_, str> {
+    if !s.contains('\t') {
+        return Cow::Borrowed(s);
+    }
+    let tab_width = tab_width.max(1);
+    let mut expanded = String::new();
+    let mut last = 0;
+    let mut column = 0;
+    for (c, char_end) in shown_chars(s) {
+        if c == '\t' {
+            let width = tab_width - column % tab_width;
+            expanded.push_str(&s[last..char_end - 1]);
+            expanded.push_str(&" ".repeat(width));
+            column += width;
+        } else {
+            expanded.push_str(&s[last..char_end]);
+            column += c.width().unwrap_or(0);
+        }
+        last = char_end;
+    }
+    expanded.push_str(&s[last..]);
+    Cow::Owned(expanded)
+}
+
+/// Gives `s` padded with spaces or cut short to take up exactly `width` columns, ending in "…" if
+/// it was cut. Escape sequences in `s` take up no columns and are all kept, so that e.g. the end of
+/// a background colour isn't lost.
+fn fit(s: &str, width: usize) -> String {
+    let chars = shown_chars(s);
+    let columns: usize = chars.iter().map(|(c, _)| c.width().unwrap_or(0)).sum();
+    if columns <= width {
+        return format!("{s}{}", " ".repeat(width - columns));
+    }
+    let mut fitted = String::new();
+    let mut last = 0;
+    let mut columns = 0;
+    let mut cut = false;
+    for &(c, char_end) in &chars {
+        let char_start = char_end - c.len_utf8();
+        // Keep the escape sequences before every character, but only the characters that fit
+        // along with the "…".
+        fitted.push_str(&s[last..char_start]);
+        let char_width = c.width().unwrap_or(0);
+        if !cut && columns + char_width < width {
+            fitted.push(c);
+            columns += char_width;
+        } else if !cut && width > 0 {
+            // A wide character that doesn't fit leaves a gap before the "…".
+            fitted.push_str(&" ".repeat(width - 1 - columns));
+            fitted.push('…');
+            cut = true;
         }
-        write!(f, "{outbuf}")
+        last = char_end;
     }
+    fitted.push_str(&s[last..]);
+    fitted
 }
 
-fn format_trailing_whitespace<'s>(s: &'s str, config: &'_ Config) -> Cow<'s, str> {
-    let count_trailing_whitespace = s
-        .bytes()
+/// Gives `s` with its whitespace errors given the error background: whitespace at the end of the
+/// line, and spaces before tabs or tabs in the indentation, depending on the `whitespace` option.
+/// Escape sequences already in `s`, e.g. from the syntax highlighting, are left alone.
+fn format_whitespace_errors<'s>(s: &'s str, config: &'_ Config) -> Cow<'s, str> {
+    use fmt::Write;
+    let chars = shown_chars(s);
+
+    let whitespace = config.options.whitespace;
+    let indent = chars
+        .iter()
+        .take_while(|(c, _)| matches!(c, ' ' | '\t'))
+        .count();
+    let indent_chars = || chars[..indent].iter().map(|(c, _)| *c);
+    let indent_error = (whitespace.tab_in_indent && indent_chars().any(|c| c == '\t'))
+        || (whitespace.space_before_tab
+            && indent_chars()
+                .tuple_windows()
+                .any(|pair: (char, char)| pair == (' ', '\t')));
+    let trailing = chars
+        .iter()
         .rev()
-        .take_while(|c| c.is_ascii_whitespace())
+        .take_while(|(c, _)| c.is_ascii_whitespace())
         .count();
-    if count_trailing_whitespace > 0 {
-        Cow::Owned({
-            let mut line = s.to_string();
-            line.insert_str(
-                line.len() - count_trailing_whitespace,
-                &format!("{}", style::SetBackgroundColor(config.colors.error)),
-            );
-            line
-        })
+    // The errors are the first `indent_end` characters and those from `trailing_start` on.
+    let indent_end = if indent_error { indent } else { 0 };
+    let trailing_start = if whitespace.blank_at_eol {
+        chars.len() - trailing
     } else {
-        Cow::Borrowed(s)
+        chars.len()
+    };
+    if indent_end == 0 && trailing_start == chars.len() {
+        return Cow::Borrowed(s);
+    }
+
+    // Tabs have to be expanded afterwards, as they only move the cursor and wouldn't show the
+    // background.
+    let mut formatted = String::new();
+    let mut last = 0;
+    for (i, &(_, char_end)) in chars.iter().enumerate() {
+        if (i == 0 && indent_end > 0) || (i == trailing_start && i >= indent_end) {
+            write!(formatted, "{}", SetBackgroundColor(config.colors.error))
+                .expect("writing to a String can't fail");
+        }
+        formatted.push_str(&s[last..char_end]);
+        last = char_end;
+        if i + 1 == indent_end && indent_end < trailing_start {
+            write!(formatted, "{}", SetBackgroundColor(Color::Reset))
+                .expect("writing to a String can't fail");
+        }
     }
+    formatted.push_str(&s[last..]);
+    write!(formatted, "{}", SetBackgroundColor(Color::Reset))
+        .expect("writing to a String can't fail");
+    Cow::Owned(formatted)
 }
 
 impl Hunk {
-    pub const fn new(diff: String, expanded: bool) -> Self {
-        Self { diff, expanded }
+    pub const fn new(
+        diff: String,
+        expanded: bool,
+        syntax: Option<&'static SyntaxReference>,
+    ) -> Self {
+        Self {
+            diff,
+            expanded,
+            selection: None,
+            syntax,
+            highlighted: OnceCell::new(),
+            unsplit: None,
+        }
+    }
+
+    /// Indexes of the `+` and `-` lines of the hunk, counting from the line after the header.
+    fn changed_lines(&self) -> Vec<usize> {
+        self.diff
+            .lines()
+            .skip(1)
+            .positions(|l| l.starts_with(['+', '-']))
+            .collect()
+    }
+
+    /// The hunk without its `@@` header, which tells it apart from the other hunks of the file
+    /// even once staging some of them has moved its lines.
+    fn body(&self) -> &str {
+        self.diff.split_once('\n').map_or("", |(_, body)| body)
+    }
+
+    /// Starts choosing lines, with the cursor on the first changed line.
+    fn select_lines(&mut self) {
+        self.expanded = true;
+        self.selection = Some(LineSelection {
+            cursor: self.changed_lines().first().copied().unwrap_or_default(),
+            marked: BTreeSet::new(),
+        });
+    }
+
+    /// Moves the line cursor to the next changed line, if there is one.
+    fn line_down(&mut self) {
+        let changed_lines = self.changed_lines();
+        if let Some(ref mut selection) = self.selection {
+            if let Some(&next) = changed_lines.iter().find(|&&i| i > selection.cursor) {
+                selection.cursor = next;
+            }
+        }
+    }
+
+    /// Moves the line cursor to the previous changed line, if there is one.
+    fn line_up(&mut self) {
+        let changed_lines = self.changed_lines();
+        if let Some(ref mut selection) = self.selection {
+            if let Some(&previous) = changed_lines.iter().rev().find(|&&i| i < selection.cursor) {
+                selection.cursor = previous;
+            }
+        }
+    }
+
+    /// Marks or unmarks the line under the line cursor.
+    fn toggle_line(&mut self) {
+        if let Some(ref mut selection) = self.selection {
+            if !selection.marked.remove(&selection.cursor) {
+                selection.marked.insert(selection.cursor);
+            }
+        }
+    }
+
+    /// Builds a hunk of just the marked lines, or the line under the cursor if none are marked.
+    fn selected_lines(&self, reverse: bool) -> Result<String> {
+        let selection = self
+            .selection
+            .as_ref()
+            .context("no lines are being selected")?;
+        patch::select_lines(
+            &self.diff,
+            |i| {
+                if selection.marked.is_empty() {
+                    i == selection.cursor
+                } else {
+                    selection.marked.contains(&i)
+                }
+            },
+            reverse,
+        )
     }
 }
 
@@ -143,63 +861,222 @@ impl Expand for Hunk {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct FileDiff {
     path: String,
+    /// The path the file was renamed or copied from.
+    old_path: Option<String>,
+    /// What changed, if the file is a submodule.
+    submodule: Option<Submodule>,
+    /// The header of the file's diff, for building patches from its hunks.
+    header: String,
+    #[serde(skip)]
     expanded: bool,
+    /// Whether the file is marked, to be staged, unstaged or discarded along with the other
+    /// marked files.
+    #[serde(skip)]
+    marked: bool,
     hunks: Vec<Hunk>,
+    #[serde(skip)]
     cursor: usize,
     kind: DiffType,
+    /// Whether the file has both staged and unstaged changes, so it's listed in both sections,
+    /// each showing only its own part of the changes.
+    partial: bool,
+    /// Whether git found the file to be binary, so there's no diff to show.
+    binary: bool,
+    /// How similar, in percent, a renamed or copied file is to the original.
+    similarity: Option<u8>,
+    /// The number of lines added and removed.
+    stats: (usize, usize),
     // The implementation here involving this `selected` field is awful and hacky and I can't wait
     // to refactor it out.
+    #[serde(skip)]
     selected: bool,
 }
 
-impl render::Render for FileDiff {
-    fn render(&self, f: &mut Renderer) -> fmt::Result {
+impl FileDiff {
+    /// Renders the file and, if it's expanded, its hunks, highlighting matches of `search`. The
+    /// hunks are shown side by side if `side_by_side`.
+    pub fn render(&self, f: &mut Renderer, search: &str, side_by_side: bool) -> fmt::Result {
+        self.render_as(f, "", &self.path, search, side_by_side)
+    }
+
+    /// Renders the file like `render`, but labelled `name` and indented by `indent`, for showing
+    /// it in the tree of directories.
+    fn render_as(
+        &self,
+        f: &mut Renderer,
+        indent: &str,
+        name: &str,
+        search: &str,
+        side_by_side: bool,
+    ) -> fmt::Result {
         use fmt::Write;
         let config = CONFIG.get().expect("config wasn't initialised");
+        let label = self
+            .old_path
+            .as_ref()
+            .map_or_else(|| name.to_string(), |old| format!("{old} → {name}"));
+        write!(f, "\r{indent}{}", config.symbols.get(self.expanded))?;
+        if self.marked {
+            write!(
+                f,
+                "{}*{ResetAttributes} ",
+                SetForegroundColor(config.colors.key)
+            )?;
+        }
+        if matches!(self.kind, DiffType::Conflicted) {
+            write!(
+                f,
+                "{}[CONFLICT]{ResetAttributes} ",
+                SetForegroundColor(config.colors.error)
+            )?;
+        }
         write!(
             f,
-            "\r{}{}{}{ResetAttributes}",
-            if self.expanded { "⌄" } else { "›" },
-            match self.kind {
-                DiffType::Renamed => "[RENAME] ",
-                DiffType::Deleted => "[DELETE] ",
-                _ => "",
+            "{}{}{ResetAttributes}",
+            match (&self.kind, self.similarity) {
+                (DiffType::Renamed, Some(similarity)) => format!("[RENAME {similarity}%] "),
+                (DiffType::Renamed, None) => String::from("[RENAME] "),
+                (DiffType::Copied, Some(similarity)) => format!("[COPY {similarity}%] "),
+                (DiffType::Copied, None) => String::from("[COPY] "),
+                (DiffType::Deleted, _) => String::from("[DELETE] "),
+                (DiffType::TypeChanged, _) => String::from("[TYPECHANGE] "),
+                _ => String::new(),
             },
-            self.path,
+            highlight_matches(&label, search, config).unwrap_or(label),
         )?;
+        if self.submodule.is_some() {
+            write!(
+                f,
+                " {}(submodule){ResetAttributes}",
+                SetAttribute(Attribute::Dim)
+            )?;
+        }
+        if self.partial {
+            write!(
+                f,
+                " {}(partially staged){ResetAttributes}",
+                SetAttribute(Attribute::Dim)
+            )?;
+        }
+        // Without the hunks showing, how many there are tells how spread out the changes are.
+        if !self.expanded && !self.hunks.is_empty() {
+            write!(
+                f,
+                " {}({} hunk{}){ResetAttributes}",
+                SetAttribute(Attribute::Dim),
+                self.hunks.len(),
+                if self.hunks.len() == 1 { "" } else { "s" }
+            )?;
+        }
+        if self.stats != (0, 0) {
+            write!(f, " ")?;
+            write_stats(f, self.stats, config)?;
+        }
         if self.expanded {
-            if self.hunks.is_empty() {
-                if let Ok(file_content) = fs::read_to_string(&self.path) {
-                    let ws_error_highlight = config.options.ws_error_highlight;
-
-                    write!(f, "{ResetAttributes}")?;
-                    for l in file_content.lines() {
-                        write!(
-                            f,
-                            "\r\n{}+{l}",
-                            style::SetForegroundColor(config.colors.addition),
-                            l = if ws_error_highlight.new {
-                                format_trailing_whitespace(l, config)
-                            } else {
-                                Cow::Borrowed(l)
-                            }
-                        )?;
-                    }
+            if let Some(ref submodule) = self.submodule {
+                submodule.render(f, config)?;
+                if self.selected {
+                    f.insert_item_end();
+                }
+            } else if self.binary {
+                write_binary(f)?;
+                if self.selected {
+                    f.insert_item_end();
+                }
+            } else if self.hunks.is_empty()
+                // An exact rename or copy has no changes to show, rather than being all new.
+                && !matches!(self.kind, DiffType::Renamed | DiffType::Copied)
+            {
+                // Big files, like logs or generated data, would take too long to read and show.
+                let size = fs::metadata(worktree_path(&self.path)).map_or(0, |m| m.len());
+                if size > config.options.untracked_preview_bytes {
+                    write!(
+                        f,
+                        "{ResetAttributes}\r\n{}File too large to show ({size} bytes){ResetAttributes}",
+                        SetAttribute(Attribute::Dim)
+                    )?;
                     if self.selected {
                         f.insert_item_end();
                     }
+                } else {
+                    match fs::read(worktree_path(&self.path)) {
+                        Ok(file_content) => {
+                            let ws_error_highlight = config.options.ws_error_highlight;
+
+                            write!(f, "{ResetAttributes}")?;
+                            // Like git, treat files that contain a NUL byte as binary.
+                            let file_content = match String::from_utf8(file_content) {
+                                Ok(content) if !content.contains('\0') => content,
+                                _ => {
+                                    write_binary(f)?;
+                                    String::new()
+                                }
+                            };
+                            let mut lines = file_content.lines();
+                            for l in lines.by_ref().take(config.options.untracked_preview_lines) {
+                                write!(
+                                    f,
+                                    "\r\n{}+{l}",
+                                    SetForegroundColor(config.colors.addition),
+                                    l = expand_tabs(
+                                        &highlight_matches(l, search, config).map_or_else(
+                                            || {
+                                                if ws_error_highlight.new {
+                                                    format_whitespace_errors(l, config)
+                                                } else {
+                                                    Cow::Borrowed(l)
+                                                }
+                                            },
+                                            Cow::Owned
+                                        ),
+                                        config.options.tab_width
+                                    )
+                                )?;
+                            }
+                            let truncated = lines.count();
+                            if truncated > 0 {
+                                write!(
+                                    f,
+                                    "\r\n{}…(truncated, {truncated} more line{}){ResetAttributes}",
+                                    SetAttribute(Attribute::Dim),
+                                    if truncated == 1 { "" } else { "s" }
+                                )?;
+                            }
+                            if self.selected {
+                                f.insert_item_end();
+                            }
+                        }
+                        // Untracked directories have nothing to preview, but a file that can't be
+                        // read should say why rather than show nothing.
+                        Err(e) if !self.path.ends_with('/') => {
+                            write!(
+                                f,
+                                "{ResetAttributes}\r\n{}Couldn't read {}: {e}{ResetAttributes}",
+                                SetForegroundColor(config.colors.error),
+                                self.path
+                            )?;
+                            if self.selected {
+                                f.insert_item_end();
+                            }
+                        }
+                        Err(_) => {}
+                    }
                 }
             } else {
                 for (i, hunk) in self.hunks.iter().enumerate() {
-                    if self.selected && i + 1 == self.cursor {
+                    f.insert_sub_target(i + 1);
+                    if self.selected && i + 1 == self.cursor && hunk.selection.is_none() {
                         f.insert_cursor();
-                        write!(f, "{ResetAttributes}\r\n{}{hunk}", Attribute::Reverse)?;
+                        write!(f, "{ResetAttributes}\r\n{SetCursorStyle}")?;
+                        hunk.render(f, search, side_by_side)?;
                         f.insert_item_end();
                     } else {
-                        write!(f, "{ResetAttributes}\r\n{hunk}")?;
+                        write!(f, "{ResetAttributes}\r\n")?;
+                        hunk.render(f, search, side_by_side)?;
                     }
                 }
             }
@@ -212,14 +1089,105 @@ impl FileDiff {
     fn new(path: &str, kind: DiffType, expanded: bool, cursor: usize) -> Self {
         Self {
             path: path.to_string(),
+            old_path: None,
+            submodule: None,
+            header: String::new(),
             hunks: Vec::new(),
             selected: false,
+            marked: false,
             kind,
+            partial: false,
+            binary: false,
+            similarity: None,
+            stats: (0, 0),
             expanded,
             cursor,
         }
     }
 
+    /// The lines shown when the file is expanded but has no hunks, i.e. the start of an untracked
+    /// file, or nothing if its content isn't shown because it's binary, too large or unreadable.
+    fn preview_lines(&self, options: &Options) -> Vec<String> {
+        if self.submodule.is_some()
+            || self.binary
+            || !self.hunks.is_empty()
+            || matches!(self.kind, DiffType::Renamed | DiffType::Copied)
+        {
+            return Vec::new();
+        }
+        let path = worktree_path(&self.path);
+        if fs::metadata(&path).map_or(true, |m| m.len() > options.untracked_preview_bytes) {
+            return Vec::new();
+        }
+        match fs::read(path).map(String::from_utf8) {
+            Ok(Ok(content)) if !content.contains('\0') => content
+                .lines()
+                .take(options.untracked_preview_lines)
+                .map(String::from)
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The file's hunks as git gave them, undoing any splitting.
+    fn unsplit_hunks(&self) -> Vec<&str> {
+        patch::unsplit(
+            self.hunks
+                .iter()
+                .map(|hunk| (hunk.diff.as_str(), hunk.unsplit.as_deref())),
+        )
+    }
+
+    /// Creates a `FileDiff` showing all of `diff`, for diffs that aren't of the working tree, like
+    /// those of commits.
+    pub fn from_diff(path: &str, diff: &parse::Diff, options: &Options) -> Self {
+        let kind = if diff.header.contains("\nnew file mode") {
+            DiffType::Created
+        } else if diff.header.contains("\ndeleted file mode") {
+            DiffType::Deleted
+        } else if diff.header.contains("\nrename from") {
+            DiffType::Renamed
+        } else {
+            DiffType::Modified
+        };
+        let submodule = diff
+            .hunks
+            .iter()
+            .find_map(|h| parse::parse_submodule_hunk(h))
+            .map(|(old, new)| Submodule {
+                old: old.map(str::to_string),
+                new: new.map(str::to_string),
+                ..Submodule::default()
+            });
+        if submodule.is_some() {
+            return Self {
+                header: diff.header.clone(),
+                submodule,
+                ..Self::new(path, kind, true, 0)
+            };
+        }
+        let syntax = if options.syntax_highlighting && render::color_enabled() {
+            highlight::syntax_for_path(path)
+        } else {
+            None
+        };
+        Self {
+            header: diff.header.clone(),
+            hunks: diff
+                .hunks
+                .iter()
+                .map(|hunk| Hunk::new(hunk.clone(), true, syntax))
+                .collect(),
+            binary: diff.binary,
+            similarity: diff.similarity,
+            old_path: diff.old_path.clone(),
+            stats: sum_changes(&diff.hunks),
+            // Without hunks, e.g. for changes to only the mode, there's nothing to expand, and
+            // expanding would show the file in the working tree instead.
+            ..Self::new(path, kind, !diff.hunks.is_empty() || diff.binary, 0)
+        }
+    }
+
     /// Fails on the case that we are already on the first hunk
     fn up(&mut self) -> Result<(), ()> {
         self.cursor = self.cursor.checked_sub(1).ok_or(())?;
@@ -227,7 +1195,7 @@ impl FileDiff {
     }
 
     /// Fails on the case that we are already on the final hunk
-    fn down(&mut self) -> Result<(), ()> {
+    const fn down(&mut self) -> Result<(), ()> {
         if self.cursor + 1 >= self.len() {
             return Err(());
         }
@@ -236,16 +1204,16 @@ impl FileDiff {
     }
 
     /// Move the cursor to the topmost element of this `FileDiff`.
-    fn cursor_first(&mut self) {
+    const fn cursor_first(&mut self) {
         self.cursor = 0;
     }
 
     /// Move the cursor to the last element of this `FileDiff`, if it is expanded.
-    fn cursor_last(&mut self) {
+    const fn cursor_last(&mut self) {
         self.cursor = self.len() - 1;
     }
 
-    fn len(&self) -> usize {
+    const fn len(&self) -> usize {
         if self.expanded {
             self.hunks.len() + 1
         } else {
@@ -257,6 +1225,10 @@ impl FileDiff {
 impl Expand for FileDiff {
     fn toggle_expand(&mut self) {
         self.expanded = !self.expanded;
+        // A collapsed file has no hunks showing for the cursor to be on.
+        if !self.expanded {
+            self.cursor = 0;
+        }
     }
 
     fn expanded(&self) -> bool {
@@ -264,99 +1236,605 @@ impl Expand for FileDiff {
     }
 }
 
-// Enum for `Status.stage_or_unstage`
-#[derive(Clone, Copy)]
+/// An item in the list of files. In the tree view, this includes the directories the files are
+/// in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Row {
+    /// The directory made of the first `depth` components of the path of `file`, which is the
+    /// first file in it.
+    Dir {
+        file: usize,
+        depth: usize,
+    },
+    File(usize),
+}
+
+impl Row {
+    /// The index into `Status.file_diffs` of the file the row is, or is the directory of.
+    const fn file(self) -> usize {
+        match self {
+            Self::Dir { file, .. } | Self::File(file) => file,
+        }
+    }
+}
+
+/// The directories leading to `path`, leaving out the file's own name. Untracked directories are
+/// listed by git with a trailing `/`, and count as files here.
+fn dir_components(path: &str) -> Vec<&str> {
+    let mut components = path.trim_end_matches('/').split('/').collect::<Vec<_>>();
+    components.pop();
+    components
+}
+
+/// The index as it was before something was staged or unstaged, so that it can be undone.
+#[derive(Debug)]
+struct IndexSnapshot {
+    /// The commit HEAD was at, or empty if there were no commits yet.
+    head: String,
+    /// The tree written from the index by `git write-tree`.
+    tree: String,
+}
+
+impl IndexSnapshot {
+    /// Writes the index to a tree, which fails if it has unmerged entries, in which case there's
+    /// no snapshot.
+    fn take() -> Result<Option<Self>> {
+        let tree = git_process(&["write-tree"])?;
+        if !tree.status.success() {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            head: head_commit()?,
+            tree: String::from_utf8_lossy(&tree.stdout).trim().to_string(),
+        }))
+    }
+}
+
+/// The hash of the commit at HEAD, or empty if there are no commits yet.
+fn head_commit() -> Result<String> {
+    let head = git_process(&["rev-parse", "--verify", "--quiet", "HEAD"])?;
+    Ok(String::from_utf8_lossy(&head.stdout).trim().to_string())
+}
+
+// Enum for `Status.stage_or_unstage`
+#[derive(Clone, Copy)]
 enum Stage {
     Add,
     Reset,
 }
 
+/// The state of the repository and the changes in it. Only what describes the repository is
+/// serialised, for `--json`, leaving out how it's being displayed.
 #[derive(Debug, Default)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct Status {
-    pub branch: String,
+    /// The current branch, or `None` if HEAD is detached.
+    pub branch: Option<String>,
+    /// The upstream of the current branch, if it has one.
+    pub upstream: Option<String>,
+    /// Number of commits the current branch is ahead of its upstream.
+    pub ahead: usize,
+    /// Number of commits the current branch is behind its upstream.
+    pub behind: usize,
+    /// The abbreviated hash and subject of the commit at HEAD, or empty if there are no commits
+    /// yet.
     pub head: String,
+    /// The author of the commit at HEAD.
+    pub head_author: String,
+    /// How long ago the commit at HEAD was made, like "2 days ago".
+    pub head_date: String,
+    /// The number of stashes.
+    pub stashes: usize,
+    /// The operation in progress, e.g. a cherry-pick that stopped because of conflicts.
+    pub operation: Option<Operation>,
+    /// The path of the work tree, if the repository has more than one, so it's clear which one
+    /// this is.
+    pub worktree: Option<String>,
     pub file_diffs: Vec<FileDiff>,
+    /// The text to highlight and jump between matches of.
+    pub search: String,
+    /// Only files whose paths contain this, ignoring case, are listed, unless it's empty.
+    pub filter: String,
+    /// The files left out by `filter`, along with the index of the section they're in.
+    filtered_out: Vec<(usize, FileDiff)>,
+    /// Number of lines of context to show around changes in diffs.
+    pub context_lines: usize,
+    /// Whether changes to whitespace are left out of diffs.
+    pub ignore_whitespace: bool,
+    /// How similar, in percent, a deleted and a new file must be to be shown as a rename, or 0 to
+    /// never detect renames.
+    pub rename_threshold: u8,
+    /// Whether new files that are copies of changed ones are shown as copies, with the same
+    /// threshold as renames.
+    pub detect_copies: bool,
+    /// Whether untracked files are listed. If not, there's no section for them.
+    pub show_untracked: bool,
     pub count_untracked: usize,
     pub count_unstaged: usize,
     pub count_staged: usize,
     pub cursor: usize,
+    /// Whether files are grouped into a tree of the directories they're in.
+    pub tree: bool,
+    /// Whether hunks are shown with the old and new versions next to each other.
+    pub side_by_side: bool,
+    /// The directories collapsed in the tree view, along with the index of the section they're
+    /// in.
+    collapsed_dirs: BTreeSet<(usize, String)>,
+    /// If the cursor is on a directory in the tree view, the depth of it. The directory is that of
+    /// the file at `cursor`.
+    dir_cursor: Option<usize>,
+    /// The index before each stage and unstage, most recent last, to undo them.
+    undo: Vec<IndexSnapshot>,
+    /// Where the cursor goes once the status is updated, after staging or unstaging with
+    /// `advance_after_stage`.
+    advance: Option<Advance>,
+    /// Receives the output of the fetch running in the background, if there is one.
+    loading: Option<Receiver<Result<FetchOutput>>>,
+    /// What the diffs of the files shown depend on, so that the next fetch only diffs those that
+    /// have changed.
+    diff_cache: DiffCache,
+}
+
+impl Serialize for Status {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        /// The commit at HEAD.
+        #[derive(Serialize)]
+        struct Head<'a> {
+            hash: &'a str,
+            subject: &'a str,
+        }
+
+        /// A file, along with the section it's listed in.
+        #[derive(Serialize)]
+        struct File<'a> {
+            section: &'static str,
+            #[serde(flatten)]
+            file: &'a FileDiff,
+        }
+
+        let head = (!self.head.is_empty()).then(|| {
+            let (hash, subject) = self.head.split_once(' ').unwrap_or((&self.head, ""));
+            Head { hash, subject }
+        });
+        let files: Vec<_> = self
+            .sections()
+            .into_iter()
+            .zip(["untracked", "unstaged", "staged"])
+            .flat_map(|(section, name)| {
+                self.file_diffs[section].iter().map(move |file| File {
+                    section: name,
+                    file,
+                })
+            })
+            .collect();
+
+        let mut status = serializer.serialize_struct("Status", 14)?;
+        status.serialize_field("branch", &self.branch)?;
+        status.serialize_field("upstream", &self.upstream)?;
+        status.serialize_field("ahead", &self.ahead)?;
+        status.serialize_field("behind", &self.behind)?;
+        status.serialize_field("head", &head)?;
+        status.serialize_field("head_author", &self.head_author)?;
+        status.serialize_field("head_date", &self.head_date)?;
+        status.serialize_field("stashes", &self.stashes)?;
+        status.serialize_field("operation", &self.operation)?;
+        status.serialize_field("worktree", &self.worktree)?;
+        status.serialize_field("file_diffs", &files)?;
+        status.serialize_field("count_untracked", &self.count_untracked)?;
+        status.serialize_field("count_unstaged", &self.count_unstaged)?;
+        status.serialize_field("count_staged", &self.count_staged)?;
+        status.end()
+    }
+}
+
+/// What a file's diff depends on, so that it's only diffed again once this changes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct DiffKey {
+    /// The file's modes and object names in HEAD and the index, and where it was renamed or copied
+    /// from, if it was.
+    objects: String,
+    /// The size and modification time of the file in the work tree, for unstaged diffs.
+    stat: Option<(u64, Option<SystemTime>)>,
+}
+
+/// What the diffs of the unstaged and staged files depended on when they were fetched.
+#[derive(Debug, Clone, Default)]
+struct DiffCache {
+    /// The arguments the diffs were made with, as changing them changes every diff.
+    args: Vec<String>,
+    /// What the diff of each unstaged file, then each staged file, depended on, by its path.
+    /// Submodules and conflicted files are always diffed again, so they aren't here.
+    keys: [HashMap<String, DiffKey>; 2],
+}
+
+impl DiffCache {
+    /// The files of `section`, 0 for unstaged and 1 for staged, whose diffs can be reused from
+    /// when `previous` was fetched, as nothing they depend on has changed since.
+    fn unchanged(&self, previous: &Self, section: usize) -> HashSet<String> {
+        if self.args != previous.args {
+            return HashSet::new();
+        }
+        self.keys[section]
+            .iter()
+            .filter(|&(path, key)| previous.keys[section].get(path) == Some(key))
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+}
+
+/// What came after the item that was staged or unstaged, for the cursor to move on to.
+#[derive(Debug)]
+struct Advance {
+    /// The indices of the sections it was in, which the cursor stays in.
+    sections: Range<usize>,
+    /// The items listed after it, in order, to move to the first of that's still there. Each is
+    /// the path of a file and, if it's expanded, the body of one of its hunks.
+    items: Vec<(String, Option<String>)>,
+}
+
+/// The output of the git commands that the status is built from. These are run on a background
+/// thread, as they can take a while in big repositories.
+#[derive(Debug)]
+struct FetchOutput {
+    status: Output,
+    /// The diffs of the unstaged files, leaving out those in `unchanged`.
+    unstaged: String,
+    /// The diffs of the staged files, leaving out those in `unchanged`.
+    staged: String,
+    /// The unstaged and staged files whose diffs haven't changed since the last fetch, so weren't
+    /// diffed again.
+    unchanged: [HashSet<String>; 2],
+    /// What the diffs depend on, to compare against on the next fetch.
+    cache: DiffCache,
+    head: Output,
+    stashes: Output,
+    /// The commits each changed submodule moved between, from `submodule_logs`.
+    submodule_logs: HashMap<String, Vec<String>>,
+}
+
+impl FetchOutput {
+    fn run(
+        show_untracked: bool,
+        renames: &str,
+        copies: bool,
+        diff_args: &[&str],
+        previous: &DiffCache,
+    ) -> Result<Self> {
+        // Without taking the index lock, `git status` doesn't write back what it refreshed in the
+        // index, which would be seen as a change when watching for them.
+        let mut status_args = vec!["--no-optional-locks"];
+        // It only looks for copies when it's configured to.
+        if copies {
+            status_args.extend(["-c", "status.renames=copies"]);
+        }
+        status_args.extend(["status", "--porcelain=v2", "--branch", "-z", renames]);
+        if !show_untracked {
+            status_args.push("--untracked-files=no");
+        }
+        let status = git_process(&status_args)?;
+        let porcelain = String::from_utf8_lossy(&status.stdout);
+        let porcelain = parse::parse_status(&porcelain)?;
+
+        let mut cache = DiffCache {
+            args: diff_args.iter().map(ToString::to_string).collect(),
+            ..DiffCache::default()
+        };
+        // The unstaged and staged files, along with where they were renamed or copied from, which
+        // `git diff` needs to see that they were.
+        let mut files: [Vec<(&str, Option<&str>)>; 2] = Default::default();
+        for entry in &porcelain.entries {
+            match *entry {
+                StatusEntry::Changed {
+                    index,
+                    worktree,
+                    objects,
+                    path,
+                    orig_path,
+                    submodule,
+                } => {
+                    let key = |stat| DiffKey {
+                        objects: format!("{objects} {}", orig_path.unwrap_or_default()),
+                        stat,
+                    };
+                    // The commits a submodule moved between are looked up every time.
+                    let cached = submodule.is_none();
+                    if worktree != '.' {
+                        files[0].push((path, orig_path));
+                        let stat = fs::symlink_metadata(worktree_path(path))
+                            .ok()
+                            .map(|meta| (meta.len(), meta.modified().ok()));
+                        // A file changed again within the same tick of its modification time
+                        // would look unchanged, so one changed just now isn't trusted yet.
+                        let settled = stat.is_some_and(|(_, modified)| {
+                            modified
+                                .and_then(|modified| modified.elapsed().ok())
+                                .is_some_and(|elapsed| elapsed > RACY_INTERVAL)
+                        });
+                        if cached && settled {
+                            cache.keys[0].insert(path.to_string(), key(stat));
+                        }
+                    }
+                    if index != '.' {
+                        files[1].push((path, orig_path));
+                        if cached {
+                            cache.keys[1].insert(path.to_string(), key(None));
+                        }
+                    }
+                }
+                StatusEntry::Unmerged(path) => files[0].push((path, None)),
+                StatusEntry::Untracked(_) => {}
+            }
+        }
+        let unchanged = [cache.unchanged(previous, 0), cache.unchanged(previous, 1)];
+        let unstaged = diff_changed(&["diff"], diff_args, &files[0], &unchanged[0])?;
+        let staged = diff_changed(&["diff", "--cached"], diff_args, &files[1], &unchanged[1])?;
+        Ok(Self {
+            submodule_logs: submodule_logs(&[&unstaged, &staged]),
+            status,
+            unstaged,
+            staged,
+            unchanged,
+            cache,
+            head: git_process(&[
+                "log",
+                "HEAD",
+                "--pretty=format:%h %s%x00%an%x00%ar",
+                "-n",
+                "1",
+            ])?,
+            stashes: git_process(&["stash", "list"])?,
+        })
+    }
 }
 
 impl render::Render for Status {
     fn render(&self, f: &mut Renderer) -> Result<(), fmt::Error> {
         use fmt::Write;
         let config = CONFIG.get().expect("config wasn't initialised");
+        // Nothing has been fetched yet.
+        if self.branch.is_none() && self.head.is_empty() && self.is_loading() {
+            return write!(
+                f,
+                "\r{}Loading...{ResetAttributes}",
+                SetAttribute(Attribute::Dim)
+            );
+        }
+
         // Display the current branch
-        writeln!(
-            f,
-            "\rOn branch {}{}{}",
-            Attribute::Bold,
-            self.branch,
-            ResetAttributes,
-        )?;
+        match self.branch {
+            Some(ref branch) => write!(
+                f,
+                "\rOn branch {}{branch}{ResetAttributes}",
+                SetAttribute(Attribute::Bold),
+            )?,
+            None => write!(
+                f,
+                "\r{}HEAD detached at {}{ResetAttributes}",
+                SetForegroundColor(config.colors.error),
+                self.head.split_whitespace().next().unwrap_or_default(),
+            )?,
+        }
+        match self.upstream {
+            Some(ref upstream) => {
+                write!(
+                    f,
+                    " {}{upstream}{ResetAttributes}",
+                    SetAttribute(Attribute::Dim)
+                )?;
+                if self.ahead > 0 {
+                    write!(f, " ↑{}", self.ahead)?;
+                }
+                if self.behind > 0 {
+                    write!(f, " ↓{}", self.behind)?;
+                }
+            }
+            None if self.branch.is_some() => write!(
+                f,
+                " {}(no upstream){ResetAttributes}",
+                SetAttribute(Attribute::Dim)
+            )?,
+            None => {}
+        }
+        if let Some(ref worktree) = self.worktree {
+            let name = Path::new(worktree)
+                .file_name()
+                .map_or_else(|| worktree.clone(), |name| name.to_string_lossy().into());
+            write!(
+                f,
+                " {}in worktree {}{name}{ResetAttributes}",
+                SetAttribute(Attribute::Dim),
+                SetAttribute(Attribute::NormalIntensity),
+            )?;
+        }
+        if self.is_loading() {
+            write!(
+                f,
+                " {}(refreshing...){ResetAttributes}",
+                SetAttribute(Attribute::Dim)
+            )?;
+        }
+        writeln!(f)?;
+        if let Some(operation) = self.operation {
+            write!(
+                f,
+                "\r{}{}{} in progress",
+                SetForegroundColor(config.colors.error),
+                SetAttribute(Attribute::Bold),
+                operation.name(),
+            )?;
+            if let Operation::Rebase(Some((done, total))) = operation {
+                write!(f, " ({done}/{total})")?;
+            }
+            write!(f, ".{ResetAttributes} Resolve any conflicts, then press ")?;
+            let actions = if operation == Operation::Merge {
+                "continue or abort"
+            } else {
+                "continue, skip or abort"
+            };
+            match config.keys.operation.first() {
+                Some(key) => write!(
+                    f,
+                    "{}{key}{ResetAttributes} to {actions}.",
+                    SetForegroundColor(config.colors.key)
+                )?,
+                None => write!(f, "the key for `operation` to {actions}.")?,
+            }
+            writeln!(f)?;
+        }
+        if self.ignore_whitespace {
+            writeln!(
+                f,
+                "\r{}Ignoring whitespace changes{ResetAttributes}",
+                SetAttribute(Attribute::Dim)
+            )?;
+        }
+        if !self.show_untracked {
+            writeln!(
+                f,
+                "\r{}Hiding untracked files{ResetAttributes}",
+                SetAttribute(Attribute::Dim)
+            )?;
+        }
+
+        if self.stashes > 0 {
+            writeln!(
+                f,
+                "\r{}{} stash{}{ResetAttributes}",
+                SetAttribute(Attribute::Dim),
+                self.stashes,
+                if self.stashes == 1 { "" } else { "es" }
+            )?;
+        }
+
+        // Sum up the unstaged and staged changes, counting files that are in both once.
+        let tracked = &self.file_diffs[self.count_untracked..];
+        let files_changed = tracked.iter().map(|f| &f.path).unique().count();
+        if files_changed > 0 {
+            write!(
+                f,
+                "\r{} file{} changed, ",
+                files_changed,
+                if files_changed == 1 { "" } else { "s" }
+            )?;
+            let stats = tracked
+                .iter()
+                .fold((0, 0), |(i, d), file| (i + file.stats.0, d + file.stats.1));
+            write_stats(f, stats, config)?;
+            writeln!(f)?;
+        }
 
         // Display most recent commit
-        if !self.head.is_empty() {
+        if self.head.is_empty() {
+            writeln!(f, "\r\nNo commits yet")?;
+        } else {
             let mut head = self.head.split_whitespace();
-            writeln!(
+            write!(
                 f,
                 "{}\r\n{}{}{}",
-                Attribute::Dim,
+                SetAttribute(Attribute::Dim),
                 head.next().unwrap(), // !self.head.is_empty()
                 ResetAttributes,
                 head.map(|w| format!(" {w}")).collect::<String>()
             )?;
+            if !self.head_author.is_empty() {
+                write!(
+                    f,
+                    " {}({}, {}){ResetAttributes}",
+                    SetAttribute(Attribute::Dim),
+                    self.head_author,
+                    self.head_date
+                )?;
+            }
+            writeln!(f)?;
         }
 
-        if self.file_diffs.is_empty() {
+        if !self.filter.is_empty() {
+            writeln!(
+                f,
+                "\r\n{}{} \"{}\"{ResetAttributes}",
+                SetAttribute(Attribute::Dim),
+                if self.file_diffs.is_empty() {
+                    "No files match"
+                } else {
+                    "Showing files matching"
+                },
+                self.filter,
+            )?;
+        } else if self.file_diffs.is_empty() {
             write!(
                 f,
                 "\r\n{}nothing to commit, working tree clean{}",
-                style::SetForegroundColor(config.colors.heading),
-                style::SetForegroundColor(config.colors.foreground)
+                SetForegroundColor(config.colors.heading),
+                SetForegroundColor(config.colors.foreground)
             )?;
             drop(stdout().flush());
         }
 
-        for (index, file) in self.file_diffs.iter().enumerate() {
-            if index == 0 && self.count_untracked != 0 {
-                writeln!(
-                    f,
-                    "\r\n{}Untracked files{} {}({}){}",
-                    style::SetForegroundColor(config.colors.heading),
-                    ResetColor,
-                    style::Attribute::Dim,
-                    self.count_untracked,
-                    ResetAttributes
-                )?;
-            } else if index == self.count_untracked && self.count_unstaged != 0 {
-                writeln!(
-                    f,
-                    "\r\n{}Unstaged changes{} {}({}){}",
-                    style::SetForegroundColor(config.colors.heading),
-                    ResetColor,
-                    style::Attribute::Dim,
-                    self.count_unstaged,
-                    ResetAttributes
-                )?;
-            } else if index == self.count_untracked + self.count_unstaged {
+        let sections = self.sections();
+        let mut section = None;
+        for (i, row) in self.rows().into_iter().enumerate() {
+            let index = row.file();
+            let file = &self.file_diffs[index];
+            // Every file is in one of the sections.
+            let row_section = sections
+                .iter()
+                .position(|s| s.contains(&index))
+                .unwrap_or_default();
+            if section != Some(row_section) {
+                f.clear_target();
+                let (heading, count) = match row_section {
+                    0 => ("Untracked files", self.count_untracked),
+                    1 => ("Unstaged changes", self.count_unstaged),
+                    _ => ("Staged changes", self.count_staged),
+                };
                 writeln!(
                     f,
-                    "\r\n{}Staged changes{} {}({}){}",
-                    style::SetForegroundColor(config.colors.heading),
+                    "\r\n{}{heading}{} {}({count}){}",
+                    SetForegroundColor(config.colors.heading),
                     ResetColor,
-                    style::Attribute::Dim,
-                    self.count_staged,
+                    SetAttribute(Attribute::Dim),
                     ResetAttributes
                 )?;
+                section = Some(row_section);
             }
 
-            if file.cursor == 0 && self.cursor == index {
+            f.insert_target(i);
+            if file.cursor == 0 && self.cursor_row() == row {
                 f.insert_cursor();
-                write!(f, "{}", Attribute::Reverse)?;
+                write!(f, "{SetCursorStyle}")?;
+            }
+            let dirs = dir_components(&file.path);
+            match row {
+                Row::Dir { depth, .. } => {
+                    let dir = dirs[..depth].join("/");
+                    let count = self.dir_files(row_section, &dir).len();
+                    write!(
+                        f,
+                        "\r{}{}{}/ {}({count}){ResetAttributes}",
+                        "  ".repeat(depth - 1),
+                        config
+                            .symbols
+                            .get(!self.collapsed_dirs.contains(&(row_section, dir))),
+                        highlight_matches(dirs[depth - 1], &self.search, config)
+                            .unwrap_or_else(|| dirs[depth - 1].to_string()),
+                        SetAttribute(Attribute::Dim),
+                    )?;
+                }
+                Row::File(_) if self.tree => {
+                    let name = file
+                        .path
+                        .get(dirs.iter().map(|d| d.len() + 1).sum::<usize>()..)
+                        .unwrap_or_default();
+                    file.render_as(
+                        f,
+                        &"  ".repeat(dirs.len()),
+                        name,
+                        &self.search,
+                        self.side_by_side,
+                    )?;
+                }
+                Row::File(_) => file.render(f, &self.search, self.side_by_side)?,
             }
-            write!(f, "\r    ")?;
-            file.render(f)?;
             writeln!(f, "{ResetAttributes}")?;
         }
 
@@ -365,27 +1843,133 @@ impl render::Render for Status {
 }
 
 impl Status {
-    pub fn new(repo: &Repository, options: &Options) -> Result<Self> {
-        let mut status = Self::default();
-        status.fetch(repo, options)?;
-        Ok(status)
+    pub fn new(options: &Options) -> Self {
+        let mut status = Self {
+            context_lines: options.context_lines,
+            rename_threshold: options.rename_threshold,
+            detect_copies: options.detect_copies,
+            tree: options.tree_view,
+            side_by_side: options.side_by_side,
+            show_untracked: options.show_untracked,
+            ..Self::default()
+        };
+        status.fetch();
+        status
     }
 
-    pub fn fetch(&mut self, repo: &Repository, options: &Options) -> Result<()> {
-        // Leaving ourselves a lot of room to optimise and tidy up in here :D
-        let output = git_process(&["status"])?;
+    /// A status for another work tree, after changing to it. How things are displayed carries
+    /// over, but not the files or what can be undone, as they belong to this work tree.
+    pub fn for_worktree(&self) -> Self {
+        let mut status = Self {
+            context_lines: self.context_lines,
+            ignore_whitespace: self.ignore_whitespace,
+            rename_threshold: self.rename_threshold,
+            detect_copies: self.detect_copies,
+            show_untracked: self.show_untracked,
+            tree: self.tree,
+            side_by_side: self.side_by_side,
+            ..Self::default()
+        };
+        status.fetch();
+        status
+    }
+
+    /// Starts fetching the status in the background. Once it's done, it's picked up by
+    /// `poll_fetch`. Starting a new fetch abandons any that is still running.
+    pub fn fetch(&mut self) {
+        let context = format!("-U{}", self.context_lines);
+        let ignore_whitespace = self.ignore_whitespace;
+        let show_untracked = self.show_untracked;
+        // Both `git status` and `git diff` take these, so that they agree on what's a rename.
+        let renames = match self.rename_threshold {
+            0 => String::from("--no-renames"),
+            threshold => format!("--find-renames={}%", threshold.min(100)),
+        };
+        // Copies are only looked for along with renames.
+        let copies = (self.detect_copies && self.rename_threshold > 0)
+            .then(|| format!("--find-copies={}%", self.rename_threshold.min(100)));
+        let diff_cache = self.diff_cache.clone();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut diff_args = vec!["--no-ext-diff", &context, &renames];
+            if let Some(ref copies) = copies {
+                diff_args.push(copies);
+            }
+            if ignore_whitespace {
+                diff_args.push("--ignore-all-space");
+            }
+            // If the receiver is gone, a newer fetch has replaced this one.
+            drop(sender.send(FetchOutput::run(
+                show_untracked,
+                &renames,
+                copies.is_some(),
+                &diff_args,
+                &diff_cache,
+            )));
+        });
+        self.loading = Some(receiver);
+    }
+
+    pub const fn is_loading(&self) -> bool {
+        self.loading.is_some()
+    }
+
+    /// Updates the status from the fetch running in the background if it has finished. Returns
+    /// whether it had.
+    pub fn poll_fetch(&mut self, repo: &Repository, options: &Options) -> Result<bool> {
+        let Some(ref loading) = self.loading else {
+            return Ok(false);
+        };
+        let output = match loading.try_recv() {
+            Ok(output) => output,
+            Err(TryRecvError::Empty) => return Ok(false),
+            Err(TryRecvError::Disconnected) => {
+                self.loading = None;
+                bail!("fetching the status stopped unexpectedly");
+            }
+        };
+        self.loading = None;
+        self.update(repo, options, &output?)?;
+        Ok(true)
+    }
+
+    /// Waits for the fetch running in the background to finish, and updates the status from it.
+    pub fn wait_fetch(&mut self, repo: &Repository, options: &Options) -> Result<()> {
+        let Some(loading) = self.loading.take() else {
+            return Ok(());
+        };
+        let output = loading
+            .recv()
+            .context("fetching the status stopped unexpectedly")?;
+        self.update(repo, options, &output?)
+    }
 
-        let input =
-            std::str::from_utf8(&output.stdout).context("malformed stdout from `git status`")?;
+    /// Moves the cursor off every file, for when the status is printed rather than navigated.
+    pub fn hide_cursor(&mut self) {
+        if let Some(file) = self.file_diffs.get_mut(self.cursor) {
+            file.selected = false;
+        }
+        self.cursor = self.file_diffs.len();
+        self.dir_cursor = None;
+    }
+
+    /// Rebuilds the status from the `output` of a fetch.
+    fn update(&mut self, repo: &Repository, options: &Options, output: &FetchOutput) -> Result<()> {
+        // Leaving ourselves a lot of room to optimise and tidy up in here :D
+        // Paths aren't necessarily valid UTF-8, but we'd still like to display them.
+        let input = String::from_utf8_lossy(&output.status.stdout);
+        let porcelain = parse::parse_status(&input)?;
+        // Until the diffs are all in place, the next fetch can't reuse any of them.
+        self.diff_cache = DiffCache::default();
 
-        // TODO: When head().is_branch() is false, we should do something different. For example,
-        // use `branch: Option<String>` in `Status` and display something different when head
-        // detached or on tag, etc.
         let branch = match repo.head() {
-            Ok(head) => head
-                .shorthand()
-                .context("no name found for current HEAD")?
-                .to_string(),
+            Ok(head) if head.is_branch() => Some(
+                head.shorthand()
+                    .context("no name found for current HEAD")?
+                    .to_string(),
+            ),
+            // HEAD is detached.
+            Ok(_) => None,
             Err(e) => {
                 // git2 doesn't provide any API to get the name of an unborn branch, so we have to
                 // read it directly :(
@@ -393,142 +1977,177 @@ impl Status {
                     let mut head_path = repo.path().to_path_buf();
                     head_path.push("HEAD");
                     fs::read_to_string(&head_path)
-                        .with_context(|| format!("couldn't read file: {head_path:?}"))?
+                        .with_context(|| format!("couldn't read file: {}", head_path.display()))?
                         .lines()
                         .next()
-                        .with_context(|| format!("no ref found in {head_path:?}"))?
+                        .with_context(|| format!("no ref found in {}", head_path.display()))?
                         .trim()
                         .strip_prefix("ref: refs/heads/")
-                        .with_context(|| format!("unexpected ref path found in {head_path:?}"))?
+                        .with_context(|| {
+                            format!("unexpected ref path found in {}", head_path.display())
+                        })?
                         .to_string()
+                        .into()
                 } else {
                     return Err(Error::new(e)).context("failed to get name of current branch");
                 }
             }
         };
 
+        self.worktree = (repo.is_worktree() || repo.worktrees().is_ok_and(|w| !w.is_empty()))
+            .then(|| repo.workdir().map(|dir| dir.display().to_string()))
+            .flatten();
+
         let mut untracked = Vec::new();
         let mut staged = Vec::new();
         let mut unstaged = Vec::new();
 
-        let mut lines = input.lines();
-        while let Some(line) = lines.next() {
-            if line == "Untracked files:" {
-                // (use "git add <file>..." to include in what will be committed)
-                lines.next().context("strange `git status` output")?;
-                for line in lines.by_ref() {
-                    if line.is_empty() {
-                        break;
+        let sections = self.sections();
+        // The file under the cursor and which section it's in, to find it again afterwards.
+        let cursor_file = self.file_diffs.get(self.cursor).and_then(|file| {
+            let section = sections.iter().position(|s| s.contains(&self.cursor))?;
+            Some((file.path.clone(), section))
+        });
+        let cursor_dir = self.cursor_dir();
+        // Put back the files left out by the filter, so they keep how they were shown too.
+        self.unfilter();
+        let sections = self.sections();
+        for entry in porcelain.entries {
+            match entry {
+                StatusEntry::Untracked(path) => untracked.push(self.new_file_diff(
+                    path,
+                    DiffType::Untracked,
+                    sections[0].clone(),
+                    options,
+                )),
+                StatusEntry::Changed {
+                    index,
+                    worktree,
+                    objects: _,
+                    path,
+                    orig_path,
+                    submodule,
+                } => {
+                    // The original path belongs to whichever side the rename or copy is on.
+                    let old_path = |code| {
+                        matches!(code, 'R' | 'C')
+                            .then(|| orig_path.map(str::to_string))
+                            .flatten()
+                    };
+                    // The unstaged entry shows `git diff` and the staged one `git diff --cached`,
+                    // so each has only its own part of the changes.
+                    let partial = worktree != '.' && index != '.';
+                    if worktree != '.' {
+                        unstaged.push(FileDiff {
+                            partial,
+                            old_path: old_path(worktree),
+                            // Changes within the submodule can only be made in the work tree.
+                            submodule: submodule.map(|state| Submodule {
+                                modified: state.modified,
+                                untracked: state.untracked,
+                                ..Submodule::default()
+                            }),
+                            ..self.new_file_diff(
+                                path,
+                                DiffType::from_status_code(worktree),
+                                sections[1].clone(),
+                                options,
+                            )
+                        });
                     }
-                    let path = line.trim_start();
-                    let previous_entry = self
-                        .file_diffs
-                        .iter()
-                        .take(self.count_untracked)
-                        .find(|f| f.path == path);
-                    untracked.push(FileDiff::new(
-                        path,
-                        DiffType::Untracked,
-                        previous_entry.map_or(options.auto_expand_files, |f| f.expanded),
-                        previous_entry.map_or(0, |f| f.cursor),
-                    ));
-                }
-            } else if line == "Changes not staged for commit:" {
-                // (use "git add <file>..." to update what will be committed)
-                // (use "git restore <file>..." to discard changes in working directory)
-                lines.next().context("strange `git status` output")?;
-                lines.next().context("strange `git status` output")?;
-                for line in lines.by_ref() {
-                    if line.is_empty() {
-                        break;
+                    if index != '.' {
+                        staged.push(FileDiff {
+                            partial,
+                            old_path: old_path(index),
+                            submodule: submodule.map(|_| Submodule::default()),
+                            ..self.new_file_diff(
+                                path,
+                                DiffType::from_status_code(index),
+                                sections[2].clone(),
+                                options,
+                            )
+                        });
                     }
-
-                    let parse_result: IResult<&str, &str> = take_until("  ")(line.trim_start());
-                    let (line, prefix) = parse_result
-                        .map_err(|e| e.to_owned())
-                        .context("strange diff output")?;
-
-                    let path = line.trim_start();
-                    let previous_entry = self
-                        .file_diffs
-                        .iter()
-                        .skip(self.count_untracked)
-                        .take(self.count_unstaged)
-                        .find(|f| f.path == path);
-                    unstaged.push(FileDiff::new(
-                        path,
-                        match prefix {
-                            "" => DiffType::Untracked,        // untracked files
-                            "new file:" => DiffType::Created, // staged new files
-                            "modified:" => DiffType::Modified,
-                            "renamed:" => DiffType::Renamed,
-                            "deleted:" => DiffType::Deleted,
-                            _ => {
-                                return Err(anyhow!(
-                                    "unknown file prefix in `git status` output: `{prefix}`"
-                                ))
-                            }
-                        },
-                        previous_entry.map_or(options.auto_expand_files, |f| f.expanded),
-                        previous_entry.map_or(0, |f| f.cursor),
-                    ));
                 }
-            } else if line == "Changes to be committed:" {
-                // (use "git restore --staged <file>..." to unstage)
-                lines.next().context("strange `git status` output")?;
-                for line in lines.by_ref() {
-                    if line.is_empty() {
-                        break;
-                    }
-
-                    let parse_result: IResult<&str, &str> = take_until("  ")(line.trim_start());
-                    let (line, prefix) = parse_result
-                        .map_err(|e| e.to_owned())
-                        .context("strange `git status` output")?;
+                // Conflicts are resolved by staging the file, so they're shown with the unstaged
+                // changes.
+                StatusEntry::Unmerged(path) => unstaged.push(self.new_file_diff(
+                    path,
+                    DiffType::Conflicted,
+                    sections[1].clone(),
+                    options,
+                )),
+            }
+        }
 
-                    let path = line.trim_start();
-                    let previous_entry = self
-                        .file_diffs
-                        .iter()
-                        .skip(self.count_untracked + self.count_unstaged)
-                        .find(|f| f.path == path);
-                    staged.push(FileDiff::new(
-                        path,
-                        match prefix {
-                            "" => DiffType::Untracked,        // untracked files
-                            "new file:" => DiffType::Created, // staged new files
-                            "modified:" => DiffType::Modified,
-                            "renamed:" => DiffType::Renamed,
-                            "deleted:" => DiffType::Deleted,
-                            _ => {
-                                return Err(anyhow!(
-                                    "unknown file prefix in `git status` output: `{prefix}`"
-                                ))
-                            }
-                        },
-                        previous_entry.map_or(options.auto_expand_files, |f| f.expanded),
-                        previous_entry.map_or(0, |f| f.cursor),
-                    ));
+        for (files, section, name, diff, unchanged) in [
+            (
+                &mut unstaged,
+                1,
+                "unstaged",
+                &output.unstaged,
+                &output.unchanged[0],
+            ),
+            (
+                &mut staged,
+                2,
+                "staged",
+                &output.staged,
+                &output.unchanged[1],
+            ),
+        ] {
+            let previous = &self.file_diffs[sections[section].clone()];
+            let missing = Self::populate_diffs(
+                files,
+                previous,
+                &self.file_diffs,
+                diff,
+                unchanged,
+                &output.submodule_logs,
+                options,
+            )
+            .with_context(|| format!("failed to populate {name} file diffs"))?;
+            // Files whose diffs were meant to be reused but can't be found are diffed after all.
+            if !missing.is_empty() {
+                let mut args = vec!["--literal-pathspecs", "diff"];
+                if section == 2 {
+                    args.push("--cached");
                 }
+                args.extend(output.cache.args.iter().map(String::as_str));
+                args.push("--");
+                args.extend(missing.iter().map(String::as_str));
+                let diff = String::from_utf8_lossy(&git_process(&args)?.stdout).into_owned();
+                Self::populate_diffs(
+                    files,
+                    &[],
+                    &self.file_diffs,
+                    &diff,
+                    &HashSet::new(),
+                    &output.submodule_logs,
+                    options,
+                )?;
             }
         }
-
-        // Get the diff information for unstaged changes
-        let diff = git_process(&["diff", "--no-ext-diff"])?;
-        Self::populate_diffs(&mut unstaged, &self.file_diffs, &diff, options)
-            .context("failed to populate unstaged file diffs")?;
-
-        // Get the diff information for staged changes
-        let diff = git_process(&["diff", "--cached", "--no-ext-diff"])?;
-        Self::populate_diffs(&mut staged, &self.file_diffs, &diff, options)
-            .context("failed to populate unstaged file diffs")?;
+        self.diff_cache = output.cache.clone();
 
         self.branch = branch;
-        self.head = std::str::from_utf8(
-            &git_process(&["log", "HEAD", "--pretty=format:%h %s", "-n", "1"])?.stdout,
-        )
-        .context("invalid utf8 from `git log`")?
-        .to_string();
+        self.operation = Operation::from_repo(repo);
+        (self.upstream, self.ahead, self.behind) = porcelain
+            .tracking
+            .map_or((None, 0, 0), |(upstream, ahead, behind)| {
+                (Some(upstream.to_string()), ahead, behind)
+            });
+        let head = String::from_utf8_lossy(&output.head.stdout);
+        let mut head = head.split('\0').map(str::to_string);
+        self.head = head.next().unwrap_or_default();
+        self.head_author = head.next().unwrap_or_default();
+        self.head_date = head.next().unwrap_or_default();
+        self.stashes = output
+            .stashes
+            .stdout
+            .split(|&b| b == b'\n')
+            .filter(|l| !l.is_empty())
+            .count();
         self.count_untracked = untracked.len();
         self.count_staged = staged.len();
         self.count_unstaged = unstaged.len();
@@ -537,6 +2156,8 @@ impl Status {
         self.file_diffs.append(&mut unstaged);
         self.file_diffs.append(&mut staged);
 
+        self.restore_cursor(cursor_file, cursor_dir);
+
         for file_diff in self.file_diffs.iter_mut().filter(|f| f.cursor >= f.len()) {
             file_diff.cursor = file_diff.len() - 1;
         }
@@ -548,149 +2169,1243 @@ impl Status {
         if let Some(file_diff) = self.file_diffs.get_mut(self.cursor) {
             file_diff.selected = true;
         }
+        self.apply_filter();
+        if let Some(advance) = self.advance.take() {
+            self.advance_cursor(&advance);
+        }
+        self.reveal_cursor();
 
         Ok(())
     }
 
-    /// Takes a vec `file_diffs` containing `FileDiff` elements that have only the name populated,
-    /// and populates their hunks based on the parsing of `diff`, and the `prev_file_diffs`.
-    fn populate_diffs(
-        file_diffs: &mut Vec<FileDiff>,
-        prev_file_diffs: &[FileDiff],
-        diff: &Output,
-        options: &Options,
-    ) -> Result<()> {
-        let diff = std::str::from_utf8(&diff.stdout).context("malformed stdout from `git diff`")?;
-        let hunks = parse::parse_diff(diff)?;
-        for file in file_diffs {
-            if let Some(hunks) = hunks.get(file.path.as_str()) {
-                // Get all the diffs entries of this file from the previous iteration.
-                let previous_file_entries = prev_file_diffs.iter().filter(|f| f.path == file.path);
-                file.hunks = hunks
-                    .iter()
-                    .map(|hunk| {
-                        let expanded = previous_file_entries
-                            .clone()
-                            .find_map(|f| {
-                                f.hunks.iter().find(|h| {
-                                    let h_header =
-                                        h.diff.lines().next().expect("hunk should never be empty");
-                                    let hunk_header =
-                                        hunk.lines().next().expect("hunk should never be empty");
-                                    (parse_hunk_new(h_header).unwrap_or_else(|e| panic!("{e:?}"))
-                                        == parse_hunk_new(hunk_header)
-                                            .unwrap_or_else(|e| panic!("{e:?}")))
-                                        || (parse_hunk_old(h_header)
-                                            .unwrap_or_else(|e| panic!("{e:?}"))
-                                            == parse_hunk_old(hunk_header)
-                                                .unwrap_or_else(|e| panic!("{e:?}")))
-                                })
-                            })
-                            .map_or(options.auto_expand_hunks, |h| h.expanded);
+    /// The ranges of `file_diffs` holding untracked, unstaged and staged files respectively.
+    const fn sections(&self) -> [Range<usize>; 3] {
+        let unstaged_end = self.count_untracked + self.count_unstaged;
+        [
+            0..self.count_untracked,
+            self.count_untracked..unstaged_end,
+            unstaged_end..self.file_diffs.len(),
+        ]
+    }
 
-                        Hunk::new(hunk.clone(), expanded)
-                    })
-                    .collect();
+    /// The rows that are shown, in order. These are the files, and in the tree view, the
+    /// directories they're in, leaving out those inside collapsed directories.
+    fn rows(&self) -> Vec<Row> {
+        if !self.tree {
+            return (0..self.file_diffs.len()).map(Row::File).collect();
+        }
+        let mut rows = Vec::new();
+        for (section, range) in self.sections().into_iter().enumerate() {
+            let mut previous = Vec::new();
+            // The depth of the collapsed directory the files are in, if they are.
+            let mut collapsed = None;
+            for i in range {
+                let dirs = dir_components(&self.file_diffs[i].path);
+                // Git lists files sorted by path, so files in the same directory are together.
+                let common = previous
+                    .iter()
+                    .zip(&dirs)
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if collapsed.is_some_and(|depth| common < depth) {
+                    collapsed = None;
+                }
+                for depth in common + 1..=dirs.len() {
+                    if collapsed.is_none() {
+                        rows.push(Row::Dir { file: i, depth });
+                        if self
+                            .collapsed_dirs
+                            .contains(&(section, dirs[..depth].join("/")))
+                        {
+                            collapsed = Some(depth);
+                        }
+                    }
+                }
+                if collapsed.is_none() {
+                    rows.push(Row::File(i));
+                }
+                previous = dirs;
             }
         }
-        Ok(())
+        rows
     }
 
-    fn stage_or_unstage(&mut self, command: Stage) -> Result<()> {
-        if self.file_diffs.is_empty() {
-            return Ok(());
+    /// The row the cursor is on.
+    const fn cursor_row(&self) -> Row {
+        match self.dir_cursor {
+            Some(depth) => Row::Dir {
+                file: self.cursor,
+                depth,
+            },
+            None => Row::File(self.cursor),
         }
+    }
 
-        let file = self
-            .file_diffs
-            .get_mut(self.cursor)
-            .context("cursor is at invalid position")?;
-        file.selected = false;
-
-        match file.cursor {
-            0 => {
-                let args = match command {
-                    Stage::Add => vec!["add", &file.path],
-                    Stage::Reset => match file.kind {
-                        DiffType::Deleted => vec!["reset", "HEAD", &file.path],
-                        _ => vec!["reset", &file.path],
-                    },
-                };
-                git_process(&args)?;
-            }
-            i => {
-                let mut patch = Command::new("git")
-                    .args(match command {
-                        Stage::Add => ["add", "-p", &file.path],
-                        Stage::Reset => ["reset", "-p", &file.path],
-                    })
-                    .stdin(Stdio::piped())
-                    .stdout(Stdio::null())
-                    .stderr(Stdio::piped())
-                    .spawn()
-                    .context("failed to spawn interactive git process")?;
-
-                let mut stdin = patch.stdin.take().context("failed to open child stdin")?;
+    /// The section and path of the directory under the cursor, if it's on one.
+    fn cursor_dir(&self) -> Option<(usize, String)> {
+        let depth = self.dir_cursor?;
+        let file = self.file_diffs.get(self.cursor)?;
+        let section = self
+            .sections()
+            .iter()
+            .position(|s| s.contains(&self.cursor))?;
+        Some((section, dir_components(&file.path)[..depth].join("/")))
+    }
 
-                let mut bufs = vec![b"n\n"; i - 1];
-                bufs.push(b"y\n");
+    /// The paths of the marked files in the given `sections`.
+    fn marked_files(&self, sections: &[usize]) -> Vec<&str> {
+        let ranges = self.sections();
+        sections
+            .iter()
+            .flat_map(|&section| &self.file_diffs[ranges[section].clone()])
+            .filter(|f| f.marked)
+            .map(|f| f.path.as_str())
+            .collect()
+    }
 
-                std::thread::spawn(move || {
-                    for buf in bufs {
-                        stdin.write_all(buf).context("failed to patch hunk")?;
-                    }
-                    Ok::<_, Error>(())
-                })
-                .join()
-                .unwrap()
-                .context("failed to patch hunk")?;
+    /// Whether any files are marked, in which case staging, unstaging and discarding act on them
+    /// instead of on the item under the cursor.
+    pub fn any_marked(&self) -> bool {
+        self.file_diffs.iter().any(|f| f.marked)
+    }
 
-                let mut stderr_buf = String::new();
-                patch
-                    .stderr
-                    // If I understand correctly, reading to EOF should have the added effect
-                    // waiting on the child process to finish.
-                    .map(|mut stderr| stderr.read_to_string(&mut stderr_buf))
-                    .context("failed to read stderr of child process")??;
-                MiniBuffer::push(&stderr_buf, MessageType::Error);
-            }
+    /// Marks or unmarks the file under the cursor. Directories can't be marked.
+    pub fn toggle_mark(&mut self) {
+        if self.dir_cursor.is_some() {
+            return;
         }
+        if let Some(file) = self.file_diffs.get_mut(self.cursor) {
+            file.marked = !file.marked;
+        }
+    }
 
-        self.file_diffs
-            .get_mut(self.cursor)
-            .context("cursor is at invalid position")?
-            .selected = true;
-        Ok(())
+    pub fn clear_marks(&mut self) {
+        for file in &mut self.file_diffs {
+            file.marked = false;
+        }
     }
 
-    pub fn stage(&mut self) -> Result<()> {
-        self.stage_or_unstage(Stage::Add)
+    /// The paths of the files in `dir` in the given `section`.
+    fn dir_files(&self, section: usize, dir: &str) -> Vec<&str> {
+        let prefix = format!("{dir}/");
+        self.file_diffs[self.sections()[section].clone()]
+            .iter()
+            .map(|f| f.path.as_str())
+            .filter(|path| path.starts_with(&prefix))
+            .collect()
     }
 
-    pub fn unstage(&mut self) -> Result<()> {
-        self.stage_or_unstage(Stage::Reset)
+    /// Moves the cursor to `row`. If it's a file, the cursor goes to its last hunk if `last`, or
+    /// to its header otherwise.
+    fn select_row(&mut self, row: Row, last: bool) {
+        if let Some(file) = self.file_diffs.get_mut(self.cursor) {
+            file.selected = false;
+        }
+        (self.cursor, self.dir_cursor) = match row {
+            Row::Dir { file, depth } => (file, Some(depth)),
+            Row::File(file) => (file, None),
+        };
+        let file = &mut self.file_diffs[self.cursor];
+        file.selected = true;
+        if last && self.dir_cursor.is_none() && file.expanded() {
+            file.cursor_last();
+        } else {
+            file.cursor_first();
+        }
     }
 
-    /// Toggles expand on the selected diff item.
-    pub fn expand(&mut self) -> Result<()> {
-        if self.file_diffs.is_empty() {
-            return Ok(());
+    /// Moves the cursor out of any collapsed directory it ended up in, onto the directory.
+    fn reveal_cursor(&mut self) {
+        let rows = self.rows();
+        if rows.contains(&self.cursor_row()) {
+            return;
+        }
+        // Rows are in the order of their files, so the last one before the cursor's file is the
+        // collapsed directory that it's in.
+        if let Some(&row) = rows.iter().rev().find(|row| row.file() <= self.cursor) {
+            self.select_row(row, false);
+        } else if let Some(&row) = rows.first() {
+            self.select_row(row, false);
         }
+    }
 
-        let file = self
+    /// Toggles grouping files into a tree of the directories they're in.
+    pub fn toggle_tree(&mut self) {
+        self.tree = !self.tree;
+        self.dir_cursor = None;
+        self.reveal_cursor();
+    }
+
+    /// Lists only the files whose paths contain `filter`, ignoring case, or all of them if it's
+    /// empty.
+    pub fn set_filter(&mut self, filter: &str) {
+        if filter == self.filter {
+            return;
+        }
+        self.unfilter();
+        filter.clone_into(&mut self.filter);
+        self.apply_filter();
+        self.reveal_cursor();
+    }
+
+    /// Moves the files that don't match the filter out of `file_diffs`, so the sections only count
+    /// the rest. The cursor stays on its file if that's still listed, or otherwise goes to the next
+    /// one that is. Files left out are unmarked, so that acting on the marked files can't change
+    /// ones that aren't listed.
+    fn apply_filter(&mut self) {
+        if self.filter.is_empty() {
+            return;
+        }
+        let filter = self.filter.to_lowercase();
+        let sections = self.sections();
+        let mut counts = [0; 3];
+        let mut cursor = None;
+        let mut shown = Vec::new();
+        for (i, mut file) in std::mem::take(&mut self.file_diffs).into_iter().enumerate() {
+            // Every file is in one of the sections.
+            let section = sections
+                .iter()
+                .position(|s| s.contains(&i))
+                .unwrap_or_default();
+            if file.path.to_lowercase().contains(&filter) {
+                if cursor.is_none() && i >= self.cursor {
+                    cursor = Some(shown.len());
+                }
+                counts[section] += 1;
+                shown.push(file);
+            } else {
+                file.selected = false;
+                file.marked = false;
+                self.filtered_out.push((section, file));
+            }
+        }
+        [self.count_untracked, self.count_unstaged, self.count_staged] = counts;
+        self.file_diffs = shown;
+        self.dir_cursor = None;
+        self.cursor = cursor.unwrap_or_else(|| self.file_diffs.len().saturating_sub(1));
+        if let Some(file) = self.file_diffs.get_mut(self.cursor) {
+            if !file.selected {
+                file.selected = true;
+                file.cursor_first();
+            }
+        }
+    }
+
+    /// Puts the files left out by the filter back in their sections, keeping the cursor on its
+    /// file.
+    fn unfilter(&mut self) {
+        if self.filtered_out.is_empty() {
+            return;
+        }
+        let sections = self.sections();
+        let mut files: [Vec<FileDiff>; 3] = Default::default();
+        for (i, file) in std::mem::take(&mut self.file_diffs).into_iter().enumerate() {
+            let section = sections
+                .iter()
+                .position(|s| s.contains(&i))
+                .unwrap_or_default();
+            files[section].push(file);
+        }
+        for (section, file) in self.filtered_out.drain(..) {
+            files[section].push(file);
+        }
+        // Git lists the files in each section sorted by path.
+        for section in &mut files {
+            section.sort_by(|a, b| a.path.cmp(&b.path));
+        }
+        [self.count_untracked, self.count_unstaged, self.count_staged] =
+            files.each_ref().map(Vec::len);
+        self.file_diffs = files.into_iter().flatten().collect();
+        self.dir_cursor = None;
+        self.cursor = self
+            .file_diffs
+            .iter()
+            .position(|f| f.selected)
+            .unwrap_or_default();
+        if let Some(file) = self.file_diffs.get_mut(self.cursor) {
+            file.selected = true;
+        }
+    }
+
+    /// The items after the one under the cursor that are left to stage, or left to unstage if
+    /// `command` is `Stage::Reset`, for the cursor to move on to once it's done.
+    fn advance_from_cursor(&self, command: Stage) -> Advance {
+        let sections = match command {
+            Stage::Add => 0..2,
+            Stage::Reset => 2..3,
+        };
+        let start = self.sections()[sections.start].start;
+        let end = self.sections()[sections.end - 1].end;
+        // Everything in the directory under the cursor is staged along with it.
+        let dir = self.cursor_dir().map(|(_, dir)| format!("{dir}/"));
+        let staged = |i: usize, file: &FileDiff| {
+            dir.as_ref()
+                .map_or(i == self.cursor && file.cursor == 0, |dir| {
+                    file.path.starts_with(dir)
+                })
+        };
+        let mut items = Vec::new();
+        for (i, file) in self
+            .file_diffs
+            .iter()
+            .enumerate()
+            .take(end)
+            .skip(self.cursor)
+        {
+            if staged(i, file) {
+                continue;
+            }
+            if file.expanded() {
+                // Hunks after the one under the cursor in its file, or all of them in later files.
+                let first = if i == self.cursor { file.cursor } else { 0 };
+                items.extend(
+                    file.hunks
+                        .iter()
+                        .skip(first)
+                        .map(|h| (file.path.clone(), Some(h.body().to_string()))),
+                );
+            }
+            // The file whose hunk was staged is still listed, but there's nothing after the hunk
+            // to move on to in it unless it's one of its other hunks.
+            if i != self.cursor {
+                items.push((file.path.clone(), None));
+            }
+        }
+        // If nothing after it is left, the cursor goes back to the nearest item before it instead.
+        let before = start..(self.cursor + 1).clamp(start, end);
+        for (i, file) in self.file_diffs[before.clone()]
+            .iter()
+            .enumerate()
+            .map(|(i, file)| (before.start + i, file))
+            .rev()
+        {
+            if staged(i, file) {
+                continue;
+            }
+            if file.expanded() {
+                // Hunks before the one under the cursor in its file, or all of them in earlier
+                // files.
+                let last = if i == self.cursor {
+                    file.cursor.saturating_sub(1)
+                } else {
+                    file.hunks.len()
+                };
+                items.extend(
+                    file.hunks[..last]
+                        .iter()
+                        .rev()
+                        .map(|h| (file.path.clone(), Some(h.body().to_string()))),
+                );
+            }
+            if i != self.cursor {
+                items.push((file.path.clone(), None));
+            }
+        }
+        Advance { sections, items }
+    }
+
+    /// Moves the cursor to the first of the items of `advance` that's still listed in its
+    /// sections. If none are, it's left where it is.
+    fn advance_cursor(&mut self, advance: &Advance) {
+        let sections = self.sections();
+        let range = sections[advance.sections.start].start..sections[advance.sections.end - 1].end;
+        let found = advance.items.iter().find_map(|(path, body)| {
+            let i = range.start
+                + self.file_diffs[range.clone()]
+                    .iter()
+                    .position(|f| &f.path == path)?;
+            let cursor = match body {
+                Some(body) => {
+                    1 + self.file_diffs[i]
+                        .hunks
+                        .iter()
+                        .position(|h| h.body() == body)?
+                }
+                None => 0,
+            };
+            Some((i, cursor))
+        });
+        let Some((i, cursor)) = found else {
+            return;
+        };
+        if let Some(file) = self.file_diffs.get_mut(self.cursor) {
+            file.selected = false;
+        }
+        self.cursor = i;
+        self.dir_cursor = None;
+        let file = &mut self.file_diffs[i];
+        file.selected = true;
+        file.cursor = cursor;
+    }
+
+    /// Creates a `FileDiff` for `path`, keeping the expansion, cursor and mark of its entry in the
+    /// `section` of the current `file_diffs`, if it has one. Otherwise, it's expanded if the file
+    /// was expanded in another section, so it stays open when it's staged or unstaged as a whole.
+    fn new_file_diff(
+        &self,
+        path: &str,
+        kind: DiffType,
+        section: Range<usize>,
+        options: &Options,
+    ) -> FileDiff {
+        let previous_entry = self
+            .file_diffs
+            .get(section)
+            .and_then(|section| section.iter().find(|f| f.path == path));
+        FileDiff {
+            marked: previous_entry.is_some_and(|f| f.marked),
+            ..FileDiff::new(
+                path,
+                kind,
+                previous_entry
+                    .or_else(|| self.file_diffs.iter().find(|f| f.path == path))
+                    .map_or(options.auto_expand_files, |f| f.expanded),
+                previous_entry.map_or(0, |f| f.cursor),
+            )
+        }
+    }
+
+    /// Puts the cursor back on the file it was on, `file`, given by its path and the index of its
+    /// section, once the files have been fetched again, or on the directory `dir` it was on, given
+    /// by its section and path.
+    fn restore_cursor(&mut self, file: Option<(String, usize)>, dir: Option<(usize, String)>) {
+        // Keep the cursor on the same file if it's still in the same section, e.g. when files were
+        // added or removed before it. Otherwise it stays at the same index, which moves it on to
+        // the next file after staging one.
+        if let Some((path, section)) = file {
+            let section = self.sections()[section].clone();
+            if let Some(i) = self.file_diffs[section.clone()]
+                .iter()
+                .position(|f| f.path == path)
+            {
+                self.cursor = section.start + i;
+            }
+        }
+        // Likewise for a directory, which is found by the first file in it.
+        let dir_start = dir.and_then(|(section, dir)| {
+            let section = self.sections()[section].clone();
+            let prefix = format!("{dir}/");
+            self.file_diffs[section.clone()]
+                .iter()
+                .position(|f| f.path.starts_with(&prefix))
+                .map(|i| section.start + i)
+        });
+        match dir_start {
+            Some(i) => self.cursor = i,
+            None => self.dir_cursor = None,
+        }
+    }
+
+    /// Takes a vec `file_diffs` containing `FileDiff` elements that have only the name populated,
+    /// and populates their hunks based on the parsing of `diff`, and the `prev_file_diffs`.
+    ///
+    /// The files in `unchanged` aren't in `diff`, and are copied from the same file in
+    /// `prev_section` instead, the previous files of the same section. Those that can't be found
+    /// there are returned, to be diffed after all.
+    fn populate_diffs(
+        file_diffs: &mut [FileDiff],
+        prev_section: &[FileDiff],
+        prev_file_diffs: &[FileDiff],
+        diff: &str,
+        unchanged: &HashSet<String>,
+        submodule_logs: &HashMap<String, Vec<String>>,
+        options: &Options,
+    ) -> Result<Vec<String>> {
+        let diffs = parse::parse_diff(diff)?;
+        let mut missing = Vec::new();
+        for file in file_diffs {
+            if unchanged.contains(&file.path) {
+                if let Some(previous) = prev_section.iter().find(|f| f.path == file.path) {
+                    file.header.clone_from(&previous.header);
+                    file.binary = previous.binary;
+                    file.similarity = previous.similarity;
+                    if file.old_path.is_none() {
+                        file.old_path.clone_from(&previous.old_path);
+                    }
+                    file.stats = previous.stats;
+                    file.hunks.clone_from(&previous.hunks);
+                } else {
+                    missing.push(file.path.clone());
+                }
+                continue;
+            }
+            if let Some(diff) = diffs.get(file.path.as_str()) {
+                // Submodules have no content to show the diff of, only the commit they're at.
+                if let Some(ref mut submodule) = file.submodule {
+                    file.header.clone_from(&diff.header);
+                    if let Some((old, new)) = diff
+                        .hunks
+                        .iter()
+                        .find_map(|h| parse::parse_submodule_hunk(h))
+                    {
+                        submodule.old = old.map(str::to_string);
+                        submodule.new = new.map(str::to_string);
+                        if let (Some(old), Some(new)) = (old, new) {
+                            submodule.commits = submodule_logs
+                                .get(&format!("{old}...{new}"))
+                                .cloned()
+                                .unwrap_or_default();
+                        }
+                    }
+                    continue;
+                }
+
+                // Get all the diffs entries of this file from the previous iteration.
+                let previous_file_entries = prev_file_diffs.iter().filter(|f| f.path == file.path);
+                file.header.clone_from(&diff.header);
+                file.binary = diff.binary;
+                file.similarity = diff.similarity;
+                if file.old_path.is_none() {
+                    file.old_path.clone_from(&diff.old_path);
+                }
+                // Lines of conflicts start with two `+`, `-` or ` `s, so they can't be counted.
+                if !matches!(file.kind, DiffType::Conflicted) {
+                    file.stats = sum_changes(&diff.hunks);
+                }
+
+                // If the file's diff hasn't changed, reuse its hunks rather than building them
+                // again, which keeps their highlighting and expansion as they were.
+                if let Some(previous) = previous_file_entries.clone().find(|f| {
+                    f.header == diff.header && f.unsplit_hunks().into_iter().eq(&diff.hunks)
+                }) {
+                    file.hunks.clone_from(&previous.hunks);
+                    continue;
+                }
+
+                // Lines of conflicts start with two `+`, `-` or ` `s, which the highlighting
+                // doesn't expect.
+                let syntax = if options.syntax_highlighting
+                    && render::color_enabled()
+                    && !matches!(file.kind, DiffType::Conflicted)
+                {
+                    highlight::syntax_for_path(&file.path)
+                } else {
+                    None
+                };
+                file.hunks = diff
+                    .hunks
+                    .iter()
+                    .map(|hunk| {
+                        let header = hunk.lines().next().context("found an empty hunk")?;
+                        let (old, new) = (parse_hunk_old(header)?, parse_hunk_new(header)?);
+                        let expanded = previous_file_entries
+                            .clone()
+                            .find_map(|f| {
+                                f.hunks.iter().find(|h| {
+                                    h.diff.lines().next().is_some_and(|h_header| {
+                                        parse_hunk_new(h_header).is_ok_and(|h_new| h_new == new)
+                                            || parse_hunk_old(h_header)
+                                                .is_ok_and(|h_old| h_old == old)
+                                    })
+                                })
+                            })
+                            .map_or(options.auto_expand_hunks, |h| h.expanded);
+
+                        Ok(Hunk::new(hunk.clone(), expanded, syntax))
+                    })
+                    .collect::<Result<_>>()?;
+            }
+        }
+        Ok(missing)
+    }
+
+    fn stage_or_unstage(&mut self, command: Stage) -> Result<()> {
+        // The cursor only moves on if this stages or unstages something, once it has.
+        self.advance = None;
+        if self.file_diffs.is_empty() {
+            return Ok(());
+        }
+        self.save_index()?;
+
+        if self.any_marked() {
+            let (mut args, sections) = match command {
+                Stage::Add => (vec!["add", "--"], [0, 1].as_slice()),
+                Stage::Reset => (vec!["reset", "--"], [2].as_slice()),
+            };
+            let paths = self.marked_files(sections);
+            if paths.is_empty() {
+                MiniBuffer::push(
+                    match command {
+                        Stage::Add => "None of the marked files have changes to stage.",
+                        Stage::Reset => "None of the marked files have staged changes.",
+                    },
+                    MessageType::Error,
+                );
+                return Ok(());
+            }
+            args.extend(paths);
+            MiniBuffer::push_failure(&git_process(&args)?);
+            self.clear_marks();
+            return Ok(());
+        }
+
+        let advance = CONFIG
+            .get()
+            .is_some_and(|config| config.options.advance_after_stage)
+            .then(|| self.advance_from_cursor(command));
+
+        let apply_args = match command {
+            Stage::Add => self.apply_args(&["--cached"]),
+            Stage::Reset => self.apply_args(&["--cached", "--reverse"]),
+        };
+        if let Some((section, dir)) = self.cursor_dir() {
+            let mut args = match command {
+                Stage::Add => vec!["add", "--"],
+                Stage::Reset => vec!["reset", "--"],
+            };
+            args.extend(self.dir_files(section, &dir));
+            let output = git_process(&args)?;
+            MiniBuffer::push_failure(&output);
+            if output.status.success() {
+                self.advance = advance;
+            }
+            return Ok(());
+        }
+        let file = self
+            .file_diffs
+            .get_mut(self.cursor)
+            .context("cursor is at invalid position")?;
+        file.selected = false;
+
+        let output = match file.cursor {
+            0 => {
+                let args = match command {
+                    Stage::Add => vec!["add", &file.path],
+                    Stage::Reset => match file.kind {
+                        DiffType::Deleted => vec!["reset", "HEAD", &file.path],
+                        _ => vec!["reset", &file.path],
+                    },
+                };
+                Some(git_process(&args)?)
+            }
+            _ if matches!(file.kind, DiffType::Conflicted) => {
+                MiniBuffer::push(
+                    "Resolve the conflicts, then stage the whole file.",
+                    MessageType::Error,
+                );
+                None
+            }
+            i => {
+                let hunk = file
+                    .hunks
+                    .get(i - 1)
+                    .context("cursor is at invalid position")?;
+                Some(patch::apply(&file.header, &hunk.diff, &apply_args)?)
+            }
+        };
+        if let Some(output) = output {
+            MiniBuffer::push_failure(&output);
+            if output.status.success() {
+                self.advance = advance;
+            }
+        }
+
+        self.file_diffs
+            .get_mut(self.cursor)
+            .context("cursor is at invalid position")?
+            .selected = true;
+        Ok(())
+    }
+
+    /// Adds to `args` for `git apply` what's needed to apply hunks from the current diffs, which
+    /// without any context lines means `--unidiff-zero`, and when ignoring whitespace means
+    /// `--ignore-whitespace` as the context lines may differ in whitespace from the file.
+    fn apply_args<'a>(&self, args: &[&'a str]) -> Vec<&'a str> {
+        let mut args = args.to_vec();
+        if self.context_lines == 0 {
+            args.push("--unidiff-zero");
+        }
+        if self.ignore_whitespace {
+            args.push("--ignore-whitespace");
+        }
+        args
+    }
+
+    /// Remembers the index as it is, before staging or unstaging something, so that can be undone.
+    pub fn save_index(&mut self) -> Result<()> {
+        if let Some(snapshot) = IndexSnapshot::take()? {
+            self.undo.push(snapshot);
+        }
+        Ok(())
+    }
+
+    /// Puts the index back to how it was before the last stage or unstage. Snapshots of the index
+    /// matching how it is now are skipped, as undoing them wouldn't do anything.
+    pub fn undo(&mut self) -> Result<()> {
+        let current = IndexSnapshot::take()?;
+        while let Some(snapshot) = self.undo.pop() {
+            if current.as_ref().is_some_and(|c| c.tree == snapshot.tree) {
+                continue;
+            }
+            // The index was recorded relative to the old HEAD, so putting it back now would
+            // look like the changes since were being undone.
+            if snapshot.head != head_commit()? {
+                self.undo.clear();
+                MiniBuffer::push(
+                    "Can't undo, as HEAD has changed since then.",
+                    MessageType::Error,
+                );
+                return Ok(());
+            }
+            // Merging keeps the cached stat info of entries that are the same in the tree, which
+            // plain `read-tree` throws away, making git read every file again to see it's clean.
+            MiniBuffer::push_failure(&git_process(&["read-tree", "-m", &snapshot.tree])?);
+            return Ok(());
+        }
+        MiniBuffer::push("Nothing to undo.", MessageType::Error);
+        Ok(())
+    }
+
+    /// Toggles listing untracked files. Takes effect on the next fetch.
+    pub const fn toggle_untracked(&mut self) {
+        self.show_untracked = !self.show_untracked;
+    }
+
+    /// Toggles leaving whitespace changes out of diffs. Takes effect on the next fetch.
+    pub const fn toggle_ignore_whitespace(&mut self) {
+        self.ignore_whitespace = !self.ignore_whitespace;
+    }
+
+    /// Shows one more line of context around changes. Takes effect on the next fetch.
+    pub const fn more_context(&mut self) {
+        self.context_lines += 1;
+    }
+
+    /// Shows one less line of context around changes, down to none. Takes effect on the next
+    /// fetch.
+    pub const fn less_context(&mut self) {
+        self.context_lines = self.context_lines.saturating_sub(1);
+    }
+
+    pub fn stage(&mut self) -> Result<()> {
+        self.stage_or_unstage(Stage::Add)
+    }
+
+    pub fn unstage(&mut self) -> Result<()> {
+        self.stage_or_unstage(Stage::Reset)
+    }
+
+    /// Stages every change, including untracked files unless they're hidden.
+    pub fn stage_all(&mut self) -> Result<()> {
+        self.save_index()?;
+        let args = if self.show_untracked {
+            ["add", "--all"]
+        } else {
+            ["add", "--update"]
+        };
+        MiniBuffer::push_command_output(&git_process(&args)?);
+        Ok(())
+    }
+
+    /// Stashes the changes in the working tree and index, with `message` if given, and the
+    /// untracked files too if `include_untracked`.
+    pub fn stash_save(message: Option<&str>, include_untracked: bool) -> Result<()> {
+        let mut args = vec!["stash", "push"];
+        if include_untracked {
+            args.push("--include-untracked");
+        }
+        if let Some(message) = message {
+            args.extend(["--message", message]);
+        }
+        MiniBuffer::push_command_output(&git_process(&args)?);
+        Ok(())
+    }
+
+    /// Applies the latest stash and drops it.
+    pub fn stash_pop() -> Result<()> {
+        MiniBuffer::push_command_output(&git_process(&["stash", "pop"])?);
+        Ok(())
+    }
+
+    /// The directory of the file under the cursor, or in the tree view, the directory under the
+    /// cursor if it's on one. An untracked directory listed as a whole is its own directory.
+    /// Files at the top of the repository aren't in one.
+    fn directory_under_cursor(&self) -> Option<String> {
+        let file = self.file_diffs.get(self.cursor)?;
+        if self.dir_cursor.is_none() && file.path.ends_with('/') {
+            return Some(file.path.trim_end_matches('/').to_string());
+        }
+        let dirs = dir_components(&file.path);
+        let depth = self.dir_cursor.unwrap_or(dirs.len());
+        (depth > 0).then(|| dirs[..depth].join("/"))
+    }
+
+    /// Stages everything in the directory under the cursor, or unstages it if `Stage::Reset`.
+    fn stage_or_unstage_directory(&mut self, command: Stage) -> Result<()> {
+        let Some(dir) = self.directory_under_cursor() else {
+            if !self.file_diffs.is_empty() {
+                MiniBuffer::push(
+                    "The file under the cursor isn't in a directory.",
+                    MessageType::Error,
+                );
+            }
+            return Ok(());
+        };
+        self.save_index()?;
+        let dir = format!("{dir}/");
+        let args = match command {
+            Stage::Add if self.show_untracked => ["add", "--all", "--", &dir],
+            Stage::Add => ["add", "--update", "--", &dir],
+            Stage::Reset => ["reset", "--quiet", "--", &dir],
+        };
+        MiniBuffer::push_failure(&git_process(&args)?);
+        Ok(())
+    }
+
+    pub fn stage_directory(&mut self) -> Result<()> {
+        self.stage_or_unstage_directory(Stage::Add)
+    }
+
+    pub fn unstage_directory(&mut self) -> Result<()> {
+        self.stage_or_unstage_directory(Stage::Reset)
+    }
+
+    /// Unstages every change.
+    pub fn unstage_all(&mut self) -> Result<()> {
+        self.save_index()?;
+        MiniBuffer::push_command_output(&git_process(&["reset"])?);
+        Ok(())
+    }
+
+    /// The hunk under the cursor, if the cursor is on a hunk rather than a file.
+    fn selected_hunk(&mut self) -> Option<&mut Hunk> {
+        let file = self.file_diffs.get_mut(self.cursor)?;
+        let i = file.cursor.checked_sub(1)?;
+        file.hunks.get_mut(i)
+    }
+
+    /// Splits the hunk under the cursor into a hunk for each run of changes in it, so they can be
+    /// staged separately.
+    pub fn split_hunk(&mut self) -> Result<()> {
+        let Some(file) = self.file_diffs.get_mut(self.cursor) else {
+            return Ok(());
+        };
+        let Some(i) = file.cursor.checked_sub(1) else {
+            MiniBuffer::push("Only hunks can be split.", MessageType::Error);
+            return Ok(());
+        };
+        let hunk = &file.hunks[i];
+        let pieces = patch::split(&hunk.diff)?;
+        if pieces.len() < 2 {
+            MiniBuffer::push(
+                "The hunk has no context lines between its changes to split it at.",
+                MessageType::Error,
+            );
+            return Ok(());
+        }
+        let unsplit = hunk.unsplit.clone().unwrap_or_else(|| hunk.diff.clone());
+        let syntax = hunk.syntax;
+        let pieces: Vec<_> = pieces
+            .into_iter()
+            .map(|piece| Hunk {
+                unsplit: Some(unsplit.clone()),
+                ..Hunk::new(piece, true, syntax)
+            })
+            .collect();
+        file.hunks.splice(i..=i, pieces);
+        Ok(())
+    }
+
+    /// Resolves the conflicts in the file under the cursor by checking out one `side` of them,
+    /// `"--ours"` or `"--theirs"`. The file still has to be staged to mark it as resolved.
+    pub fn checkout_side(&self, side: &str) -> Result<()> {
+        let Some(file) = self.file_diffs.get(self.cursor) else {
+            return Ok(());
+        };
+        if self.dir_cursor.is_some() || !matches!(file.kind, DiffType::Conflicted) {
+            MiniBuffer::push(
+                "The file under the cursor has no conflicts.",
+                MessageType::Error,
+            );
+            return Ok(());
+        }
+        let output = git_process(&["checkout", side, "--", &file.path])?;
+        MiniBuffer::push_failure(&output);
+        Ok(())
+    }
+
+    /// A patch of the file under the cursor that can be applied with `git apply`, with only the
+    /// hunk under the cursor if it's on one. `None` for directories, and for files without a diff
+    /// to patch, like untracked and binary ones.
+    pub fn cursor_patch(&self) -> Option<String> {
+        if self.dir_cursor.is_some() {
+            return None;
+        }
+        let file = self.file_diffs.get(self.cursor)?;
+        if file.header.is_empty() || file.hunks.is_empty() {
+            return None;
+        }
+        let hunks = match file.cursor {
+            0 => file.unsplit_hunks(),
+            i => vec![file.hunks.get(i - 1)?.diff.as_str()],
+        };
+        Some(patch::build(&file.header, &hunks))
+    }
+
+    /// The diff of the hunk under the cursor, including its `@@` header.
+    pub fn cursor_hunk(&self) -> Option<&str> {
+        let file = self.file_diffs.get(self.cursor)?;
+        let i = file.cursor.checked_sub(1)?;
+        file.hunks.get(i).map(|hunk| hunk.diff.as_str())
+    }
+
+    /// Whether the cursor is on a directory rather than a file, either in the tree view or on an
+    /// untracked directory listed as a whole.
+    pub fn cursor_on_directory(&self) -> bool {
+        self.dir_cursor.is_some()
+            || self
+                .file_diffs
+                .get(self.cursor)
+                .is_some_and(|file| file.path.ends_with('/'))
+    }
+
+    /// The path of the file under the cursor, and if the cursor is on a hunk, the line the hunk
+    /// starts at in the new version of the file, or the chosen line while choosing lines. In the
+    /// tree view, this is the path of the directory if the cursor is on one.
+    pub fn cursor_location(&self) -> Option<(&str, Option<usize>)> {
+        let file = self.file_diffs.get(self.cursor)?;
+        if let Some(depth) = self.dir_cursor {
+            let len = dir_components(&file.path)[..depth]
+                .iter()
+                .map(|d| d.len() + 1)
+                .sum::<usize>();
+            return Some((&file.path[..len - 1], None));
+        }
+        let hunk = file.cursor.checked_sub(1).and_then(|i| file.hunks.get(i));
+        // While choosing lines, go to the chosen one, or the line after it if it was removed.
+        let chosen_line = hunk.and_then(|hunk| {
+            let selection = hunk.selection.as_ref()?;
+            let line_numbers = parse::hunk_line_numbers(&hunk.diff).ok()?;
+            line_numbers
+                .get(selection.cursor..)?
+                .iter()
+                .find_map(|&(_, new)| new)
+        });
+        let line = chosen_line.or_else(|| {
+            hunk.and_then(|hunk| hunk.diff.lines().next())
+                .and_then(|header| parse_hunk_new(header).ok())
+                .and_then(|range| parse::parse_hunk_range(range).ok())
+                .map(|(start, _)| start.max(1))
+        });
+        Some((&file.path, line))
+    }
+
+    /// The question to ask before discarding the changes under the cursor, naming exactly what
+    /// would be lost.
+    pub fn discard_prompt(&self) -> Option<String> {
+        if self.any_marked() {
+            let count = self.marked_files(&[0, 1]).len();
+            return (count > 0).then(|| {
+                format!(
+                    "Discard the changes to the {count} marked file{}? This can't be undone. (y/N) ",
+                    if count == 1 { "" } else { "s" }
+                )
+            });
+        }
+        let (path, line) = self.cursor_location()?;
+        let untracked = self.cursor < self.count_untracked;
+        let question = match (self.dir_cursor, line) {
+            (Some(_), _) if untracked => format!("Delete the untracked files in {path}/?"),
+            (Some(_), _) => format!("Discard the changes to the files in {path}/?"),
+            (None, Some(line)) => format!("Discard the hunk at line {line} of {path}?"),
+            (None, None) if untracked => format!("Delete {path}?"),
+            (None, None) => format!("Discard the changes to {path}?"),
+        };
+        Some(format!("{question} This can't be undone. (y/N) "))
+    }
+
+    /// Starts choosing individual lines of the hunk under the cursor. Returns false if the cursor
+    /// isn't on a hunk.
+    pub fn select_lines(&mut self) -> bool {
+        self.selected_hunk().map(Hunk::select_lines).is_some()
+    }
+
+    /// Stops choosing lines, forgetting any that were marked.
+    pub fn clear_line_selection(&mut self) {
+        if let Some(hunk) = self.selected_hunk() {
+            hunk.selection = None;
+        }
+    }
+
+    pub fn line_down(&mut self) {
+        if let Some(hunk) = self.selected_hunk() {
+            hunk.line_down();
+        }
+    }
+
+    pub fn line_up(&mut self) {
+        if let Some(hunk) = self.selected_hunk() {
+            hunk.line_up();
+        }
+    }
+
+    pub fn toggle_line(&mut self) {
+        if let Some(hunk) = self.selected_hunk() {
+            hunk.toggle_line();
+        }
+    }
+
+    /// Stages the chosen lines of the hunk under the cursor, or unstages them if `Stage::Reset`.
+    fn stage_or_unstage_lines(&mut self, command: Stage) -> Result<()> {
+        self.save_index()?;
+        let file = self
+            .file_diffs
+            .get(self.cursor)
+            .context("cursor is at invalid position")?;
+        let hunk = file
+            .cursor
+            .checked_sub(1)
+            .and_then(|i| file.hunks.get(i))
+            .context("cursor is not on a hunk")?;
+
+        let output = match command {
+            Stage::Add => patch::apply(
+                &file.header,
+                &hunk.selected_lines(false)?,
+                &self.apply_args(&["--cached"]),
+            ),
+            Stage::Reset => patch::apply(
+                &file.header,
+                &hunk.selected_lines(true)?,
+                &self.apply_args(&["--cached", "--reverse"]),
+            ),
+        }?;
+        MiniBuffer::push_failure(&output);
+        Ok(())
+    }
+
+    pub fn stage_lines(&mut self) -> Result<()> {
+        self.stage_or_unstage_lines(Stage::Add)
+    }
+
+    pub fn unstage_lines(&mut self) -> Result<()> {
+        self.stage_or_unstage_lines(Stage::Reset)
+    }
+
+    /// Throws away the unstaged changes under the cursor. On a file header, the whole file is
+    /// checked out, or deleted if it's untracked. On a hunk, just that hunk is reverted. On a
+    /// directory, every file in it is.
+    pub fn discard(&mut self) -> Result<()> {
+        if self.any_marked() {
+            if self.marked_files(&[0, 1]).is_empty() {
+                MiniBuffer::push(
+                    "None of the marked files have unstaged changes.",
+                    MessageType::Error,
+                );
+                return Ok(());
+            }
+            for (section, mut args) in [
+                (0, vec!["clean", "--force", "-d", "--"]),
+                (1, vec!["checkout", "--"]),
+            ] {
+                let paths = self.marked_files(&[section]);
+                if !paths.is_empty() {
+                    args.extend(paths);
+                    MiniBuffer::push_failure(&git_process(&args)?);
+                }
+            }
+            self.clear_marks();
+            return Ok(());
+        }
+        if let Some((section, dir)) = self.cursor_dir() {
+            let mut args = if section == 0 {
+                vec!["clean", "--force", "-d", "--"]
+            } else {
+                vec!["checkout", "--"]
+            };
+            args.extend(self.dir_files(section, &dir));
+            MiniBuffer::push_failure(&git_process(&args)?);
+            return Ok(());
+        }
+        let Some(file) = self.file_diffs.get(self.cursor) else {
+            return Ok(());
+        };
+
+        let output = match file.cursor {
+            0 if self.cursor < self.count_untracked => {
+                git_process(&["clean", "--force", "-d", "--", &file.path])?
+            }
+            0 => git_process(&["checkout", "--", &file.path])?,
+            i => {
+                let hunk = file
+                    .hunks
+                    .get(i - 1)
+                    .context("cursor is at invalid position")?;
+                patch::apply(&file.header, &hunk.diff, &self.apply_args(&["--reverse"]))?
+            }
+        };
+        MiniBuffer::push_failure(&output);
+        Ok(())
+    }
+
+    /// Moves the cursor to the next file or hunk containing `self.search`, or the previous one if
+    /// `backwards`, wrapping around at the ends. Collapsed files and hunks are expanded to show
+    /// the match, as are untracked files with a match in their preview.
+    pub fn search_next(&mut self, backwards: bool) {
+        if self.search.is_empty() {
+            return;
+        }
+        let options = &CONFIG.get().expect("config wasn't initialised").options;
+
+        // Positions of the matching items, as (index into `file_diffs`, `FileDiff` cursor).
+        let mut matches = Vec::new();
+        for (i, file) in self.file_diffs.iter().enumerate() {
+            if matches_search(&file.path, &self.search)
+                || file
+                    .preview_lines(options)
+                    .iter()
+                    .any(|line| matches_search(line, &self.search))
+            {
+                matches.push((i, 0));
+            }
+            for (j, hunk) in file.hunks.iter().enumerate() {
+                let hunk_matches = hunk.diff.lines().enumerate().any(|(n, line)| {
+                    // Skip the +/-/space at the start of each line after the header.
+                    let line = if n == 0 {
+                        line
+                    } else {
+                        line.get(1..).unwrap_or_default()
+                    };
+                    matches_search(line, &self.search)
+                });
+                if hunk_matches {
+                    matches.push((i, j + 1));
+                }
+            }
+        }
+
+        let current = (
+            self.cursor,
+            self.file_diffs.get(self.cursor).map_or(0, |f| f.cursor),
+        );
+        let next = if backwards {
+            matches
+                .iter()
+                .rev()
+                .find(|&&m| m < current)
+                .or_else(|| matches.last())
+        } else {
+            matches
+                .iter()
+                .find(|&&m| m > current)
+                .or_else(|| matches.first())
+        };
+        let Some(&(i, j)) = next else {
+            MiniBuffer::push(
+                &format!("No matches for {}", self.search),
+                MessageType::Error,
+            );
+            return;
+        };
+
+        if let Some(file) = self.file_diffs.get_mut(self.cursor) {
+            file.selected = false;
+        }
+        self.cursor = i;
+        self.dir_cursor = None;
+        if let Some(section) = self.sections().iter().position(|s| s.contains(&i)) {
+            let path = &self.file_diffs[i].path;
+            self.collapsed_dirs
+                .retain(|(s, dir)| *s != section || !path.starts_with(&format!("{dir}/")));
+        }
+        let file = &mut self.file_diffs[i];
+        file.selected = true;
+        if j > 0 {
+            file.expanded = true;
+            file.hunks[j - 1].expanded = true;
+        } else if !matches_search(&file.path, &self.search) {
+            // The match is in the preview of an untracked file.
+            file.expanded = true;
+        }
+        file.cursor = j;
+    }
+
+    /// Collapses every file and hunk, moving the cursor of each file back to its header.
+    pub fn collapse_all(&mut self) {
+        for file in &mut self.file_diffs {
+            file.expanded = false;
+            file.cursor = 0;
+            for hunk in &mut file.hunks {
+                hunk.expanded = false;
+            }
+        }
+    }
+
+    /// Expands every file and hunk.
+    pub fn expand_all(&mut self) {
+        for file in &mut self.file_diffs {
+            file.expanded = true;
+            for hunk in &mut file.hunks {
+                hunk.expanded = true;
+            }
+        }
+    }
+
+    /// Moves the cursor to what was clicked on: the `part`th hunk of the file in the `row`th row,
+    /// or the row itself if `part` is 0. Clicking on the first line of a file, directory or hunk
+    /// expands or collapses it too.
+    pub fn click(&mut self, (row, part): (usize, usize), first_line: bool) -> Result<()> {
+        let Some(&row) = self.rows().get(row) else {
+            return Ok(());
+        };
+        self.select_row(row, false);
+        if part > 0 {
+            let file = &mut self.file_diffs[self.cursor];
+            if part > file.hunks.len() {
+                return Ok(());
+            }
+            file.cursor = part;
+        }
+        if first_line {
+            self.expand()?;
+        }
+        Ok(())
+    }
+
+    /// Toggles expand on the selected diff item.
+    pub fn expand(&mut self) -> Result<()> {
+        if self.file_diffs.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(dir) = self.cursor_dir() {
+            if !self.collapsed_dirs.remove(&dir) {
+                self.collapsed_dirs.insert(dir);
+            }
+            return Ok(());
+        }
+
+        let file = self
             .file_diffs
             .get_mut(self.cursor)
             .context("cursor is at invalid position")?;
 
         if file.cursor == 0 {
-            file.expanded = !file.expanded;
+            file.toggle_expand();
         } else {
-            file.hunks[file.cursor - 1].expanded = !file.hunks[file.cursor - 1].expanded;
+            file.hunks[file.cursor - 1].toggle_expand();
         }
 
         Ok(())
     }
 
+    /// Moves the cursor to the next row, or the previous one if `backwards`. If that's a file, the
+    /// cursor goes to its last hunk if `last`, or to its header otherwise.
+    fn next_row(&mut self, backwards: bool, last: bool) {
+        let rows = self.rows();
+        let Some(i) = rows.iter().position(|&row| row == self.cursor_row()) else {
+            return;
+        };
+        let next = if backwards {
+            i.checked_sub(1)
+        } else {
+            Some(i + 1)
+        };
+        if let Some(&row) = next.and_then(|i| rows.get(i)) {
+            self.select_row(row, last);
+        }
+    }
+
     /// Jump to previous file.
     pub fn file_up(&mut self) -> Result<()> {
         if self.file_diffs.is_empty() {
@@ -701,14 +3416,7 @@ impl Status {
             .get_mut(self.cursor)
             .context("cursor is at invalid position")?;
         if file.cursor == 0 {
-            file.selected = false;
-            self.cursor = self.cursor.saturating_sub(1);
-            let new_file = self
-                .file_diffs
-                .get_mut(self.cursor)
-                .expect("self.cursor >= 0, !self.file_diffs.is_empty");
-            new_file.selected = true;
-            new_file.cursor = 0;
+            self.next_row(true, false);
         } else {
             file.cursor = 0;
         }
@@ -716,21 +3424,8 @@ impl Status {
     }
 
     /// Jump to next file.
-    pub fn file_down(&mut self) -> Result<()> {
-        if self.cursor < self.file_diffs.len() - 1 {
-            self.file_diffs
-                .get_mut(self.cursor)
-                .context("cursor is at invalid position")?
-                .selected = false;
-            self.cursor += 1;
-            let new_file = self
-                .file_diffs
-                .get_mut(self.cursor)
-                .expect("self.cursor < self.file_diffs.len");
-            new_file.selected = true;
-            new_file.cursor = 0;
-        }
-        Ok(())
+    pub fn file_down(&mut self) {
+        self.next_row(false, false);
     }
 
     /// Move the cursor up one
@@ -744,22 +3439,8 @@ impl Status {
             .get_mut(self.cursor)
             .context("cursor is at invalid position")?;
 
-        if file.up().is_err() {
-            match self.cursor.checked_sub(1) {
-                Some(v) => {
-                    self.cursor = v;
-                    file.selected = false;
-                    let new_file = self
-                        .file_diffs
-                        .get_mut(self.cursor)
-                        .context("cursor at invalid position")?;
-                    new_file.selected = true;
-                    if new_file.expanded() {
-                        new_file.cursor_last();
-                    }
-                }
-                None => self.cursor = 0,
-            }
+        if self.dir_cursor.is_some() || file.up().is_err() {
+            self.next_row(true, true);
         }
 
         Ok(())
@@ -771,69 +3452,649 @@ impl Status {
             return Ok(());
         }
 
-        let count_file_diffs = self.file_diffs.len();
         let file = self
             .file_diffs
             .get_mut(self.cursor)
             .context("cursor is at invalid position")?;
 
-        if file.down().is_err() {
-            if self.cursor + 1 >= count_file_diffs {
-                return Ok(());
-            }
-
-            self.cursor += 1;
-            file.selected = false;
-            let new_file = self
-                .file_diffs
-                .get_mut(self.cursor)
-                .context("cursor at invalid position")?;
-            new_file.selected = true;
-            if new_file.expanded() {
-                new_file.cursor_first();
-            }
+        if self.dir_cursor.is_some() || file.down().is_err() {
+            self.next_row(false, false);
         }
 
         Ok(())
     }
 
     /// Move the cursor to the first element.
-    pub fn cursor_first(&mut self) -> Result<()> {
-        if self.file_diffs.is_empty() {
-            return Ok(());
+    pub fn cursor_first(&mut self) {
+        if let Some(&row) = self.rows().first() {
+            self.select_row(row, false);
         }
-
-        self.file_diffs
-            .get_mut(self.cursor)
-            .context("cursor is at invalid position")?
-            .selected = false;
-        self.cursor = 0;
-        let new_file = self
-            .file_diffs
-            .get_mut(self.cursor)
-            .expect("0th element must exist, !self.file_diffs.is_empty()");
-        new_file.cursor_first();
-        new_file.selected = true;
-        Ok(())
     }
 
     /// Move the cursor to the last element.
-    pub fn cursor_last(&mut self) -> Result<()> {
-        if self.file_diffs.is_empty() {
-            return Ok(());
+    pub fn cursor_last(&mut self) {
+        if let Some(&row) = self.rows().last() {
+            self.select_row(row, true);
         }
+    }
+}
 
-        self.file_diffs
-            .get_mut(self.cursor)
-            .context("cursor is at invalid position")?
-            .selected = false;
-        self.cursor = self.file_diffs.len() - 1;
-        let new_file = self
-            .file_diffs
-            .get_mut(self.cursor)
-            .expect("cursor at `len() - 1`th pos of non-empty diffs");
-        new_file.cursor_last();
-        new_file.selected = true;
-        Ok(())
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+    use crate::config::Whitespace;
+
+    const DIFF: &str = "diff --git a/b.txt b/b.txt
+index 3f4b5a6..e69de29 100644
+--- a/b.txt
++++ b/b.txt
+@@ -1 +1 @@
+-old
++new
+";
+
+    fn cache(args: &[&str], keys: &[(&str, &str)]) -> DiffCache {
+        DiffCache {
+            args: args.iter().map(ToString::to_string).collect(),
+            keys: [
+                keys.iter()
+                    .map(|&(path, objects)| {
+                        let key = DiffKey {
+                            objects: objects.to_string(),
+                            stat: Some((3, None)),
+                        };
+                        (path.to_string(), key)
+                    })
+                    .collect(),
+                HashMap::new(),
+            ],
+        }
+    }
+
+    #[test_case(&["-U3"], &[("a.txt", "1 2")], &["a.txt"]; "same key")]
+    #[test_case(&["-U3"], &[("a.txt", "1 3")], &[]; "changed key")]
+    #[test_case(&["-U3"], &[("c.txt", "1 2")], &[]; "new file")]
+    #[test_case(&["-U5"], &[("a.txt", "1 2")], &[]; "different arguments")]
+    fn unchanged(args: &[&str], keys: &[(&str, &str)], expected: &[&str]) {
+        let previous = cache(&["-U3"], &[("a.txt", "1 2"), ("b.txt", "1 2")]);
+        let expected: HashSet<String> = expected.iter().map(ToString::to_string).collect();
+        assert_eq!(cache(args, keys).unchanged(&previous, 0), expected);
+    }
+
+    #[test]
+    fn reuses_unchanged_hunks() {
+        let options = Options {
+            syntax_highlighting: false,
+            ..Options::default()
+        };
+        let mut previous = FileDiff::new("a.txt", DiffType::Modified, true, 0);
+        previous.header = String::from("diff --git a/a.txt b/a.txt");
+        previous.stats = (1, 1);
+        previous.hunks = vec![Hunk::new(String::from("@@ -1 +1 @@\n-a\n+A\n"), true, None)];
+        let mut files = vec![
+            FileDiff::new("a.txt", DiffType::Modified, true, 0),
+            FileDiff::new("b.txt", DiffType::Modified, true, 0),
+        ];
+        let unchanged = HashSet::from([String::from("a.txt")]);
+        let previous = [previous];
+        let missing = Status::populate_diffs(
+            &mut files,
+            &previous,
+            &previous,
+            DIFF,
+            &unchanged,
+            &HashMap::new(),
+            &options,
+        )
+        .unwrap();
+        assert!(missing.is_empty());
+        assert_eq!(files[0].header, previous[0].header);
+        assert_eq!(files[0].stats, previous[0].stats);
+        assert_eq!(files[0].hunks[0].diff, previous[0].hunks[0].diff);
+        assert_eq!(files[1].hunks[0].diff, "@@ -1 +1 @@\n-old\n+new");
+    }
+
+    #[test]
+    fn json() {
+        let mut hunk_file = FileDiff::new("b.txt", DiffType::Modified, true, 3);
+        hunk_file.header = String::from("diff --git a/b.txt b/b.txt");
+        hunk_file.stats = (1, 1);
+        hunk_file.hunks = vec![Hunk::new(String::from("@@ -1 +1 @@\n-a\n+A"), true, None)];
+        let status = Status {
+            branch: Some(String::from("main")),
+            head: String::from("3f4b5a6 Add the thing"),
+            head_author: String::from("Tester"),
+            head_date: String::from("2 days ago"),
+            file_diffs: vec![
+                FileDiff::new("a.txt", DiffType::Untracked, false, 0),
+                hunk_file,
+            ],
+            count_untracked: 1,
+            count_staged: 1,
+            context_lines: 3,
+            show_untracked: true,
+            tree: true,
+            cursor: 1,
+            ..Status::default()
+        };
+        let file = |path: &str, section: &str, kind: &str| {
+            serde_json::json!({
+                "section": section,
+                "path": path,
+                "old_path": null,
+                "submodule": null,
+                "header": "",
+                "hunks": [],
+                "kind": kind,
+                "partial": false,
+                "binary": false,
+                "similarity": null,
+                "stats": [0, 0],
+            })
+        };
+        let mut staged = file("b.txt", "staged", "modified");
+        staged["header"] = "diff --git a/b.txt b/b.txt".into();
+        staged["hunks"] = serde_json::json!([{ "diff": "@@ -1 +1 @@\n-a\n+A" }]);
+        staged["stats"] = serde_json::json!([1, 1]);
+        assert_eq!(
+            serde_json::to_value(&status).unwrap(),
+            serde_json::json!({
+                "branch": "main",
+                "upstream": null,
+                "ahead": 0,
+                "behind": 0,
+                "head": { "hash": "3f4b5a6", "subject": "Add the thing" },
+                "head_author": "Tester",
+                "head_date": "2 days ago",
+                "stashes": 0,
+                "operation": null,
+                "worktree": null,
+                "file_diffs": [file("a.txt", "untracked", "untracked"), staged],
+                "count_untracked": 1,
+                "count_unstaged": 0,
+                "count_staged": 1,
+            })
+        );
+    }
+
+    /// A status with an unstaged file with two hunks and a staged file with one.
+    fn clickable() -> Status {
+        let mut unstaged = FileDiff::new("a.txt", DiffType::Modified, true, 0);
+        unstaged.hunks = vec![
+            Hunk::new(String::from("@@ -1 +1 @@\n-a\n+A"), true, None),
+            Hunk::new(String::from("@@ -9 +9 @@\n-b\n+B"), true, None),
+        ];
+        let mut staged = FileDiff::new("b.txt", DiffType::Modified, false, 0);
+        staged.hunks = vec![Hunk::new(String::from("@@ -1 +1 @@\n-c\n+C"), true, None)];
+        Status {
+            file_diffs: vec![unstaged, staged],
+            count_unstaged: 1,
+            count_staged: 1,
+            ..Status::default()
+        }
+    }
+
+    #[test_case((0, 2), false, 0, 2, [true, false] ; "hunk line")]
+    #[test_case((0, 1), true, 0, 1, [true, false] ; "hunk header")]
+    #[test_case((1, 0), false, 1, 0, [true, false] ; "file line")]
+    #[test_case((1, 0), true, 1, 0, [true, true] ; "file header")]
+    #[test_case((0, 3), false, 0, 0, [true, false] ; "past the last hunk")]
+    #[test_case((2, 0), true, 1, 1, [true, false] ; "past the last file")]
+    fn click(
+        target: (usize, usize),
+        first_line: bool,
+        cursor: usize,
+        file_cursor: usize,
+        expanded: [bool; 2],
+    ) {
+        let mut status = clickable();
+        status.cursor = 1;
+        status.file_diffs[1].cursor = 1;
+        status.click(target, first_line).unwrap();
+        assert_eq!(status.cursor, cursor);
+        assert_eq!(status.file_diffs[cursor].cursor, file_cursor);
+        assert_eq!(
+            status
+                .file_diffs
+                .iter()
+                .map(Expand::expanded)
+                .collect::<Vec<_>>(),
+            expanded
+        );
+    }
+
+    #[test]
+    fn click_hunk_header_collapses() {
+        let mut status = clickable();
+        status.click((0, 1), true).unwrap();
+        assert!(!status.file_diffs[0].hunks[0].expanded());
+    }
+
+    /// A status with an untracked file, two unstaged ones and a staged one, the cursor on the
+    /// second unstaged file and the first two files marked.
+    fn filterable() -> Status {
+        let file = |path, kind, marked| FileDiff {
+            marked,
+            ..FileDiff::new(path, kind, false, 0)
+        };
+        let mut status = Status {
+            file_diffs: vec![
+                file("notes.txt", DiffType::Untracked, true),
+                file("src/main.rs", DiffType::Modified, true),
+                file("src/status.rs", DiffType::Modified, false),
+                file("README.md", DiffType::Modified, false),
+            ],
+            count_untracked: 1,
+            count_unstaged: 2,
+            count_staged: 1,
+            cursor: 2,
+            ..Status::default()
+        };
+        status.file_diffs[2].selected = true;
+        status
+    }
+
+    fn paths(status: &Status) -> Vec<&str> {
+        status.file_diffs.iter().map(|f| f.path.as_str()).collect()
+    }
+
+    #[test_case("src", &["src/main.rs", "src/status.rs"], [0, 2, 0], 1 ; "keeps the cursor's file")]
+    #[test_case("STATUS", &["src/status.rs"], [0, 1, 0], 0 ; "ignores case")]
+    #[test_case("md", &["README.md"], [0, 0, 1], 0 ; "moves the cursor on")]
+    #[test_case("txt", &["notes.txt"], [1, 0, 0], 0 ; "moves the cursor back")]
+    #[test_case("nothing", &[], [0, 0, 0], 0 ; "no matches")]
+    fn set_filter(filter: &str, expected: &[&str], counts: [usize; 3], cursor: usize) {
+        let mut status = filterable();
+        status.set_filter(filter);
+        assert_eq!(paths(&status), expected);
+        assert_eq!(
+            [
+                status.count_untracked,
+                status.count_unstaged,
+                status.count_staged
+            ],
+            counts
+        );
+        assert_eq!(status.cursor, cursor);
+    }
+
+    #[test]
+    fn unfilter() {
+        let mut status = filterable();
+        status.set_filter("src");
+        status.set_filter("");
+        assert_eq!(
+            paths(&status),
+            ["notes.txt", "src/main.rs", "src/status.rs", "README.md"]
+        );
+        assert_eq!(
+            [
+                status.count_untracked,
+                status.count_unstaged,
+                status.count_staged
+            ],
+            [1, 2, 1]
+        );
+        assert_eq!(status.cursor, 2);
+    }
+
+    #[test]
+    fn filter_unmarks_hidden_files() {
+        let mut status = filterable();
+        status.set_filter("src");
+        assert_eq!(status.marked_files(&[0, 1]), ["src/main.rs"]);
+        status.set_filter("");
+        assert_eq!(status.marked_files(&[0, 1]), ["src/main.rs"]);
+    }
+
+    /// A status in the tree view with the unstaged `files` and a staged file, `src/d.rs`, the
+    /// cursor on the file at `cursor`.
+    fn tree(files: &[&str], cursor: usize) -> Status {
+        let mut file_diffs: Vec<_> = files
+            .iter()
+            .chain(&["src/d.rs"])
+            .map(|path| FileDiff::new(path, DiffType::Modified, false, 0))
+            .collect();
+        file_diffs[cursor].selected = true;
+        Status {
+            file_diffs,
+            count_unstaged: files.len(),
+            count_staged: 1,
+            cursor,
+            tree: true,
+            ..Status::default()
+        }
+    }
+
+    const FILES: [&str; 4] = ["README.md", "src/app/a.rs", "src/app/b.rs", "src/c.rs"];
+
+    #[test_case("src/app/main.rs", &["src", "app"] ; "nested")]
+    #[test_case("README.md", &[] ; "top level")]
+    #[test_case("target/debug/", &["target"] ; "untracked directory")]
+    fn dir_components(path: &str, expected: &[&str]) {
+        assert_eq!(super::dir_components(path), expected);
+    }
+
+    #[test_case(&[], false, &[Row::File(0), Row::File(1), Row::File(2), Row::File(3), Row::File(4)] ; "not a tree")]
+    #[test_case(&[], true, &[
+        Row::File(0),
+        Row::Dir { file: 1, depth: 1 },
+        Row::Dir { file: 1, depth: 2 },
+        Row::File(1),
+        Row::File(2),
+        Row::File(3),
+        Row::Dir { file: 4, depth: 1 },
+        Row::File(4),
+    ] ; "nested directories")]
+    #[test_case(&["src/app"], true, &[
+        Row::File(0),
+        Row::Dir { file: 1, depth: 1 },
+        Row::Dir { file: 1, depth: 2 },
+        Row::File(3),
+        Row::Dir { file: 4, depth: 1 },
+        Row::File(4),
+    ] ; "collapsed subdirectory")]
+    #[test_case(&["src"], true, &[
+        Row::File(0),
+        Row::Dir { file: 1, depth: 1 },
+        Row::Dir { file: 4, depth: 1 },
+        Row::File(4),
+    ] ; "collapsed directory, only in its section")]
+    fn rows(collapsed: &[&str], tree: bool, expected: &[Row]) {
+        let mut status = tree_status(collapsed);
+        status.tree = tree;
+        assert_eq!(status.rows(), expected);
+    }
+
+    /// [`tree`] of [`FILES`] with the cursor on `src/app/b.rs` and the unstaged directories
+    /// `collapsed`.
+    fn tree_status(collapsed: &[&str]) -> Status {
+        let mut status = tree(&FILES, 2);
+        status.collapsed_dirs = collapsed.iter().map(|dir| (1, dir.to_string())).collect();
+        status
+    }
+
+    #[test_case(&[], 2, None ; "shown")]
+    #[test_case(&["src/app"], 1, Some(2) ; "in a collapsed directory")]
+    #[test_case(&["src", "src/app"], 1, Some(1) ; "in nested collapsed directories")]
+    fn reveal_cursor(collapsed: &[&str], cursor: usize, dir_cursor: Option<usize>) {
+        let mut status = tree_status(collapsed);
+        status.reveal_cursor();
+        assert_eq!((status.cursor, status.dir_cursor), (cursor, dir_cursor));
+    }
+
+    #[test_case(&["README.md", "src/app/0.rs", "src/app/a.rs", "src/app/b.rs"], 3 ; "file added before")]
+    #[test_case(&["src/app/b.rs", "src/c.rs"], 0 ; "file removed before")]
+    #[test_case(&["README.md", "src/app/a.rs", "src/c.rs"], 2 ; "file gone")]
+    fn restore_cursor_on_file(files: &[&str], cursor: usize) {
+        let mut status = tree(&FILES, 2);
+        let file = Some((String::from("src/app/b.rs"), 1));
+        let refreshed = tree(files, 0);
+        status.file_diffs = refreshed.file_diffs;
+        status.count_unstaged = refreshed.count_unstaged;
+        status.restore_cursor(file, None);
+        assert_eq!(status.cursor, cursor);
+    }
+
+    #[test_case(&["src/app/b.rs", "src/c.rs"], 0, Some(2) ; "directory moved")]
+    #[test_case(&["README.md", "src/c.rs"], 1, None ; "directory gone")]
+    fn restore_cursor_on_dir(files: &[&str], cursor: usize, dir_cursor: Option<usize>) {
+        let mut status = tree(&FILES, 1);
+        status.dir_cursor = Some(2);
+        let dir = Some((1, String::from("src/app")));
+        let refreshed = tree(files, 0);
+        status.file_diffs = refreshed.file_diffs;
+        status.count_unstaged = refreshed.count_unstaged;
+        status.restore_cursor(None, dir);
+        assert_eq!((status.cursor, status.dir_cursor), (cursor, dir_cursor));
+    }
+
+    #[test_case("src/app/a.rs", None, Some("src/app") ; "file")]
+    #[test_case("README.md", None, None ; "top level file")]
+    #[test_case("src/app/a.rs", Some(1), Some("src") ; "directory in the tree")]
+    #[test_case("target/", None, Some("target") ; "untracked directory")]
+    #[test_case("src/target/", None, Some("src/target") ; "nested untracked directory")]
+    fn directory_under_cursor(path: &str, dir_cursor: Option<usize>, expected: Option<&str>) {
+        let mut status = tree(&[path], 0);
+        status.dir_cursor = dir_cursor;
+        assert_eq!(status.directory_under_cursor().as_deref(), expected);
+        assert_eq!(
+            status.cursor_on_directory(),
+            dir_cursor.is_some() || path.ends_with('/')
+        );
+    }
+
+    #[test_case("abc", &[('a', 1), ('b', 2), ('c', 3)] ; "plain")]
+    #[test_case("a\x1b[31mé", &[('a', 1), ('é', 8)] ; "escapes skipped")]
+    #[test_case("\tx", &[('\t', 1), ('x', 2)] ; "tab")]
+    fn shown_chars(s: &str, expected: &[(char, usize)]) {
+        assert_eq!(super::shown_chars(s), expected);
+    }
+
+    #[test_case("ab", 4, "ab" ; "no tabs")]
+    #[test_case("a\tb", 4, "a   b" ; "to the tab stop")]
+    #[test_case("abcd\tb", 4, "abcd    b" ; "on the tab stop")]
+    #[test_case("日\tb", 4, "日  b" ; "after a wide character")]
+    #[test_case("\x1b[31m\tb", 4, "\x1b[31m    b" ; "after an escape")]
+    fn expand_tabs(s: &str, tab_width: usize, expected: &str) {
+        assert_eq!(super::expand_tabs(s, tab_width), expected);
+    }
+
+    #[test_case("abc", 5, "abc  " ; "padded")]
+    #[test_case("abcde", 5, "abcde" ; "fits exactly")]
+    #[test_case("abcdef", 4, "abc…" ; "cut off")]
+    #[test_case("日本", 4, "日本" ; "wide characters fit")]
+    #[test_case("日本", 5, "日本 " ; "wide characters padded")]
+    #[test_case("日本語", 4, "日 …" ; "wide character cut off")]
+    #[test_case("日本語", 5, "日本…" ; "wide characters cut off")]
+    #[test_case("\x1b[31mabcdef\x1b[m", 4, "\x1b[31mabc…\x1b[m" ; "escapes kept")]
+    #[test_case("abc", 0, "" ; "no room")]
+    fn fit(s: &str, width: usize, expected: &str) {
+        assert_eq!(super::fit(s, width), expected);
+    }
+
+    #[test_case("abc", "abc" ; "no errors")]
+    #[test_case("abc  ", "abc{E}  {R}" ; "trailing whitespace")]
+    #[test_case("   ", "{E}   {R}" ; "only whitespace")]
+    #[test_case(" \tabc", "{E} \t{R}abc{R}" ; "space before tab")]
+    #[test_case("\tabc", "\tabc" ; "tab in indent allowed")]
+    #[test_case(" \tabc ", "{E} \t{R}abc{E} {R}" ; "both")]
+    fn format_whitespace_errors(s: &str, expected: &str) {
+        let mut config = Config::default();
+        config.options.whitespace = Whitespace {
+            blank_at_eol: true,
+            space_before_tab: true,
+            tab_in_indent: false,
+        };
+        let expected = expected
+            .replace("{E}", &SetBackgroundColor(config.colors.error).to_string())
+            .replace("{R}", &SetBackgroundColor(Color::Reset).to_string());
+        assert_eq!(super::format_whitespace_errors(s, &config), expected);
+    }
+
+    #[test_case("@@ -1 +1 @@", "", "@@ -1 +1 @@{R}" ; "no heading")]
+    #[test_case("@@ -1 +1 @@ fn main", "", "@@ -1 +1 @@{R} fn main" ; "heading")]
+    #[test_case("@@@ -1 -1 +1 @@@ fn main", "", "@@@ -1 -1 +1 @@@{R} fn main" ; "combined")]
+    #[test_case("@@ -1 +1 @@ fn main", "main", "@@ -1 +1 @@{R} fn {M}main{B}" ; "match in heading")]
+    #[test_case("@@ -1 +1 @@ fn main", "@@", "{M}@@{B} -1 +1 {M}@@{B}{R} fn main" ; "match in ranges")]
+    #[test_case("@@ -1 +1 @@ fn main", "@ f", "@@ -1 +1 @@{R} fn main" ; "match across both")]
+    fn format_hunk_head(head: &str, search: &str, expected: &str) {
+        let config = CONFIG.get_or_init(Config::default);
+        let expected = expected
+            .replace("{R}", &ResetAttributes.to_string())
+            .replace(
+                "{M}",
+                &SetBackgroundColor(config.colors.search_match).to_string(),
+            )
+            .replace("{B}", &SetBackgroundColor(Color::Reset).to_string());
+        assert_eq!(super::format_hunk_head(head, search, config), expected);
+    }
+
+    #[test]
+    fn search_untracked_preview() {
+        CONFIG.get_or_init(Config::default);
+        let mut status = Status {
+            file_diffs: vec![
+                FileDiff::new("a.txt", DiffType::Untracked, false, 0),
+                FileDiff::new("Cargo.toml", DiffType::Untracked, false, 0),
+            ],
+            count_untracked: 2,
+            search: String::from("[package]"),
+            ..Status::default()
+        };
+        status.file_diffs[0].selected = true;
+        status.search_next(false);
+        assert_eq!(status.cursor, 1);
+        assert!(status.file_diffs[1].expanded);
+    }
+
+    /// A file with a hunk for each of `bodies`, expanded if there are any.
+    fn file_with_hunks(path: &str, bodies: &[&str]) -> FileDiff {
+        let mut file = FileDiff::new(path, DiffType::Modified, !bodies.is_empty(), 0);
+        file.hunks = bodies
+            .iter()
+            .map(|body| Hunk::new(format!("@@ -1 +1 @@\n{body}"), true, None))
+            .collect();
+        file
+    }
+
+    /// A status of the `unstaged` and `staged` files, with the cursor on `cursor`, where the
+    /// file's cursor is `file_cursor`.
+    fn staging(
+        unstaged: Vec<FileDiff>,
+        staged: Vec<FileDiff>,
+        cursor: usize,
+        file_cursor: usize,
+    ) -> Status {
+        let mut status = Status {
+            count_unstaged: unstaged.len(),
+            count_staged: staged.len(),
+            file_diffs: unstaged.into_iter().chain(staged).collect(),
+            cursor,
+            ..Status::default()
+        };
+        status.file_diffs[cursor].selected = true;
+        status.file_diffs[cursor].cursor = file_cursor;
+        status
+    }
+
+    /// Stages what's under the cursor of `before`, giving `after`, and returns where the cursor
+    /// moves on to, as the path and cursor of its file.
+    fn advance(before: &Status, mut after: Status) -> (String, usize) {
+        let advance = before.advance_from_cursor(Stage::Add);
+        after.advance_cursor(&advance);
+        let file = &after.file_diffs[after.cursor];
+        (file.path.clone(), file.cursor)
+    }
+
+    #[test]
+    fn advance_to_next_file() {
+        let before = staging(
+            vec![file_with_hunks("a", &[]), file_with_hunks("b", &[])],
+            vec![],
+            0,
+            0,
+        );
+        let after = staging(
+            vec![file_with_hunks("b", &[])],
+            vec![file_with_hunks("a", &[])],
+            0,
+            0,
+        );
+        assert_eq!(advance(&before, after), (String::from("b"), 0));
+    }
+
+    #[test]
+    fn advance_to_next_hunk() {
+        let before = staging(
+            vec![file_with_hunks("a", &["-1", "-2", "-3"])],
+            vec![],
+            0,
+            2,
+        );
+        let after = staging(
+            vec![file_with_hunks("a", &["-1", "-3"])],
+            vec![file_with_hunks("a", &["-2"])],
+            0,
+            0,
+        );
+        assert_eq!(advance(&before, after), (String::from("a"), 2));
+    }
+
+    #[test]
+    fn advance_after_last_file_in_section() {
+        let before = staging(
+            vec![file_with_hunks("a", &[]), file_with_hunks("b", &[])],
+            vec![file_with_hunks("c", &[])],
+            1,
+            0,
+        );
+        // Without anything to move on to, the cursor would stay at the same index, in the staged
+        // files.
+        let after = staging(
+            vec![file_with_hunks("a", &[])],
+            vec![file_with_hunks("b", &[]), file_with_hunks("c", &[])],
+            1,
+            0,
+        );
+        assert_eq!(advance(&before, after), (String::from("a"), 0));
+    }
+
+    #[test]
+    fn advance_after_last_hunk_in_section() {
+        let before = staging(vec![file_with_hunks("a", &["-1", "-2"])], vec![], 0, 2);
+        let after = staging(
+            vec![file_with_hunks("a", &["-1"])],
+            vec![file_with_hunks("a", &["-2"])],
+            1,
+            0,
+        );
+        assert_eq!(advance(&before, after), (String::from("a"), 1));
+    }
+
+    #[test]
+    fn advance_after_everything_staged() {
+        let before = staging(vec![file_with_hunks("a", &[])], vec![], 0, 0);
+        let after = staging(vec![], vec![file_with_hunks("a", &[])], 0, 0);
+        assert_eq!(advance(&before, after), (String::from("a"), 0));
+    }
+
+    #[test]
+    fn advance_while_filtered() {
+        let files = || {
+            ["a.txt", "b1.txt", "b2.txt", "c.txt"]
+                .map(|path| file_with_hunks(path, &[]))
+                .into()
+        };
+        let mut before = staging(files(), vec![], 1, 0);
+        before.set_filter("b");
+        // Only b2.txt is listed after b1.txt, not a.txt or c.txt.
+        let mut after = staging(
+            files().into_iter().filter(|f| f.path != "b1.txt").collect(),
+            vec![file_with_hunks("b1.txt", &[])],
+            1,
+            0,
+        );
+        after.set_filter("b");
+        assert_eq!(advance(&before, after), (String::from("b2.txt"), 0));
+    }
+
+    #[test]
+    fn diffs_missing_unchanged_files() {
+        let mut files = vec![FileDiff::new("a.txt", DiffType::Modified, true, 0)];
+        let unchanged = HashSet::from([String::from("a.txt")]);
+        let missing = Status::populate_diffs(
+            &mut files,
+            &[],
+            &[],
+            "",
+            &unchanged,
+            &HashMap::new(),
+            &Options::default(),
+        )
+        .unwrap();
+        assert_eq!(missing, ["a.txt"]);
     }
 }
@@ -0,0 +1,85 @@
+use std::{cmp, fmt, process::Output};
+
+use anyhow::Result;
+
+use crate::{
+    config::CONFIG,
+    git_process,
+    render::{self, Renderer, ResetAttributes, SetCursorStyle, SetForegroundColor},
+};
+
+#[derive(Default)]
+pub struct TagList {
+    /// The tags, newest first.
+    pub tags: Vec<String>,
+    pub cursor: usize,
+}
+
+impl render::Render for TagList {
+    fn render(&self, f: &mut Renderer) -> fmt::Result {
+        use fmt::Write;
+        let config = CONFIG.get().expect("config wasn't initialised");
+
+        if self.tags.is_empty() {
+            write!(
+                f,
+                "{}No tags yet.{}\r\n\n",
+                SetForegroundColor(config.colors.heading),
+                SetForegroundColor(config.colors.foreground),
+            )?;
+            return match config.keys.tag.first() {
+                Some(key) => write!(
+                    f,
+                    "Press {color}{key} n{ResetAttributes} to tag HEAD, or \
+                     {color}{key}{ResetAttributes} in the log to tag a commit.",
+                    color = SetForegroundColor(config.colors.key),
+                ),
+                None => write!(
+                    f,
+                    "Bind a key to `tag` to tag HEAD, or a commit in the log."
+                ),
+            };
+        }
+
+        for (i, tag) in self.tags.iter().enumerate() {
+            if i == self.cursor {
+                f.insert_cursor();
+                writeln!(f, "\r{SetCursorStyle}{tag}{ResetAttributes}")?;
+            } else {
+                writeln!(f, "\r{tag}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl TagList {
+    pub fn fetch(&mut self) -> Result<()> {
+        let output = git_process(&["tag", "--sort=-creatordate"])?;
+
+        self.tags = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|l| l.to_string())
+            .collect::<Vec<_>>();
+        self.cursor = 0;
+
+        Ok(())
+    }
+
+    pub fn down(&mut self) {
+        self.cursor = cmp::min(self.cursor + 1, self.tags.len().saturating_sub(1));
+    }
+
+    pub const fn cursor_last(&mut self) {
+        self.cursor = self.tags.len().saturating_sub(1);
+    }
+
+    /// Checks out the tag under the cursor, if there is one, which detaches HEAD. The full ref is
+    /// given so that a branch or file with the same name isn't checked out instead.
+    pub fn checkout(&self) -> Result<Option<Output>> {
+        self.tags
+            .get(self.cursor)
+            .map(|tag| git_process(&["checkout", "--detach", &format!("refs/tags/{tag}")]))
+            .transpose()
+    }
+}
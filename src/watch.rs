@@ -0,0 +1,111 @@
+//! Watching the work tree and repository for changes made outside gex, so the status can be kept
+//! up to date.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use git2::Repository;
+use notify_debouncer_mini::{
+    new_debouncer,
+    notify::{RecommendedWatcher, RecursiveMode},
+    DebounceEventResult, Debouncer,
+};
+
+/// How long things must stay unchanged before the status is refreshed, so that e.g. checking out
+/// a branch or saving a bunch of files refreshes once rather than for each file.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Gets told by the OS about changes to the work tree and the git directory.
+pub struct Watcher {
+    /// Stops the watching when it's dropped.
+    _debouncer: Debouncer<RecommendedWatcher>,
+    /// Receives the paths that changed each time things have changed and then settled.
+    changes: Receiver<DebounceEventResult>,
+    /// The repository, for telling which files are ignored.
+    repo: Repository,
+    workdir: PathBuf,
+    git_dir: PathBuf,
+    /// The git directory holding the refs, which for a linked work tree is the main one's.
+    common_dir: PathBuf,
+}
+
+impl Watcher {
+    /// Starts watching `workdir`, the work tree of the repository with its git directory at
+    /// `git_dir`, which needn't be inside it.
+    pub fn new(git_dir: PathBuf, workdir: PathBuf) -> Result<Self> {
+        let repo = Repository::open(&git_dir).context("failed to open repository to watch")?;
+        repo.set_workdir(&workdir, false)
+            .context("failed to set work tree to watch")?;
+        // A linked work tree's git directory names the main one that it shares refs with.
+        let common_dir = fs::read_to_string(git_dir.join("commondir"))
+            .map_or_else(|_| git_dir.clone(), |dir| git_dir.join(dir.trim()));
+
+        let (sender, changes) = mpsc::channel();
+        let mut debouncer =
+            new_debouncer(DEBOUNCE, sender).context("failed to start watching for changes")?;
+        let watcher = debouncer.watcher();
+        let mut watch = |path: &Path, mode| {
+            watcher
+                .watch(path, mode)
+                .with_context(|| format!("failed to watch {}", path.display()))
+        };
+        watch(&workdir, RecursiveMode::Recursive)?;
+        // The git directory is usually in the work tree, and so already watched.
+        if !git_dir.starts_with(&workdir) {
+            watch(&git_dir, RecursiveMode::NonRecursive)?;
+        }
+        if !common_dir.starts_with(&workdir) && common_dir != git_dir {
+            watch(&common_dir, RecursiveMode::NonRecursive)?;
+        }
+        if !common_dir.starts_with(&workdir) {
+            watch(&common_dir.join("refs"), RecursiveMode::Recursive)?;
+        }
+
+        Ok(Self {
+            _debouncer: debouncer,
+            changes,
+            repo,
+            workdir,
+            git_dir,
+            common_dir,
+        })
+    }
+
+    /// Whether anything that could change the status has changed since this was last called.
+    pub fn changed(&self) -> bool {
+        // Every change is taken, so that they aren't left to be found next time.
+        self.changes
+            .try_iter()
+            .flatten()
+            .flatten()
+            .filter(|event| self.affects_status(&event.path))
+            .count()
+            > 0
+    }
+
+    /// Whether a change to `path` could change the status. In the git directory, that's the index,
+    /// HEAD and refs, which change with e.g. `git add`, `git commit`, `git checkout` and `git
+    /// stash`. In the work tree, it's any file that isn't ignored.
+    fn affects_status(&self, path: &Path) -> bool {
+        if let Ok(relative) = path.strip_prefix(&self.git_dir) {
+            if relative == Path::new("index") || relative == Path::new("HEAD") {
+                return true;
+            }
+        }
+        if let Ok(relative) = path.strip_prefix(&self.common_dir) {
+            return relative.starts_with("refs") || relative == Path::new("packed-refs");
+        }
+        if path.starts_with(&self.git_dir) {
+            return false;
+        }
+        path.strip_prefix(&self.workdir).is_ok_and(|relative| {
+            !relative.as_os_str().is_empty()
+                && !self.repo.is_path_ignored(relative).unwrap_or(false)
+        })
+    }
+}
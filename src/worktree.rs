@@ -0,0 +1,119 @@
+//! Module for the worktree list, which lists the work trees of the repository for switching
+//! between them.
+
+use std::{env, fmt, path::Path};
+
+use anyhow::Result;
+use crossterm::style::Attribute;
+
+use crate::{
+    config::CONFIG,
+    git_process,
+    render::{self, Renderer, ResetAttributes, SetAttribute, SetCursorStyle, SetForegroundColor},
+    worktree,
+};
+
+pub struct Worktree {
+    pub path: String,
+    /// The branch checked out in the work tree, or `None` if HEAD is detached.
+    branch: Option<String>,
+    /// Whether gex is operating in this work tree.
+    current: bool,
+}
+
+#[derive(Default)]
+pub struct WorktreeList {
+    /// The work trees, the main one first.
+    pub worktrees: Vec<Worktree>,
+    pub cursor: usize,
+}
+
+impl render::Render for WorktreeList {
+    fn render(&self, f: &mut Renderer) -> fmt::Result {
+        use fmt::Write;
+        let config = CONFIG.get().expect("config wasn't initialised");
+
+        if self.worktrees.len() < 2 {
+            return write!(
+                f,
+                "{}No other worktrees.{}\r\n\nAdd one with `git worktree add <path> <branch>`.",
+                SetForegroundColor(config.colors.heading),
+                SetForegroundColor(config.colors.foreground),
+            );
+        }
+
+        for (i, worktree) in self.worktrees.iter().enumerate() {
+            if i == self.cursor {
+                f.insert_cursor();
+            }
+            writeln!(
+                f,
+                "\r{}{}{}{ResetAttributes} {}{}{ResetAttributes}",
+                if worktree.current { "* " } else { "  " },
+                if i == self.cursor {
+                    SetCursorStyle.to_string()
+                } else {
+                    String::new()
+                },
+                worktree.path,
+                SetAttribute(Attribute::Dim),
+                worktree
+                    .branch
+                    .as_ref()
+                    .map_or_else(|| "(detached HEAD)".to_string(), |b| format!("[{b}]")),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl WorktreeList {
+    /// Lists the work trees, with the cursor on the one gex is operating in.
+    pub fn fetch(&mut self) -> Result<()> {
+        let output = git_process(&["worktree", "list", "--porcelain"])?;
+        let current = worktree().map_or_else(env::current_dir, Ok)?;
+
+        self.worktrees = String::from_utf8_lossy(&output.stdout)
+            .split("\n\n")
+            .filter_map(|entry| {
+                let mut path = None;
+                let mut branch = None;
+                for line in entry.lines() {
+                    if let Some(p) = line.strip_prefix("worktree ") {
+                        path = Some(p.to_string());
+                    } else if let Some(b) = line.strip_prefix("branch ") {
+                        branch = Some(b.trim_start_matches("refs/heads/").to_string());
+                    } else if line == "bare" {
+                        // A bare repository has no work tree to switch to.
+                        return None;
+                    }
+                }
+                let path = path?;
+                Some(Worktree {
+                    current: Path::new(&path)
+                        .canonicalize()
+                        .is_ok_and(|path| path == current),
+                    path,
+                    branch,
+                })
+            })
+            .collect();
+        self.cursor = self.worktrees.iter().position(|w| w.current).unwrap_or(0);
+        Ok(())
+    }
+
+    pub const fn down(&mut self) {
+        if self.cursor + 1 < self.worktrees.len() {
+            self.cursor += 1;
+        }
+    }
+
+    pub const fn up(&mut self) {
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    /// The work tree under the cursor.
+    pub fn selected(&self) -> Option<&Worktree> {
+        self.worktrees.get(self.cursor)
+    }
+}